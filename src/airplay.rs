@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use tokio::io::AsyncReadExt;
+use tokio::net::UdpSocket;
+use crate::cover_art::CoverArtSource;
+use crate::errors::{UnyoError, UnyoResult};
+use crate::media::{MediaSource, PlaybackData, PlaybackState, PollFuture, SourceDevice};
+
+// prgr is given in RTP frames
+const RTP_RATE: u64 = 44100;
+const REOPEN_DELAY: Duration = Duration::from_secs(5);
+// Limits for chunked UDP items, anyone on the LAN can send packets.
+// Pictures are the largest items, a few hundred KiB in ~1 KiB chunks
+const MAX_CHUNKS: usize = 4096;
+const MAX_ITEM_BYTES: usize = 4 * 1024 * 1024;
+// Items being reassembled at the same time
+const MAX_PENDING_ITEMS: usize = 8;
+
+/// One metadata item of shairport-sync (`core` = DAAP, `ssnc` = shairport-sync itself)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetadataItem {
+    pub kind: String,
+    pub code: String,
+    pub data: Vec<u8>,
+}
+
+fn hex_to_string(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn tag_content<'a>(item: &'a str, tag: &str) -> Option<&'a str> {
+    let start = item.find(&format!("<{tag}"))?;
+    let start = start + item[start..].find('>')? + 1;
+    let end = start + item[start..].find(&format!("</{tag}>"))?;
+    Some(item[start..end].trim())
+}
+
+/// Parses one `<item>...</item>` of the metadata pipe
+pub fn parse_pipe_item(item: &str) -> Option<MetadataItem> {
+    let kind = hex_to_string(tag_content(item, "type")?)?;
+    let code = hex_to_string(tag_content(item, "code")?)?;
+    let length: usize = tag_content(item, "length")?.parse().ok()?;
+    let data = if length == 0 { vec![] } else {
+        let encoded: String = tag_content(item, "data")?.split_whitespace().collect();
+        BASE64.decode(encoded).ok()?
+    };
+    Some(MetadataItem {kind, code, data})
+}
+
+/// Splits the pipe stream into items, keeps incomplete ones for the next chunk
+#[derive(Default)]
+pub struct PipeParser {
+    buffer: String,
+}
+
+impl PipeParser {
+    pub fn feed(&mut self, chunk: &str) -> Vec<MetadataItem> {
+        self.buffer.push_str(chunk);
+        let mut items = vec![];
+        while let Some(end) = self.buffer.find("</item>") {
+            let item: String = self.buffer.drain(..end + "</item>".len()).collect();
+            items.extend(parse_pipe_item(&item));
+        }
+        items
+    }
+}
+
+/// Reassembles UDP packets (`type` `code` data, large items come in `chnk` packets)
+#[derive(Default)]
+pub struct UdpParser {
+    // (type, code) -> chunks
+    chunks: HashMap<(String, String), Vec<Option<Vec<u8>>>>,
+}
+
+impl UdpParser {
+    pub fn feed(&mut self, packet: &[u8]) -> Option<MetadataItem> {
+        let kind = String::from_utf8(packet.get(0..4)?.to_vec()).ok()?;
+        let code = String::from_utf8(packet.get(4..8)?.to_vec()).ok()?;
+        if code != "chnk" {
+            return Some(MetadataItem {kind, code, data: packet[8..].to_vec()});
+        }
+
+        // ssnc chnk <index u32> <count u32> <type> <code> <data>
+        let index = u32::from_be_bytes(packet.get(8..12)?.try_into().ok()?) as usize;
+        let count = u32::from_be_bytes(packet.get(12..16)?.try_into().ok()?) as usize;
+        let kind = String::from_utf8(packet.get(16..20)?.to_vec()).ok()?;
+        let code = String::from_utf8(packet.get(20..24)?.to_vec()).ok()?;
+        if index >= count || count > MAX_CHUNKS {
+            return None;
+        }
+
+        let key = (kind, code);
+        if !self.chunks.contains_key(&key) && self.chunks.len() >= MAX_PENDING_ITEMS {
+            // Most likely leftovers of items that lost a chunk
+            self.chunks.clear();
+        }
+        let chunks = self.chunks.entry(key.clone()).or_insert_with(|| vec![None; count]);
+        if chunks.len() != count {
+            *chunks = vec![None; count];
+        }
+        chunks[index] = Some(packet[24..].to_vec());
+        if chunks.iter().flatten().map(Vec::len).sum::<usize>() > MAX_ITEM_BYTES {
+            self.chunks.remove(&key);
+            return None;
+        }
+
+        if chunks.iter().all(Option::is_some) {
+            let data = self.chunks.remove(&key)?.into_iter().flatten().flatten().collect();
+            return Some(MetadataItem {kind: key.0, code: key.1, data});
+        }
+        None
+    }
+}
+
+/// What the metadata said so far
+#[derive(Debug, Default)]
+pub struct AirPlayState {
+    active: bool,
+    paused: bool,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    sender: Option<String>,
+    // Position (ms) at `progress_at`, duration (ms)
+    progress: Option<(u32, Instant, u32)>,
+    picture: Option<String>,
+}
+
+fn save_picture(data: &[u8]) -> Option<String> {
+    let extension = if data.starts_with(b"\x89PNG") { "png" } else { "jpg" };
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+
+    let dir = std::env::temp_dir().join("unyo-cover-art");
+    let path = dir.join(format!("airplay-{:016x}.{extension}", hasher.finish()));
+    if !path.exists() {
+        std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, data)).ok()?;
+    }
+    Some(path.to_string_lossy().to_string())
+}
+
+impl AirPlayState {
+    fn position(&self) -> Option<(u32, u32)> {
+        let (position, at, duration) = self.progress?;
+        let position = if self.paused { position } else { position + at.elapsed().as_millis() as u32 };
+        Some((position.min(duration), duration))
+    }
+
+    pub fn apply(&mut self, item: &MetadataItem) {
+        let text = || String::from_utf8_lossy(&item.data).to_string();
+        match (item.kind.as_str(), item.code.as_str()) {
+            ("core", "minm") => self.title = Some(text()),
+            ("core", "asar") => self.artist = Some(text()),
+            ("core", "asal") => self.album = Some(text()),
+            ("ssnc", "snam") => self.sender = Some(text()),
+            ("ssnc", "pbeg") | ("ssnc", "prsm") => {
+                self.active = true;
+                self.paused = false;
+            }
+            ("ssnc", "pfls") => {
+                self.progress = self.position().map(|(position, duration)| (position, Instant::now(), duration));
+                self.paused = true;
+            }
+            ("ssnc", "pend") => *self = Self::default(),
+            ("ssnc", "mdst") => {
+                self.title = None;
+                self.artist = None;
+                self.album = None;
+            }
+            ("ssnc", "prgr") => {
+                // start/current/end
+                let frames: Vec<u64> = text().trim_matches('\0').split('/').filter_map(|f| f.parse().ok()).collect();
+                if let [start, current, end] = frames[..] {
+                    let to_ms = |frames: u64| (frames * 1000 / RTP_RATE) as u32;
+                    let position = to_ms((current as u32).wrapping_sub(start as u32) as u64);
+                    let duration = to_ms((end as u32).wrapping_sub(start as u32) as u64);
+                    self.progress = Some((position, Instant::now(), duration));
+                    self.active = true;
+                }
+            }
+            ("ssnc", "PICT") => self.picture = if item.data.is_empty() { None } else { save_picture(&item.data) },
+            _ => {}
+        }
+    }
+
+    pub fn playback_data(&self) -> Option<PlaybackData> {
+        if !self.active {
+            return None;
+        }
+
+        let state = if self.paused { PlaybackState::Paused } else { PlaybackState::Playing };
+        let (position, duration) = self.position().unwrap_or((0, 0));
+        let mut data = PlaybackData::from((
+            self.title.clone().unwrap_or("Unknown".to_string()),
+            self.artist.clone().unwrap_or("Unknown".to_string()),
+            state, position, duration
+        ));
+        data.album = self.album.clone();
+        data.cover_art = self.picture.clone().map(CoverArtSource::File);
+        data.device = Some(SourceDevice {name: self.sender.clone().unwrap_or("AirPlay".to_string()), battery: None});
+        Some(data)
+    }
+}
+
+/// shairport-sync metadata, either from the pipe or as UDP stream
+pub struct ShairportSource {
+    state: Arc<Mutex<AirPlayState>>,
+}
+
+impl ShairportSource {
+    /// Reads the pipe at `pipe`, or listens on `udp` (bind address) if given
+    pub fn new(pipe: String, udp: Option<String>) -> Self {
+        let state = Arc::new(Mutex::new(AirPlayState::default()));
+        let reader_state = state.clone();
+        tokio::spawn(async move {
+            loop {
+                let result = match &udp {
+                    Some(address) => Self::read_udp(address, &reader_state).await,
+                    None => Self::read_pipe(&pipe, &reader_state).await
+                };
+                if let Err(e) = result {
+                    eprintln!("shairport-sync metadata: {e:?}");
+                }
+                tokio::time::sleep(REOPEN_DELAY).await;
+            }
+        });
+        Self {state}
+    }
+
+    async fn read_pipe(pipe: &str, state: &Mutex<AirPlayState>) -> UnyoResult<()> {
+        let mut file = tokio::fs::File::open(pipe).await.map_err(|e| UnyoError::AirPlay(e.to_string()))?;
+        let mut parser = PipeParser::default();
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut pending = vec![];
+        loop {
+            let read = file.read(&mut buffer).await.map_err(|e| UnyoError::AirPlay(e.to_string()))?;
+            if read == 0 {
+                // Writer went away
+                return Ok(());
+            }
+            pending.extend_from_slice(&buffer[..read]);
+            // Only feed complete UTF-8, the rest comes with the next read
+            let valid = match std::str::from_utf8(&pending) {
+                Ok(text) => text.len(),
+                Err(e) => e.valid_up_to(),
+            };
+            let text = String::from_utf8_lossy(&pending[..valid]).to_string();
+            pending.drain(..valid);
+            for item in parser.feed(&text) {
+                state.lock().unwrap().apply(&item);
+            }
+        }
+    }
+
+    async fn read_udp(address: &str, state: &Mutex<AirPlayState>) -> UnyoResult<()> {
+        let socket = UdpSocket::bind(address).await.map_err(|e| UnyoError::AirPlay(e.to_string()))?;
+        let mut parser = UdpParser::default();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = socket.recv(&mut buffer).await.map_err(|e| UnyoError::AirPlay(e.to_string()))?;
+            if let Some(item) = parser.feed(&buffer[..read]) {
+                state.lock().unwrap().apply(&item);
+            }
+        }
+    }
+}
+
+impl MediaSource for ShairportSource {
+    fn name(&self) -> &'static str {
+        "airplay"
+    }
+
+    fn poll(&self) -> PollFuture<'_> {
+        let data = self.state.lock().unwrap().playback_data();
+        Box::pin(async move { data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIPE_CAPTURE: &str = include_str!("../tests/fixtures/shairport-pipe.xml");
+    // The same session over UDP, each packet with its length (u16, big-endian) in front
+    const UDP_CAPTURE: &[u8] = include_bytes!("../tests/fixtures/shairport-udp.bin");
+
+    fn udp_packets() -> Vec<&'static [u8]> {
+        let mut packets = vec![];
+        let mut rest = UDP_CAPTURE;
+        while let [high, low, tail @ ..] = rest {
+            let (packet, tail) = tail.split_at(u16::from_be_bytes([*high, *low]) as usize);
+            packets.push(packet);
+            rest = tail;
+        }
+        packets
+    }
+
+    fn chunk(index: u32, count: u32, data: &[u8]) -> Vec<u8> {
+        [b"ssncchnk".as_slice(), &index.to_be_bytes(), &count.to_be_bytes(), b"ssncPICT", data].concat()
+    }
+
+    fn assert_session(items: &[MetadataItem]) {
+        let mut state = AirPlayState::default();
+        for item in items {
+            state.apply(item);
+        }
+        let data = state.playback_data().unwrap();
+        assert_eq!(data.title, "Über den Wolken");
+        assert_eq!(data.artist, "Reinhard Mey");
+        assert_eq!(data.album.as_deref(), Some("Mein achtel Lorbeerblatt"));
+        assert_eq!(data.device.as_ref().unwrap().name, "Annas iPhone");
+        assert_eq!(data.playback_state, PlaybackState::Paused);
+        assert_eq!(data.duration, 240_000);
+        assert!((1000..1100).contains(&data.position()));
+        assert!(data.cover_art_path().is_some_and(|path| path.ends_with(".png")));
+    }
+
+    #[test]
+    fn pipe_item() {
+        let item = parse_pipe_item("<item><type>636f7265</type><code>6173616c</code><length>24</length>\n\
+            <data encoding=\"base64\">\nTWVpbiBhY2h0ZWwgTG9yYmVlcmJsYXR0</data></item>").unwrap();
+        assert_eq!((item.kind.as_str(), item.code.as_str()), ("core", "asal"));
+        assert_eq!(item.data, b"Mein achtel Lorbeerblatt");
+
+        let item = parse_pipe_item("<item><type>73736e63</type><code>70626567</code><length>0</length></item>").unwrap();
+        assert_eq!((item.code.as_str(), item.data.len()), ("pbeg", 0));
+        assert_eq!(parse_pipe_item("<item><type>zz</type><code>70626567</code><length>0</length></item>"), None);
+    }
+
+    #[test]
+    fn pipe_capture() {
+        let items = PipeParser::default().feed(PIPE_CAPTURE);
+        assert_eq!(items.len(), 13);
+        let picture = items.iter().find(|item| item.code == "PICT").unwrap();
+        assert!(picture.data.starts_with(b"\x89PNG"));
+        assert_session(&items);
+    }
+
+    #[test]
+    fn pipe_capture_in_pieces() {
+        // Reads end anywhere, also within an item
+        let mut parser = PipeParser::default();
+        let mut items = vec![];
+        let mut rest = PIPE_CAPTURE;
+        while !rest.is_empty() {
+            let mut end = rest.len().min(37);
+            while !rest.is_char_boundary(end) {
+                end += 1;
+            }
+            items.extend(parser.feed(&rest[..end]));
+            rest = &rest[end..];
+        }
+        assert_eq!(items, PipeParser::default().feed(PIPE_CAPTURE));
+    }
+
+    #[test]
+    fn udp_capture() {
+        let mut parser = UdpParser::default();
+        let items: Vec<MetadataItem> = udp_packets().into_iter().filter_map(|packet| parser.feed(packet)).collect();
+        assert_eq!(items, PipeParser::default().feed(PIPE_CAPTURE));
+        assert_session(&items);
+    }
+
+    #[test]
+    fn udp_chunks_in_any_order() {
+        let mut parser = UdpParser::default();
+        assert_eq!(parser.feed(&chunk(1, 2, b"ld")), None);
+        let item = parser.feed(&chunk(0, 2, b"Bi")).unwrap();
+        assert_eq!(item.data, b"Bild");
+    }
+
+    #[test]
+    fn udp_limits() {
+        let mut parser = UdpParser::default();
+        // Would allocate gigabytes for the chunk list
+        assert_eq!(parser.feed(&chunk(0, u32::MAX, b"x")), None);
+        assert_eq!(parser.feed(&chunk(7, 3, b"x")), None);
+        assert!(parser.chunks.is_empty());
+
+        // Never complete, dropped once too large
+        let data = vec![0; 64 * 1024];
+        for index in 0..(MAX_ITEM_BYTES / data.len()) as u32 + 1 {
+            assert_eq!(parser.feed(&chunk(index, MAX_CHUNKS as u32, &data)), None);
+        }
+        assert!(parser.chunks.is_empty());
+    }
+}
@@ -1,31 +1,23 @@
-use std::sync::{LazyLock, Mutex, MutexGuard};
-use lazy_static::lazy_static;
 use geolocation::Locator;
 use isahc::ReadResponseExt;
 use serde_json::Value;
 use crate::errors::{UnyoError, UnyoResult};
+use crate::recording::{record, Event};
+use crate::state::Store;
 use crate::weather_widget::{time_with_hour_offset};
 
-static _WEATHER_INFO: LazyLock<Mutex<Option<WeatherInfo>>> = LazyLock::new(|| {Mutex::from(None)});
-
 #[allow(non_snake_case)]
-pub fn WEATHER_INFO<'a>() -> MutexGuard<'a, Option<WeatherInfo>> {
-    _WEATHER_INFO.lock().unwrap()
+pub fn UPDATE_WEATHER_INFO(store: &Store, location: &Locator) {
+    set_weather_info(store, Some(WeatherInfo::from_json(make_api_request(location), location)));
 }
 
-#[allow(non_snake_case)]
-pub fn UPDATE_WEATHER_INFO() {
-    let info = WeatherInfo::from_json(make_api_request());
-    *_WEATHER_INFO.lock().unwrap() = Some(info);
+pub fn set_weather_info(store: &Store, info: Option<WeatherInfo>) {
+    record(|| Event::Weather(info.clone().map(Box::new)));
+    store.weather.set(info);
 }
 
-lazy_static! {
-    pub static ref LOCATION: Locator = {
-        get_location().expect("Failed to get geo location")
-    };
-}
-
-fn get_location() -> Option<Locator> {
+/// Where the weather is shown for, by the address of wlan0
+pub fn get_location() -> Option<Locator> {
     for a in get_if_addrs::get_if_addrs().unwrap() {
         if a.ip().is_ipv6() && a.name == "wlan0" {
             return Some(geolocation::find(a.ip().to_string().as_str()).expect("Failed to get GEOLOC"));
@@ -40,27 +32,31 @@ fn api_req(uri: String) -> UnyoResult<Value> {
     serde_json::from_str(&local_data).map_err(|e| {UnyoError::ApiReqFmt(e.to_string(), uri)})
 }
 
-pub fn make_api_request() -> Value {
-    let (lat, long) = (&LOCATION.latitude, &LOCATION.longitude);
+pub fn make_api_request(location: &Locator) -> Value {
+    let (lat, long) = (&location.latitude, &location.longitude);
     let uri = format!("https://api.open-meteo.com/v1/forecast?latitude={lat}&longitude={long}&daily=sunshine_duration,temperature_2m_max,temperature_2m_min,uv_index_max,temperature_2m_mean,rain_sum&hourly=temperature_2m,cloud_cover,rain&current=temperature_2m,rain,cloud_cover,is_day&timezone=auto&forecast_hours=24");
     api_req(uri).unwrap()
 }
 
+// Days of the daily forecast open-meteo returns
+pub const FORECAST_DAYS: usize = 7;
+
+#[derive(Clone, PartialEq)]
 pub struct WeatherInfo {
     pub city: String,
     pub is_day: bool,
     // Temp, Rain, Cloud-coverage (percentage)
     pub current: (f64, f64, i64),
     // Temp (mean), UV, Rain (sum), Sunshine-duration (percentage)
-    pub daily: [(f64, f64, f64, f64); 7],
+    pub daily: [(f64, f64, f64, f64); FORECAST_DAYS],
     // Temp, Rain, Cloud-coverage (percentage)
     pub hourly: [(f64, f64, i64, String); 24]
 }
 
 impl WeatherInfo {
-    pub fn from_json(value: Value) -> Self {
+    pub fn from_json(value: Value, location: &Locator) -> Self {
         let head = value.as_object().unwrap();
-        let mut city = LOCATION.city.clone();
+        let mut city = location.city.clone();
         city.remove(0);
         city.remove(city.len()-1);
         let is_day;
@@ -89,7 +85,7 @@ impl WeatherInfo {
             ))
         };
 
-        let daily: [(f64, f64, f64, f64); 7] = {
+        let daily: [(f64, f64, f64, f64); FORECAST_DAYS] = {
             let daily = head.get("daily").unwrap().as_object().unwrap();
             
             let rain_sum = daily.get("rain_sum").unwrap().as_array().unwrap();
@@ -1,87 +1,227 @@
 use zbus::{Connection, Proxy};
 use std::collections::HashMap;
-use std::sync::{LazyLock, Mutex, MutexGuard, OnceLock};
-use zvariant::{Dict};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use zvariant::{Dict, OwnedObjectPath};
+use crate::config::CONFIG;
+use crate::cover_art::{CoverArtRef, CoverArtSource};
+use crate::errors::UnyoError;
+use crate::recording::{record, Event};
+use crate::state::Store;
+use crate::media::{ControlFuture, MediaCommand, MediaSource, PlaybackData, PlaybackState, PollFuture, SourceDevice};
 
+type Interface = HashMap<String, zvariant::OwnedValue>;
+type ManagedObjects = HashMap<OwnedObjectPath, HashMap<String, Interface>>;
 
-pub static _BLUETOOTH_CTL: OnceLock<BluetoothController> = OnceLock::new();
-pub static _BLUETOOTH_DATA: LazyLock<Mutex<Option<PlaybackData>>> =
-    LazyLock::new(|| {Mutex::new(None)});
 
-#[allow(non_snake_case)]
-pub async fn UPDATE_BLUETOOTH_DATA() {
-    let data = _BLUETOOTH_CTL.get().unwrap().poll().await;
-    *_BLUETOOTH_DATA.lock().unwrap() = data;
+const LAST_DEVICE_FILE: &str = "last_device";
+
+// AVRCP absolute volume range
+pub const VOLUME_MAX: u16 = 127;
+pub const VOLUME_STEP: u16 = 8;
+
+/// Changes the volume of the active transport by `delta` steps, on the device too if
+/// there is a `controller` (there is none when simulating)
+pub fn change_volume(store: &Store, controller: Option<&Arc<BluetoothController<'static>>>, delta: i32) {
+    let mut target = None;
+    let changed = store.transport.update(|transport| {
+        let Some(transport) = transport.as_mut() else { return };
+        let Some(volume) = transport.volume else { return };
+
+        let volume = (volume as i32 + delta * VOLUME_STEP as i32).clamp(0, VOLUME_MAX as i32) as u16;
+        transport.volume = Some(volume);
+        transport.volume_changed = Some(Instant::now());
+        target = Some((transport.path.clone(), volume));
+    });
+    if changed {
+        record(|| Event::Transport((*store.transport.get()).clone()));
+    }
+    let (Some((path, volume)), Some(controller)) = (target, controller.cloned()) else { return };
+    tokio::spawn(async move {
+        let result = async {
+            let proxy = Proxy::new(controller.connection(), "org.bluez", &path, "org.bluez.MediaTransport1").await?;
+            proxy.set_property("Volume", volume).await.map_err(zbus::Error::from)
+        };
+        if let Err(e) = result.await {
+            eprintln!("Failed to set volume: {e}");
+        }
+    });
 }
 
-#[allow(non_snake_case)]
-pub fn BLUETOOTH_DATA<'a>() -> MutexGuard<'a, Option<PlaybackData>> {
-    _BLUETOOTH_DATA.lock().unwrap()
+pub fn set_devices(store: &Store, devices: Vec<BluetoothDevice>) {
+    if store.devices.set_if_changed(devices) {
+        record(|| Event::Devices((*store.devices.get()).clone()));
+    }
 }
 
-#[derive(Debug)]
-pub struct BluetoothController<'a> {
-    proxy: Proxy<'a>
+/// Shows `transport`, a volume that differs from the shown one counts as a change. Compared
+/// in one go with the shown one, so a volume change in between isn't overwritten unseen
+pub fn set_transport(store: &Store, mut transport: Option<MediaTransport>) {
+    let changed = store.transport.update(|shown| {
+        if let (Some(transport), Some(previous)) = (&mut transport, &*shown) {
+            transport.carry_volume_change(previous);
+        }
+        *shown = transport;
+    });
+    if changed {
+        record(|| Event::Transport((*store.transport.get()).clone()));
+    }
+}
+
+fn save_last_device(address: &str) {
+    let state_dir = &CONFIG().state_dir;
+    if let Err(e) = std::fs::create_dir_all(state_dir).and_then(|_| std::fs::write(state_dir.join(LAST_DEVICE_FILE), address)) {
+        eprintln!("Failed to save last device: {e}");
+    }
+}
+
+fn load_last_device() -> Option<String> {
+    std::fs::read_to_string(CONFIG().state_dir.join(LAST_DEVICE_FILE)).ok()
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceAction {
+    Connect,
+    Disconnect,
+    // Toggles the trusted flag
+    Trust,
+    Remove,
+}
+
+/// Runs the action in the background, the device list picks up the result on the next poll
+pub fn device_action(controller: &Arc<BluetoothController<'static>>, device: &BluetoothDevice, action: DeviceAction) {
+    let (controller, device) = (controller.clone(), device.clone());
+    tokio::spawn(async move {
+        if let Err(e) = controller.run_device_action(&device, action).await {
+            eprintln!("{action:?} on {} failed: {e}", device.address);
+        }
+    });
 }
 
-#[derive(Debug)]
-pub enum PlaybackState {
-    Playing,
-    Paused,
-    Stopped,
+pub struct BluetoothController<'a> {
+    proxy: Proxy<'a>,
+    // Address of the device shown last, to remember it for reconnecting
+    last_device: Mutex<Option<String>>,
+    // Player shown last, target of transport controls
+    player: Mutex<Option<OwnedObjectPath>>,
+    // Gets the devices and the transport
+    store: Arc<Store>,
 }
 
-impl From<String> for PlaybackState {
-    fn from(value: String) -> Self {
-        if value == "playing" { PlaybackState::Playing }
-        else if value == "paused" { PlaybackState::Paused }
-        else { PlaybackState::Stopped }
+/// BlueZ (AVRCP) as a media source
+pub struct BluezSource(pub Arc<BluetoothController<'static>>);
+
+impl MediaSource for BluezSource {
+    fn name(&self) -> &'static str {
+        "bluetooth"
+    }
+
+    fn poll(&self) -> PollFuture<'_> {
+        Box::pin(self.0.poll())
     }
+
+    fn control(&self, command: MediaCommand) -> ControlFuture<'_> {
+        Box::pin(async move {
+            self.0.control(command).await.map_err(|e| UnyoError::DBus(e.to_string()))
+        })
+    }
+}
+
+/// A (paired) device as known to BlueZ
+#[derive(Debug, Clone, PartialEq)]
+pub struct BluetoothDevice {
+    pub path: OwnedObjectPath,
+    pub address: String,
+    pub alias: String,
+    pub connected: bool,
+    pub paired: bool,
+    pub trusted: bool,
+    // Percentage, only if the device reports it
+    pub battery: Option<u8>,
 }
 
-#[inline]
-pub fn limit_string_size(input: String, max_length: usize) -> String {
-    if input.len() > max_length {
-        let truncated = input.chars().take(max_length - 3).collect::<String>();
-        format!("{}...", truncated)
-    } else {
-        input.to_string()
+impl BluetoothDevice {
+    fn from_interfaces(path: OwnedObjectPath, interfaces: &HashMap<String, Interface>) -> Option<Self> {
+        let device_iface = interfaces.get("org.bluez.Device1")?;
+        let address = device_iface.get("Address")?.downcast_ref::<String>().ok()?;
+        let alias = device_iface.get("Alias")
+            .and_then(|v| v.downcast_ref::<String>().ok())
+            .unwrap_or(address.clone());
+        let flag = |name: &str| device_iface.get(name)
+            .and_then(|v| v.downcast_ref::<bool>().ok())
+            .unwrap_or(false);
+        let (connected, paired, trusted) = (flag("Connected"), flag("Paired"), flag("Trusted"));
+        let battery = interfaces.get("org.bluez.Battery1")
+            .and_then(|battery_iface| battery_iface.get("Percentage"))
+            .and_then(|v| v.downcast_ref::<u8>().ok());
+
+        Some(Self {path, address, alias, connected, paired, trusted, battery})
+    }
+
+    /// Index in the configured priority list (lower is preferred)
+    pub fn priority(&self) -> usize {
+        CONFIG().bluetooth.priority.iter()
+            .position(|p| p.eq_ignore_ascii_case(&self.address) || *p == self.alias)
+            .unwrap_or(usize::MAX)
     }
 }
 
+impl From<&BluetoothDevice> for SourceDevice {
+    fn from(device: &BluetoothDevice) -> Self {
+        Self {name: device.alias.clone(), battery: device.battery}
+    }
+}
 
-#[derive(Debug)]
-pub struct PlaybackData {
-    pub title: String,
-    pub artist: String,
-    #[allow(dead_code)]
-    playback_state: PlaybackState,
-    pub position: u32,
-    pub duration: u32
+/// An A2DP stream (org.bluez.MediaTransport1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaTransport {
+    pub path: OwnedObjectPath,
+    pub device: Option<OwnedObjectPath>,
+    pub codec: u8,
+    // idle, pending, broadcasting or active
+    pub state: String,
+    // 0 - 127, not every device supports absolute volume
+    pub volume: Option<u16>,
+    // When the volume last changed (local or from the device)
+    pub volume_changed: Option<Instant>,
 }
 
-impl PlaybackData {
-    pub fn new(title: String,
-               artist: String,
-               playback_state: PlaybackState,
-               position: u32,
-               duration: u32) -> Self {
-        Self { title, artist, playback_state, position, duration }
+impl MediaTransport {
+    fn from_interface(path: OwnedObjectPath, transport_iface: &Interface) -> Self {
+        let device = transport_iface.get("Device")
+            .and_then(|v| v.downcast_ref::<zvariant::ObjectPath>().ok())
+            .map(OwnedObjectPath::from);
+        let codec = transport_iface.get("Codec").and_then(|v| v.downcast_ref::<u8>().ok()).unwrap_or(0);
+        let state = transport_iface.get("State").and_then(|v| v.downcast_ref::<String>().ok()).unwrap_or_default();
+        let volume = transport_iface.get("Volume").and_then(|v| v.downcast_ref::<u16>().ok());
+        Self {path, device, codec, state, volume, volume_changed: None}
     }
-    
-    pub fn line_length(&self, full: i32) -> i32 {
-        ((self.position as f32 / self.duration as f32) * full as f32) as i32
+
+    /// Keeps the change timestamp, or marks the volume as changed if it differs
+    fn carry_volume_change(&mut self, previous: &MediaTransport) {
+        if self.path != previous.path {
+            return;
+        }
+        self.volume_changed = if self.volume == previous.volume { previous.volume_changed } else { Some(Instant::now()) };
     }
-}
 
-impl From<(String, String, PlaybackState, u32, u32)> for PlaybackData {
-    fn from(value: (String, String, PlaybackState, u32, u32)) -> Self {
-        Self::new(limit_string_size(value.0, 41), limit_string_size(value.1, 20), value.2, value.3, value.4)
+    pub fn codec_name(&self) -> &'static str {
+        // A2DP codec ids
+        match self.codec {
+            0x00 => "SBC",
+            0x01 => "MP3",
+            0x02 => "AAC",
+            0x04 => "ATRAC",
+            0xFF => "Vendor",
+            _ => "Unknown"
+        }
     }
 }
 
 impl BluetoothController<'_> {
-    pub async fn new() -> zbus::Result<Self> {
+    pub async fn new(store: Arc<Store>) -> zbus::Result<Self> {
         let connection = Connection::system().await?;
 
         let proxy = Proxy::new(
@@ -91,31 +231,99 @@ impl BluetoothController<'_> {
             "org.freedesktop.DBus.ObjectManager",
         ).await?;
         
-        Ok(Self {proxy})
+        Ok(Self {proxy, last_device: Mutex::new(None), player: Mutex::new(None), store})
+    }
+
+    pub fn connection(&self) -> &Connection {
+        self.proxy.connection()
     }
     
-    async fn get_data(&self) -> zbus::Result<Option<(String, String, PlaybackState, u32, u32)>> {
-        let managed_objects: HashMap<
-            zvariant::OwnedObjectPath,
-            HashMap<String, HashMap<String, zvariant::OwnedValue>>,
-        > = self.proxy.call("GetManagedObjects", &()).await?;
+    fn cover_art_ref(device: Option<&BluetoothDevice>, player_iface: &Interface, track: &Dict) -> zbus::Result<Option<CoverArtRef>> {
+        let handle: Option<String> = track.get(&"ImgHandle".to_string())?;
+        let port = player_iface.get("ObexPort").and_then(|v| v.downcast_ref::<u16>().ok());
+
+        match (handle, device, port) {
+            (Some(handle), Some(device), Some(port)) if !handle.is_empty() => Ok(Some(CoverArtRef {handle, address: device.address.clone(), port})),
+            _ => Ok(None)
+        }
+    }
+
+    fn devices(managed_objects: &ManagedObjects) -> HashMap<OwnedObjectPath, BluetoothDevice> {
+        managed_objects.iter()
+            .filter_map(|(path, interfaces)| {
+                BluetoothDevice::from_interfaces(path.clone(), interfaces).map(|device| (path.clone(), device))
+            })
+            .collect()
+    }
+
+    /// Picks the player to show: playing before paused before stopped, then by configured device priority
+    fn select_player<'m>(managed_objects: &'m ManagedObjects, devices: &HashMap<OwnedObjectPath, BluetoothDevice>) -> Option<(&'m OwnedObjectPath, &'m Interface, Option<BluetoothDevice>)> {
+        managed_objects.iter()
+            .filter_map(|(path, interfaces)| {
+                let player_iface = interfaces.get("org.bluez.MediaPlayer1")?;
+                let device = player_iface.get("Device")
+                    .and_then(|v| v.downcast_ref::<zvariant::ObjectPath>().ok())
+                    .and_then(|device| devices.get(&OwnedObjectPath::from(device)))
+                    .cloned();
+                if device.as_ref().is_some_and(|d| !d.connected) {
+                    return None;
+                }
+
+                let state = player_iface.get("Status")
+                    .and_then(|v| v.downcast_ref::<String>().ok())
+                    .map(PlaybackState::from)
+                    .unwrap_or(PlaybackState::Stopped);
+                let state_rank = state.rank();
+                let priority = device.as_ref().map(|d| d.priority()).unwrap_or(usize::MAX);
+
+                Some(((state_rank, priority, path.to_string()), path, player_iface, device))
+            })
+            .min_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, path, player_iface, device)| (path, player_iface, device))
+    }
+
+    /// Active transport first, otherwise the one of the shown device
+    fn select_transport(managed_objects: &ManagedObjects, device: Option<&BluetoothDevice>) -> Option<MediaTransport> {
+        managed_objects.iter()
+            .filter_map(|(path, interfaces)| {
+                interfaces.get("org.bluez.MediaTransport1").map(|iface| MediaTransport::from_interface(path.clone(), iface))
+            })
+            .min_by_key(|t| (t.state != "active", device.is_none_or(|d| t.device.as_ref() != Some(&d.path)), t.path.to_string()))
+    }
+
+    async fn get_data(&self) -> zbus::Result<Option<PlaybackData>> {
+        let managed_objects: ManagedObjects = self.proxy.call("GetManagedObjects", &()).await?;
+        let devices = Self::devices(&managed_objects);
+
+        let mut device_list: Vec<BluetoothDevice> = devices.values().cloned().collect();
+        device_list.sort_by_key(|d| (!d.connected, !d.paired, d.alias.to_lowercase()));
+        set_devices(&self.store, device_list);
+
+        let selected = Self::select_player(&managed_objects, &devices);
+        set_transport(&self.store, Self::select_transport(&managed_objects, selected.as_ref().and_then(|(_, _, d)| d.as_ref())));
         
-        for (_, interfaces) in managed_objects {
-            if let Some(player_iface) = interfaces.get("org.bluez.MediaPlayer1") {
-                
-                if let Some(track_value) = player_iface.get("Track") {
-                    let track: Dict = track_value.downcast_ref()?;
-
-                    let title: String = track.get(&"Title".to_string())?.or(Some("Unknown".to_string())).unwrap();
-                    let artist: String = track.get(&"Artist".to_string())?.or(Some("Unknown".to_string())).unwrap();
-                    let duration: u32 = track.get(&"Duration".to_string())?.or(Some(0)).unwrap();
-
-                    if let Some(position_value) = player_iface.get("Position") {
-                        if let Ok(pos) = position_value.downcast_ref::<u32>() {
-                            if let Some(status_value) = player_iface.get("Status") {
-                                if let Ok(status) = status_value.downcast_ref::<String>() {
-                                    return Ok(Some((title, artist, status.into(), pos, duration)))
+        *self.player.lock().unwrap() = selected.as_ref().map(|(path, _, _)| (*path).clone());
+
+        if let Some((_, player_iface, device)) = selected {
+            if let Some(track_value) = player_iface.get("Track") {
+                let track: Dict = track_value.downcast_ref()?;
+
+                let title: String = track.get(&"Title".to_string())?.or(Some("Unknown".to_string())).unwrap();
+                let artist: String = track.get(&"Artist".to_string())?.or(Some("Unknown".to_string())).unwrap();
+                let duration: u32 = track.get(&"Duration".to_string())?.or(Some(0)).unwrap();
+                let cover_art = Self::cover_art_ref(device.as_ref(), player_iface, &track)?;
+
+                if let Some(position_value) = player_iface.get("Position") {
+                    if let Ok(pos) = position_value.downcast_ref::<u32>() {
+                        if let Some(status_value) = player_iface.get("Status") {
+                            if let Ok(status) = status_value.downcast_ref::<String>() {
+                                let mut data = PlaybackData::from((title, artist, status.into(), pos, duration));
+                                data.cover_art = cover_art.map(CoverArtSource::Bluetooth);
+                                if let Some(device) = &device {
+                                    self.remember_device(device);
                                 }
+                                data.device = device.as_ref().map(SourceDevice::from);
+                                return Ok(Some(data))
                             }
                         }
                     }
@@ -126,11 +334,56 @@ impl BluetoothController<'_> {
         Ok(None)
     }
     
-    pub async fn poll(&self) -> Option<PlaybackData> {
-        if let Ok(Some(data)) = self.get_data().await {
-            Some(PlaybackData::from(data))
-        } else {
-            None
+    async fn run_device_action(&self, device: &BluetoothDevice, action: DeviceAction) -> zbus::Result<()> {
+        let device_proxy = Proxy::new(self.connection(), "org.bluez", &device.path, "org.bluez.Device1").await?;
+        match action {
+            DeviceAction::Connect => device_proxy.call("Connect", &()).await,
+            DeviceAction::Disconnect => device_proxy.call("Disconnect", &()).await,
+            DeviceAction::Trust => device_proxy.set_property("Trusted", !device.trusted).await.map_err(zbus::Error::from),
+            DeviceAction::Remove => {
+                let adapter_path = format!("/org/bluez/{}", CONFIG().bluetooth.adapter);
+                let adapter = Proxy::new(self.connection(), "org.bluez", adapter_path.as_str(), "org.bluez.Adapter1").await?;
+                adapter.call("RemoveDevice", &(&device.path,)).await
+            }
+        }
+    }
+
+    fn remember_device(&self, device: &BluetoothDevice) {
+        let mut last_device = self.last_device.lock().unwrap();
+        if last_device.as_ref() != Some(&device.address) {
+            save_last_device(&device.address);
+            *last_device = Some(device.address.clone());
+        }
+    }
+
+    /// Connects to the device that was used last (if it is known and not connected yet)
+    pub async fn reconnect_last_device(&self) -> zbus::Result<()> {
+        let Some(address) = load_last_device() else { return Ok(()) };
+        let managed_objects: ManagedObjects = self.proxy.call("GetManagedObjects", &()).await?;
+        let device = Self::devices(&managed_objects).into_values()
+            .find(|d| d.address.eq_ignore_ascii_case(&address));
+
+        match device {
+            Some(device) if !device.connected => self.run_device_action(&device, DeviceAction::Connect).await,
+            _ => Ok(())
         }
     }
+
+    async fn control(&self, command: MediaCommand) -> zbus::Result<()> {
+        let Some(path) = self.player.lock().unwrap().clone() else { return Ok(()) };
+        let player = Proxy::new(self.connection(), "org.bluez", path, "org.bluez.MediaPlayer1").await?;
+        let method = match command {
+            MediaCommand::PlayPause => {
+                let status: String = player.get_property("Status").await?;
+                if PlaybackState::from(status) == PlaybackState::Playing { "Pause" } else { "Play" }
+            }
+            MediaCommand::Next => "Next",
+            MediaCommand::Previous => "Previous",
+        };
+        player.call(method, &()).await
+    }
+
+    pub async fn poll(&self) -> Option<PlaybackData> {
+        self.get_data().await.ok().flatten()
+    }
 }
@@ -0,0 +1,15 @@
+use std::sync::Mutex;
+use chrono::{DateTime, Local};
+
+// Set for snapshots, the displayed time then stands still
+static _FROZEN_AT: Mutex<Option<DateTime<Local>>> = Mutex::new(None);
+
+/// Wall clock time for everything that is displayed
+pub fn now() -> DateTime<Local> {
+    _FROZEN_AT.lock().unwrap().unwrap_or_else(Local::now)
+}
+
+/// Stops the displayed clock at `at`, `None` lets it run again
+pub fn freeze(at: Option<DateTime<Local>>) {
+    *_FROZEN_AT.lock().unwrap() = at;
+}
@@ -0,0 +1,505 @@
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::time::Duration;
+use serde_json::{json, Map, Value};
+use crate::pairing::AgentMode;
+use crate::ui_renderer::{Align, Length};
+
+const DEFAULT_CONFIG_PATH: &str = "/home/jasper/unyo.json";
+const DEFAULT_STATE_DIR: &str = "/home/jasper/.unyo";
+const DEFAULT_LYRICS_DIR: &str = "/home/jasper/lyrics";
+const DEFAULT_RES_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res");
+
+static _CONFIG: LazyLock<Config> = LazyLock::new(Config::load);
+
+#[allow(non_snake_case)]
+pub fn CONFIG() -> &'static Config {
+    &_CONFIG
+}
+
+/// Path of a font or icon in the resource directory
+pub fn resource(name: &str) -> String {
+    CONFIG().res_dir.join(name).to_string_lossy().into_owned()
+}
+
+// `$UNYO_RES` wins over the config
+fn res_dir(configured: String) -> PathBuf {
+    PathBuf::from(std::env::var("UNYO_RES").unwrap_or(configured))
+}
+
+fn get_str_list(value: Option<&Value>) -> Vec<String> {
+    value.and_then(|v| v.as_array())
+        .map(|a| a.iter().filter_map(|s| s.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+fn get_string(head: &Map<String, Value>, key: &str, default: String) -> String {
+    head.get(key).and_then(|v| v.as_str()).map(str::to_string).unwrap_or(default)
+}
+
+pub(crate) fn get_bool(head: &Map<String, Value>, key: &str, default: bool) -> bool {
+    head.get(key).and_then(|v| v.as_bool()).unwrap_or(default)
+}
+
+pub(crate) fn get_u32(head: &Map<String, Value>, key: &str, default: u32) -> u32 {
+    head.get(key).and_then(|v| v.as_u64()).map(|v| v as u32).unwrap_or(default)
+}
+
+fn get_f32(head: &Map<String, Value>, key: &str, default: f32) -> f32 {
+    head.get(key).and_then(|v| v.as_f64()).map(|v| v as f32).unwrap_or(default)
+}
+
+#[derive(Debug)]
+pub struct BluetoothConfig {
+    // Device addresses or aliases, highest priority first
+    pub priority: Vec<String>,
+    pub adapter: String,
+    pub alias: String,
+    pub discoverable: bool,
+    pub pairable: bool,
+    // Seconds, 0 means forever
+    pub discoverable_timeout: u32,
+    pub agent: AgentMode,
+    // Reconnect the last used device at startup
+    pub auto_reconnect: bool,
+}
+
+impl Default for BluetoothConfig {
+    fn default() -> Self {
+        Self {
+            priority: vec![],
+            adapter: "hci0".to_string(),
+            alias: "Raspi Audio Player".to_string(),
+            discoverable: true,
+            pairable: true,
+            discoverable_timeout: 180,
+            agent: AgentMode::Confirm,
+            auto_reconnect: true,
+        }
+    }
+}
+
+impl BluetoothConfig {
+    fn from_json(value: Option<&Value>) -> Self {
+        let default = Self::default();
+        let Some(head) = value.and_then(|v| v.as_object()) else { return default };
+        Self {
+            priority: get_str_list(head.get("priority")),
+            adapter: get_string(head, "adapter", default.adapter),
+            alias: get_string(head, "alias", default.alias),
+            discoverable: get_bool(head, "discoverable", default.discoverable),
+            pairable: get_bool(head, "pairable", default.pairable),
+            discoverable_timeout: get_u32(head, "discoverable_timeout", default.discoverable_timeout),
+            agent: head.get("agent").and_then(|v| v.as_str()).and_then(|v| v.parse().ok()).unwrap_or(default.agent),
+            auto_reconnect: get_bool(head, "auto_reconnect", default.auto_reconnect),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaPolicy {
+    // Whatever is playing, ties are broken by source order
+    PlayingFirst,
+    // Always the first source that has something
+    Priority,
+}
+
+#[derive(Debug)]
+pub struct MediaConfig {
+    // Enabled media sources, highest priority first
+    pub sources: Vec<String>,
+    pub policy: MediaPolicy,
+    // host:port or path of the Unix socket
+    pub mpd_address: String,
+    pub mpd_password: Option<String>,
+    // shairport-sync metadata pipe, ignored if the UDP stream is configured
+    pub shairport_pipe: String,
+    // Bind address for the shairport-sync UDP metadata stream
+    pub shairport_udp: Option<String>,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            sources: vec!["bluetooth".to_string(), "mpris".to_string()],
+            policy: MediaPolicy::PlayingFirst,
+            mpd_address: "localhost:6600".to_string(),
+            mpd_password: None,
+            shairport_pipe: "/tmp/shairport-sync-metadata".to_string(),
+            shairport_udp: None,
+        }
+    }
+}
+
+impl MediaConfig {
+    fn from_json(value: Option<&Value>) -> Self {
+        let default = Self::default();
+        let Some(head) = value.and_then(|v| v.as_object()) else { return default };
+        let policy = match head.get("policy").and_then(|v| v.as_str()) {
+            Some("priority") => MediaPolicy::Priority,
+            Some("playing-first") => MediaPolicy::PlayingFirst,
+            _ => default.policy
+        };
+        Self {
+            sources: if head.contains_key("sources") { get_str_list(head.get("sources")) } else { default.sources },
+            policy,
+            mpd_address: get_string(head, "mpd_address", default.mpd_address),
+            mpd_password: head.get("mpd_password").and_then(|v| v.as_str()).map(str::to_string),
+            shairport_pipe: get_string(head, "shairport_pipe", default.shairport_pipe),
+            shairport_udp: head.get("shairport_udp").and_then(|v| v.as_str()).map(str::to_string),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrobblerService {
+    ListenBrainz,
+    // Audioscrobbler 2.0 API (Last.fm, Libre.fm, ...)
+    LastFm,
+}
+
+#[derive(Debug)]
+pub struct ScrobblerConfig {
+    pub service: ScrobblerService,
+    // Base URL of the API, e.g. a local mock
+    pub endpoint: String,
+    // ListenBrainz user token
+    pub token: String,
+    // Last.fm API key / secret and session key
+    pub api_key: String,
+    pub api_secret: String,
+    pub session_key: String,
+}
+
+impl ScrobblerConfig {
+    // None if disabled or without credentials
+    fn from_json(value: Option<&Value>) -> Option<Self> {
+        let head = value.and_then(|v| v.as_object())?;
+        if !get_bool(head, "enabled", true) {
+            return None;
+        }
+        let service = match head.get("service").and_then(|v| v.as_str()) {
+            Some("listenbrainz") => ScrobblerService::ListenBrainz,
+            Some("lastfm") => ScrobblerService::LastFm,
+            other => {
+                eprintln!("Unknown scrobbler service: {other:?}");
+                return None;
+            }
+        };
+        let endpoint = match service {
+            ScrobblerService::ListenBrainz => "https://api.listenbrainz.org",
+            ScrobblerService::LastFm => "https://ws.audioscrobbler.com/2.0/",
+        };
+        let config = Self {
+            service,
+            endpoint: get_string(head, "endpoint", endpoint.to_string()),
+            token: get_string(head, "token", String::new()),
+            api_key: get_string(head, "api_key", String::new()),
+            api_secret: get_string(head, "api_secret", String::new()),
+            session_key: get_string(head, "session_key", String::new()),
+        };
+        let credentials = match service {
+            ScrobblerService::ListenBrainz => !config.token.is_empty(),
+            ScrobblerService::LastFm => !config.api_key.is_empty() && !config.session_key.is_empty(),
+        };
+        if !credentials {
+            eprintln!("Scrobbling disabled, no credentials for {service:?}");
+            return None;
+        }
+        Some(config)
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryConfig {
+    // Number of tracks in the "recently played" view
+    pub recent: u32,
+    pub scrobbler: Option<ScrobblerConfig>,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {recent: 10, scrobbler: None}
+    }
+}
+
+impl HistoryConfig {
+    fn from_json(value: Option<&Value>) -> Self {
+        let default = Self::default();
+        let Some(head) = value.and_then(|v| v.as_object()) else { return default };
+        Self {
+            recent: get_u32(head, "recent", default.recent),
+            scrobbler: ScrobblerConfig::from_json(head.get("scrobbler")),
+        }
+    }
+}
+
+/// Audio for the spectrum, 16 bit little endian
+#[derive(Debug)]
+pub enum CaptureSource {
+    // ALSA capture device (through arecord)
+    Alsa(String),
+    // PipeWire node, e.g. a sink monitor (through pw-record)
+    PipeWire(String),
+    // WAV file, or raw stereo 44.1 kHz from a file / FIFO
+    File(String),
+}
+
+#[derive(Debug)]
+pub struct SpectrumConfig {
+    pub capture: Option<CaptureSource>,
+    pub bars: u32,
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        Self {capture: None, bars: 32}
+    }
+}
+
+impl SpectrumConfig {
+    fn from_json(value: Option<&Value>) -> Self {
+        let default = Self::default();
+        let Some(head) = value.and_then(|v| v.as_object()) else { return default };
+        let device = head.get("device").and_then(|v| v.as_str()).map(str::to_string);
+        let capture = match (head.get("capture").and_then(|v| v.as_str()), device) {
+            (Some("alsa"), device) => Some(CaptureSource::Alsa(device.unwrap_or("default".to_string()))),
+            (Some("pipewire"), Some(device)) => Some(CaptureSource::PipeWire(device)),
+            (Some("file"), Some(device)) => Some(CaptureSource::File(device)),
+            (None, _) => None,
+            (Some(capture), _) => {
+                eprintln!("Invalid spectrum capture: {capture}");
+                None
+            }
+        };
+        Self {capture, bars: get_u32(head, "bars", default.bars).max(1)}
+    }
+}
+
+// A number is a flex weight (at least 1), otherwise "12px", "4ep", "50%" or "5/9". Fractions
+// have to be positive
+fn parse_length(value: &Value) -> Option<Length> {
+    if let Some(weight) = value.as_u64() {
+        return u32::try_from(weight).ok().filter(|weight| *weight != 0).map(Length::Flex);
+    }
+    let value = value.as_str()?.trim();
+    let fraction = |fraction: f32| (fraction.is_finite() && fraction > 0.0).then_some(Length::Fraction(fraction));
+    if let Some(px) = value.strip_suffix("px") {
+        px.trim().parse().ok().map(Length::Px)
+    } else if let Some(ep) = value.strip_suffix("ep") {
+        ep.trim().parse::<f32>().ok().filter(|ep| ep.is_finite()).map(Length::Ep)
+    } else if let Some(percent) = value.strip_suffix('%') {
+        fraction(percent.trim().parse::<f32>().ok()? / 100.0)
+    } else {
+        let (a, b) = value.split_once('/')?;
+        fraction(a.trim().parse::<f32>().ok()? / b.trim().parse::<f32>().ok()?)
+    }
+}
+
+fn parse_align(value: Option<&Value>) -> Align {
+    match value.and_then(|v| v.as_str()) {
+        Some("center") => Align::Center,
+        Some("end") => Align::End,
+        _ => Align::Start
+    }
+}
+
+/// Layout container ("row", "column", "stack") or widget instance of the main screen
+#[derive(Debug, Clone)]
+pub struct ScreenNode {
+    pub kind: String,
+    pub width: Length,
+    pub height: Length,
+    // In EDGE_PADDINGs
+    pub padding: f32,
+    pub spacing: f32,
+    pub halign: Align,
+    pub valign: Align,
+    // Passed to the widget
+    pub options: Map<String, Value>,
+    pub children: Vec<ScreenNode>,
+}
+
+impl ScreenNode {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let head = value.as_object()?;
+        let Some(kind) = head.get("type").and_then(|v| v.as_str()) else {
+            eprintln!("Screen node without a type: {value}");
+            return None;
+        };
+        let length = |key: &str| match head.get(key) {
+            Some(value) => parse_length(value).unwrap_or_else(|| {
+                eprintln!("Invalid {key} of {kind}: {value}");
+                Length::Flex(1)
+            }),
+            None => Length::Flex(1)
+        };
+        Some(Self {
+            kind: kind.to_string(),
+            width: length("width"),
+            height: length("height"),
+            padding: get_f32(head, "padding", 0.0),
+            spacing: get_f32(head, "spacing", 0.0),
+            halign: parse_align(head.get("halign")),
+            valign: parse_align(head.get("valign")),
+            options: head.get("options").and_then(|v| v.as_object()).cloned().unwrap_or_default(),
+            children: head.get("children").and_then(|v| v.as_array())
+                .map(|children| children.iter().filter_map(ScreenNode::from_json).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+// Clock / media and weather on the left, lyrics on the right
+pub fn default_screen() -> ScreenNode {
+    ScreenNode::from_json(&json!({
+        "type": "row",
+        "padding": 1,
+        "spacing": 1,
+        "children": [
+            {"type": "column", "width": "5/9", "children": [{"type": "info"}, {"type": "weather"}]},
+            {"type": "lyrics"}
+        ]
+    })).unwrap()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageTransition {
+    None,
+    Slide,
+    Fade,
+}
+
+/// One page of the main screen
+#[derive(Debug, Clone)]
+pub struct PageConfig {
+    pub name: String,
+    // Shown while something is playing
+    pub media: bool,
+    pub screen: ScreenNode,
+}
+
+impl PageConfig {
+    fn from_json(value: &Value) -> Option<Self> {
+        let head = value.as_object()?;
+        let Some(screen) = head.get("screen").and_then(ScreenNode::from_json) else {
+            eprintln!("Page without a screen: {value}");
+            return None;
+        };
+        Some(Self {
+            name: get_string(head, "name", String::new()),
+            media: get_bool(head, "media", false),
+            screen,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct PagesConfig {
+    pub pages: Vec<PageConfig>,
+    pub transition: PageTransition,
+    pub transition_duration: Duration,
+    // Moves on to the next page after this long, if set
+    pub cycle: Option<Duration>,
+    // Go to the media page when playback starts, the schedule leads back to it while playing.
+    // Swipes and keys can still leave it
+    pub pin_media: bool,
+}
+
+impl PagesConfig {
+    // Without a page list the `screen` is the only page
+    fn from_json(value: Option<&Value>, screen: ScreenNode) -> Self {
+        let single = vec![PageConfig {name: "main".to_string(), media: true, screen}];
+        let Some(head) = value.and_then(|v| v.as_object()) else {
+            return Self {pages: single, transition: PageTransition::Slide, transition_duration: Duration::from_millis(350), cycle: None, pin_media: true};
+        };
+        let pages: Vec<PageConfig> = head.get("list").and_then(|v| v.as_array())
+            .map(|pages| pages.iter().filter_map(PageConfig::from_json).collect())
+            .unwrap_or_default();
+        let transition = match head.get("transition").and_then(|v| v.as_str()) {
+            Some("none") => PageTransition::None,
+            Some("fade") => PageTransition::Fade,
+            _ => PageTransition::Slide
+        };
+        Self {
+            pages: if pages.is_empty() { single } else { pages },
+            transition,
+            transition_duration: Duration::from_millis(get_u32(head, "transition_ms", 350) as u64),
+            cycle: Some(get_u32(head, "cycle", 0)).filter(|s| *s != 0).map(|s| Duration::from_secs(s as u64)),
+            pin_media: get_bool(head, "pin_media", true),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    // Where unyo keeps its own state (last device, ...)
+    pub state_dir: PathBuf,
+    // Directory with .lrc files ("Artist - Title.lrc", "Artist/Title.lrc", ...)
+    pub lyrics_dir: PathBuf,
+    // Fonts and icons
+    pub res_dir: PathBuf,
+    pub bluetooth: BluetoothConfig,
+    pub media: MediaConfig,
+    pub history: HistoryConfig,
+    pub spectrum: SpectrumConfig,
+    pub pages: PagesConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {state_dir: PathBuf::from(DEFAULT_STATE_DIR), lyrics_dir: PathBuf::from(DEFAULT_LYRICS_DIR), res_dir: res_dir(DEFAULT_RES_DIR.to_string()), bluetooth: BluetoothConfig::default(), media: MediaConfig::default(), history: HistoryConfig::default(), spectrum: SpectrumConfig::default(), pages: PagesConfig::from_json(None, default_screen())}
+    }
+}
+
+impl Config {
+    /// Loads the config from `$UNYO_CONFIG` (or the default path), falls back to defaults
+    pub fn load() -> Self {
+        let path = std::env::var("UNYO_CONFIG").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+        match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(value) => Self::from_json(value),
+                Err(e) => {
+                    eprintln!("Invalid config ({path}): {e}");
+                    Self::default()
+                }
+            },
+            Err(_) => Self::default()
+        }
+    }
+
+    pub fn from_json(value: Value) -> Self {
+        let Some(head) = value.as_object() else { return Self::default() };
+        Self {
+            state_dir: PathBuf::from(get_string(head, "state_dir", DEFAULT_STATE_DIR.to_string())),
+            lyrics_dir: PathBuf::from(get_string(head, "lyrics_dir", DEFAULT_LYRICS_DIR.to_string())),
+            res_dir: res_dir(get_string(head, "res_dir", DEFAULT_RES_DIR.to_string())),
+            bluetooth: BluetoothConfig::from_json(head.get("bluetooth")),
+            media: MediaConfig::from_json(head.get("media")),
+            history: HistoryConfig::from_json(head.get("history")),
+            spectrum: SpectrumConfig::from_json(head.get("spectrum")),
+            pages: PagesConfig::from_json(head.get("pages"), head.get("screen").and_then(ScreenNode::from_json).unwrap_or_else(default_screen)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths() {
+        assert_eq!(parse_length(&json!(2)), Some(Length::Flex(2)));
+        assert_eq!(parse_length(&json!("12px")), Some(Length::Px(12)));
+        assert_eq!(parse_length(&json!("4ep")), Some(Length::Ep(4.0)));
+        assert_eq!(parse_length(&json!("50%")), Some(Length::Fraction(0.5)));
+        assert_eq!(parse_length(&json!("1/4")), Some(Length::Fraction(0.25)));
+    }
+
+    #[test]
+    fn invalid_lengths() {
+        for value in [json!(0), json!(u64::MAX), json!("5/0"), json!("0/3"), json!("-1/2"), json!("0%"), json!("NaN%"), json!("infep"), json!("wide")] {
+            assert_eq!(parse_length(&value), None, "{value}");
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use zbus::{Connection, Proxy};
+use zvariant::{OwnedObjectPath, OwnedValue, Value};
+use crate::redraw::request_redraw;
+
+const OBEX_SERVICE: &str = "org.bluez.obex";
+const OBEX_PATH: &str = "/org/bluez/obex";
+const TRANSFER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a failed fetch is remembered before it is tried again
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+pub static _COVER_ART_FETCHER: OnceLock<CoverArtFetcher> = OnceLock::new();
+static _COVER_ART_CACHE: LazyLock<Mutex<HashMap<CoverArtRef, CoverArt>>> =
+    LazyLock::new(|| {Mutex::new(HashMap::new())});
+
+/// Where to get the cover art of a track from (AVRCP BIP)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoverArtRef {
+    pub handle: String,
+    pub address: String,
+    pub port: u16,
+}
+
+impl CoverArtRef {
+    // Address and handle come from the device, hashed they are safe to use as a file name
+    fn target_path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{:x}.jpg", md5::compute(format!("{}-{}", self.address, self.handle))))
+    }
+}
+
+/// Cover art of a track, as provided by the media source
+#[derive(Debug, Clone)]
+pub enum CoverArtSource {
+    // Fetched over AVRCP BIP
+    Bluetooth(CoverArtRef),
+    // Image file on disk
+    File(String),
+}
+
+impl CoverArtSource {
+    /// Path of the image, if it is available (yet)
+    pub fn path(&self) -> Option<String> {
+        match self {
+            CoverArtSource::Bluetooth(cover) => cover_art_path(cover),
+            CoverArtSource::File(path) => Some(path.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CoverArt {
+    Pending,
+    Available(String),
+    // Failed at this time
+    Unavailable(Instant),
+}
+
+/// Path of the cached image, if it has been fetched.
+/// Requests a fetch if the image is not known yet.
+pub fn cover_art_path(cover: &CoverArtRef) -> Option<String> {
+    let mut cache = _COVER_ART_CACHE.lock().unwrap();
+    match cache.get(cover) {
+        Some(CoverArt::Available(path)) => Some(path.clone()),
+        Some(CoverArt::Pending) => None,
+        Some(CoverArt::Unavailable(since)) if since.elapsed() < RETRY_INTERVAL => None,
+        _ => {
+            if _COVER_ART_FETCHER.get().is_some() {
+                cache.insert(cover.clone(), CoverArt::Pending);
+                let cover = cover.clone();
+                tokio::spawn(async move {
+                    let result = match _COVER_ART_FETCHER.get().unwrap().fetch(&cover).await {
+                        Ok(path) => CoverArt::Available(path),
+                        Err(e) => {
+                            eprintln!("Failed to fetch cover art {}: {e}", cover.handle);
+                            CoverArt::Unavailable(Instant::now())
+                        }
+                    };
+                    _COVER_ART_CACHE.lock().unwrap().insert(cover, result);
+                    request_redraw();
+                });
+            }
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CoverArtFetcher {
+    connection: Connection,
+    // Where fetched images are kept
+    dir: PathBuf,
+    // Device address -> OBEX session
+    sessions: tokio::sync::Mutex<HashMap<String, OwnedObjectPath>>,
+}
+
+impl CoverArtFetcher {
+    pub async fn new() -> zbus::Result<Self> {
+        Self::with_connection(Connection::session().await?, std::env::temp_dir().join("unyo-cover-art"))
+    }
+
+    pub fn with_connection(connection: Connection, dir: PathBuf) -> zbus::Result<Self> {
+        Ok(Self {connection, dir, sessions: tokio::sync::Mutex::new(HashMap::new())})
+    }
+
+    async fn session(&self, cover: &CoverArtRef) -> zbus::Result<OwnedObjectPath> {
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session) = sessions.get(&cover.address) {
+            return Ok(session.clone());
+        }
+
+        let client = Proxy::new(&self.connection, OBEX_SERVICE, OBEX_PATH, "org.bluez.obex.Client1").await?;
+        let mut args: HashMap<&str, Value> = HashMap::new();
+        args.insert("Target", "bip-avrcp".into());
+        args.insert("PSM", cover.port.into());
+        let session: OwnedObjectPath = client.call("CreateSession", &(cover.address.as_str(), args)).await?;
+
+        sessions.insert(cover.address.clone(), session.clone());
+        Ok(session)
+    }
+
+    // `size` is what the transfer announced, 0 if unknown
+    async fn wait_for_transfer(&self, transfer: OwnedObjectPath, file: &Path, size: u64) -> zbus::Result<()> {
+        let proxy = Proxy::new(&self.connection, OBEX_SERVICE, transfer, "org.bluez.obex.Transfer1").await?;
+        let deadline = tokio::time::Instant::now() + TRANSFER_TIMEOUT;
+
+        while tokio::time::Instant::now() < deadline {
+            match proxy.get_property::<String>("Status").await {
+                Ok(status) if status == "complete" => return Ok(()),
+                Ok(status) if status == "error" => return Err(zbus::Error::Failure("Transfer failed".to_string())),
+                Ok(_) => {}
+                // The transfer object is removed once it is done, only trust a file of the announced size then
+                Err(e) => {
+                    let written = std::fs::metadata(file).map(|m| m.len()).ok();
+                    return if size > 0 && written == Some(size) { Ok(()) } else { Err(e) };
+                }
+            }
+            tokio::time::sleep(TRANSFER_POLL_INTERVAL).await;
+        }
+
+        Err(zbus::Error::Failure("Transfer timed out".to_string()))
+    }
+
+    pub async fn fetch(&self, cover: &CoverArtRef) -> zbus::Result<String> {
+        let target = cover.target_path(&self.dir);
+        if target.exists() {
+            return Ok(target.to_string_lossy().to_string());
+        }
+        std::fs::create_dir_all(target.parent().unwrap()).map_err(|e| zbus::Error::Failure(e.to_string()))?;
+        // Written under another name, only a complete image is renamed to `target`
+        let partial = target.with_extension("part");
+        let _ = std::fs::remove_file(&partial);
+
+        let session = self.session(cover).await?;
+        let image = Proxy::new(&self.connection, OBEX_SERVICE, session, "org.bluez.obex.Image1").await?;
+        let result: zbus::Result<(OwnedObjectPath, HashMap<String, OwnedValue>)> =
+            image.call("GetThumbnail", &(partial.to_string_lossy().as_ref(), cover.handle.as_str())).await;
+
+        let (transfer, properties) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                // Session is probably gone (device reconnected), create a new one next time
+                self.sessions.lock().await.remove(&cover.address);
+                return Err(e);
+            }
+        };
+
+        let size = properties.get("Size").and_then(|size| u64::try_from(size).ok()).unwrap_or(0);
+        if let Err(e) = self.wait_for_transfer(transfer, &partial, size).await {
+            let _ = std::fs::remove_file(&partial);
+            return Err(e);
+        }
+        std::fs::rename(&partial, &target).map_err(|e| zbus::Error::Failure(e.to_string()))?;
+        Ok(target.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+    use zbus::object_server::ObjectServer;
+    use zvariant::ObjectPath;
+
+    const SESSION_PATH: &str = "/org/bluez/obex/client/session0";
+    const TRANSFER_PATH: &str = "/org/bluez/obex/client/session0/transfer0";
+
+    struct FakeClient;
+
+    #[zbus::interface(name = "org.bluez.obex.Client1")]
+    impl FakeClient {
+        fn create_session(&self, _destination: &str, _args: HashMap<&str, Value<'_>>) -> OwnedObjectPath {
+            ObjectPath::try_from(SESSION_PATH).unwrap().into()
+        }
+    }
+
+    // Writes `written` bytes of an image of `size`, the transfer then reports `status`
+    // (or is gone already if None)
+    struct FakeImage {
+        size: usize,
+        written: usize,
+        status: Option<&'static str>,
+    }
+
+    #[zbus::interface(name = "org.bluez.obex.Image1")]
+    impl FakeImage {
+        async fn get_thumbnail(&self, file: &str, _handle: &str, #[zbus(object_server)] server: &ObjectServer)
+            -> (OwnedObjectPath, HashMap<String, OwnedValue>) {
+            std::fs::write(file, vec![0xff; self.written]).unwrap();
+            if let Some(status) = self.status {
+                server.at(TRANSFER_PATH, FakeTransfer {status}).await.unwrap();
+            }
+            let properties = HashMap::from([("Size".to_string(), OwnedValue::from(self.size as u64))]);
+            (ObjectPath::try_from(TRANSFER_PATH).unwrap().into(), properties)
+        }
+    }
+
+    struct FakeTransfer {
+        status: &'static str,
+    }
+
+    #[zbus::interface(name = "org.bluez.obex.Transfer1")]
+    impl FakeTransfer {
+        #[zbus(property)]
+        fn status(&self) -> String {
+            self.status.to_string()
+        }
+    }
+
+    // Removed with everything in it once the test is done
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("unyo-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            Self(dir)
+        }
+
+        fn target(&self, cover: &CoverArtRef) -> PathBuf {
+            cover.target_path(&self.0)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn cover(handle: &str) -> CoverArtRef {
+        CoverArtRef {handle: handle.to_string(), address: "00:11:22:33:44:55".to_string(), port: 4097}
+    }
+
+    // Fetches from a fake obexd over a peer-to-peer connection, into a directory of its own
+    async fn fetch(name: &str, image: FakeImage) -> (zbus::Result<String>, PathBuf, TempDir) {
+        let dir = TempDir::new(name);
+        let cover = cover("1000001");
+
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = zbus::connection::Builder::async_io_unix_stream(server)
+            .server(zbus::Guid::generate()).unwrap()
+            .p2p()
+            .serve_at(OBEX_PATH, FakeClient).unwrap()
+            .serve_at(SESSION_PATH, image).unwrap()
+            .build();
+        let client = zbus::connection::Builder::async_io_unix_stream(client).p2p().build();
+        let (_server, client) = tokio::try_join!(server, client).unwrap();
+
+        let fetcher = CoverArtFetcher::with_connection(client, dir.0.clone()).unwrap();
+        let result = fetcher.fetch(&cover).await;
+        (result, dir.target(&cover), dir)
+    }
+
+    #[tokio::test]
+    async fn complete_transfer_is_renamed() {
+        let (result, target, _dir) = fetch("complete-transfer", FakeImage {size: 512, written: 512, status: Some("complete")}).await;
+        assert_eq!(result.unwrap(), target.to_string_lossy());
+        assert_eq!(std::fs::read(&target).unwrap().len(), 512);
+        assert!(!target.with_extension("part").exists());
+    }
+
+    #[tokio::test]
+    async fn failed_transfer_leaves_nothing() {
+        let (result, target, _dir) = fetch("failed-transfer", FakeImage {size: 512, written: 100, status: Some("error")}).await;
+        assert!(result.is_err());
+        assert!(!target.exists());
+        assert!(!target.with_extension("part").exists());
+    }
+
+    #[tokio::test]
+    async fn truncated_transfer_is_not_cached() {
+        let (result, target, _dir) = fetch("truncated-transfer", FakeImage {size: 512, written: 100, status: None}).await;
+        assert!(result.is_err());
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn vanished_transfer_of_full_size_counts() {
+        let (result, target, _dir) = fetch("vanished-transfer", FakeImage {size: 512, written: 512, status: None}).await;
+        assert_eq!(result.unwrap(), target.to_string_lossy());
+    }
+
+    #[test]
+    fn device_handle_is_not_a_path() {
+        let dir = Path::new("/var/cache/unyo");
+        let target = cover("../../etc/passwd").target_path(dir);
+        let name = target.file_name().unwrap().to_string_lossy().to_string();
+        assert!(name.trim_end_matches(".jpg").chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(target.parent().unwrap(), dir);
+    }
+}
@@ -0,0 +1,257 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use crate::bluetooth::{device_action, BluetoothController, BluetoothDevice, DeviceAction, MediaTransport, VOLUME_MAX};
+use crate::gestures::{Gesture, Swipe};
+use crate::color::{BG_DARKEST, BG_SHADED, BG_TINTED, DIV_LINE, PB_EMPTY, PB_FULLY, TXT_DEFAULT, TXT_SUBTEXT};
+use crate::redraw::Redraw;
+use crate::state::{Store, Subscription};
+use crate::ui_renderer::{Drawable, Handled, Layout, Length, UIContext, UIHelper, EDGE_PADDING};
+
+const ACTIONS: [(DeviceAction, &str); 4] = [
+    (DeviceAction::Connect, "Verbinden"),
+    (DeviceAction::Disconnect, "Trennen"),
+    (DeviceAction::Trust, "Vertrauen"),
+    (DeviceAction::Remove, "Entfernen"),
+];
+// How long "Entfernen" waits for the second press
+const REMOVE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lists the known Bluetooth devices and offers connect / disconnect / trust / remove
+pub struct DeviceManagerWidget {
+    position: Rect,
+    // One button per action
+    actions: [Rect; ACTIONS.len()],
+    // Address of the selected device, the list is re-sorted whenever a device (dis)connects
+    selected: Option<String>,
+    // First row shown, the list scrolls to keep the selection visible
+    scroll: Cell<usize>,
+    // Device that gets removed on the second press, until then
+    remove_pending: Option<(String, Instant)>,
+    devices: Subscription<Vec<BluetoothDevice>>,
+    transport: Subscription<Option<MediaTransport>>,
+    // Runs the actions, none when simulating
+    bluetooth: Option<Arc<BluetoothController<'static>>>,
+}
+
+fn device_state(device: &BluetoothDevice) -> &'static str {
+    if device.connected { "Verbunden" }
+    else if device.paired { "Gekoppelt" }
+    else { "Bekannt" }
+}
+
+impl DeviceManagerWidget {
+    pub fn new(position: Rect, store: &Store, bluetooth: Option<Arc<BluetoothController<'static>>>) -> Self {
+        let placement = Layout::column(vec![
+            Layout::leaf(),
+            Layout::row(vec![Layout::leaf(); ACTIONS.len()]).named("actions").height(Length::Ep(8.0)).spacing(2 * EDGE_PADDING()),
+        ]).padding(2 * EDGE_PADDING()).resolve(position);
+        let actions = core::array::from_fn(|i| placement.children("actions")[i]);
+        Self {
+            position,
+            actions,
+            selected: None,
+            scroll: Cell::new(0),
+            remove_pending: None,
+            devices: store.devices.subscribe(),
+            transport: store.transport.subscribe(),
+            bluetooth,
+        }
+    }
+
+    fn row_height(&self) -> i32 {
+        8 * EDGE_PADDING()
+    }
+
+    fn row_rect(&self, index: usize) -> Rect {
+        let y = self.position.y + 10 * EDGE_PADDING() + index as i32 * (self.row_height() + EDGE_PADDING());
+        Rect::new(self.position.x + 2 * EDGE_PADDING(), y, (self.position.w - 4 * EDGE_PADDING()) as u32, self.row_height() as u32)
+    }
+
+    fn visible_rows(&self) -> usize {
+        let available = self.actions[0].y - self.row_rect(0).y;
+        (available / (self.row_height() + EDGE_PADDING())).max(1) as usize
+    }
+
+    // Index of the selected device in `devices`, the first one if none (or a gone one) is selected
+    fn selected_index(&self, devices: &[BluetoothDevice]) -> Option<usize> {
+        let index = self.selected.as_ref().and_then(|address| devices.iter().position(|device| device.address == *address));
+        index.or((!devices.is_empty()).then_some(0))
+    }
+
+    // Rows of the list that are shown, moved so the selected one is among them
+    fn shown_rows(&self, devices: &[BluetoothDevice]) -> std::ops::Range<usize> {
+        let rows = self.visible_rows();
+        let mut first = self.scroll.get().min(devices.len().saturating_sub(rows));
+        if let Some(selected) = self.selected_index(devices) {
+            first = first.clamp((selected + 1).saturating_sub(rows), selected);
+        }
+        self.scroll.set(first);
+        first..devices.len().min(first + rows)
+    }
+
+    fn select(&mut self, step: isize) {
+        let devices = self.devices.peek();
+        let Some(index) = self.selected_index(&devices) else { return };
+        let index = (index as isize + step).rem_euclid(devices.len() as isize) as usize;
+        self.selected = Some(devices[index].address.clone());
+        self.remove_pending = None;
+    }
+
+    // Looks the device up by address when the action runs, not by its place in the list
+    fn run(&mut self, action: DeviceAction) {
+        let devices = self.devices.peek();
+        let Some(device) = self.selected_index(&devices).map(|index| &devices[index]) else { return };
+        if action == DeviceAction::Remove {
+            // Needs a second press on the same device
+            let confirmed = self.remove_pending.take()
+                .is_some_and(|(address, since)| address == device.address && since.elapsed() < REMOVE_CONFIRM_TIMEOUT);
+            if !confirmed {
+                self.remove_pending = Some((device.address.clone(), Instant::now()));
+                return;
+            }
+        }
+        if let Some(bluetooth) = &self.bluetooth {
+            device_action(bluetooth, device, action);
+        }
+    }
+
+    fn remove_pending(&self) -> bool {
+        self.remove_pending.as_ref().is_some_and(|(_, since)| since.elapsed() < REMOVE_CONFIRM_TIMEOUT)
+    }
+
+    fn handle_click(&mut self, point: Point) {
+        for (rect, (action, _)) in self.actions.into_iter().zip(ACTIONS) {
+            if rect.contains_point(point) {
+                self.run(action);
+                return;
+            }
+        }
+
+        let devices = self.devices.peek();
+        let rows = self.shown_rows(&devices);
+        let first = rows.start;
+        if let Some(index) = rows.into_iter().find(|i| self.row_rect(i - first).contains_point(point)) {
+            if self.selected.as_ref() != Some(&devices[index].address) {
+                self.selected = Some(devices[index].address.clone());
+                self.remove_pending = None;
+            }
+        }
+    }
+}
+
+impl Drawable for DeviceManagerWidget {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        let xp = self.position.x + 2 * EDGE_PADDING();
+        ctx.draw_rect(self.position, BG_DARKEST);
+        let (x, y) = ctx.draw_text(xp, self.position.y + 2 * EDGE_PADDING(), &uihelper.font_owner.jb_medium_l, "GERÄTE", TXT_DEFAULT, uihelper);
+
+        // Transport diagnostics
+        if let Some(transport) = self.transport.get().as_ref() {
+            let volume = transport.volume.map(|v| format!("{v}/{VOLUME_MAX}")).unwrap_or("-".to_string());
+            let diagnostics = format!("Codec: {}  Status: {}  Lautstärke: {volume}", transport.codec_name(), transport.state);
+            ctx.draw_text(x + 4 * EDGE_PADDING(), y, &uihelper.font_owner.jb_medium_m, diagnostics.as_str(), TXT_SUBTEXT, uihelper);
+        }
+
+        let devices = self.devices.get();
+        if devices.is_empty() {
+            ctx.draw_text(xp, self.row_rect(0).y, &uihelper.font_owner.jb_medium_m, "Keine Geräte bekannt", TXT_SUBTEXT, uihelper);
+        }
+
+        let text_h = uihelper.font_owner.jb_medium_m.char_dim().two() as i32;
+        let selected = self.selected_index(&devices);
+        let rows = self.shown_rows(&devices);
+        let first = rows.start;
+        for index in rows {
+            let device = &devices[index];
+            let row = self.row_rect(index - first);
+            ctx.draw_rect(row, if Some(index) == selected { BG_TINTED } else { BG_SHADED });
+            if device.connected {
+                ctx.draw_line(row.top_left(), row.bottom_left(), EDGE_PADDING() / 2, DIV_LINE);
+            }
+
+            let y = row.y + (row.h - text_h) / 2;
+            let (x, _) = ctx.draw_text(row.x + 2 * EDGE_PADDING(), y, &uihelper.font_owner.jb_medium_l, device.alias.as_str(), TXT_DEFAULT, uihelper);
+            ctx.draw_text(x + 3 * EDGE_PADDING(), y, &uihelper.font_owner.jb_medium_m, device.address.as_str(), TXT_SUBTEXT, uihelper);
+
+            let state = if device.trusted { format!("{} *", device_state(device)) } else { device_state(device).to_string() };
+            let state_w = uihelper.font_owner.jb_medium_m.size_of_text(state.as_str()).one() as i32;
+            ctx.draw_text(row.right() - 2 * EDGE_PADDING() - state_w, y, &uihelper.font_owner.jb_medium_m, state.as_str(), TXT_SUBTEXT, uihelper);
+        }
+
+        // Scroll bar, only if not all devices fit
+        let rows = self.visible_rows();
+        if devices.len() > rows {
+            let (top, bottom) = (self.row_rect(0).y, self.row_rect(rows - 1).bottom());
+            let track = Rect::new(self.position.right() - EDGE_PADDING(), top, (EDGE_PADDING() / 2).max(1) as u32, (bottom - top) as u32);
+            let thumb_h = track.h * rows as i32 / devices.len() as i32;
+            let thumb_y = top + (track.h - thumb_h) * first as i32 / (devices.len() - rows) as i32;
+            ctx.draw_rect(track, BG_SHADED);
+            ctx.draw_rect(Rect::new(track.x, thumb_y, track.width(), thumb_h as u32), TXT_SUBTEXT);
+        }
+
+        for (rect, (action, label)) in self.actions.into_iter().zip(ACTIONS) {
+            let label = if action == DeviceAction::Remove && self.remove_pending() { "Sicher?" } else { label };
+            let color: Color = match action {
+                DeviceAction::Remove | DeviceAction::Disconnect => PB_EMPTY,
+                DeviceAction::Connect | DeviceAction::Trust => PB_FULLY,
+            };
+            ctx.draw_rect(rect, color);
+            let size = uihelper.font_owner.jb_medium_l.size_of_text(label);
+            ctx.draw_text(rect.x + (rect.w - size.one() as i32) / 2, rect.y + (rect.h - size.two() as i32) / 2,
+                          &uihelper.font_owner.jb_medium_l, label, BG_DARKEST, uihelper);
+        }
+    }
+
+    fn redraw(&self) -> Redraw {
+        match &self.remove_pending {
+            // Back to "Entfernen"
+            Some((_, since)) if self.remove_pending() => Redraw::At(*since + REMOVE_CONFIRM_TIMEOUT),
+            _ => Redraw::Idle
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.devices.changed() || self.transport.changed()
+    }
+
+    fn handle_event(&mut self, event: &Event) -> Handled {
+        match *event {
+            Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+                Keycode::Up => self.select(-1),
+                Keycode::Down => self.select(1),
+                Keycode::C => self.run(DeviceAction::Connect),
+                Keycode::X => self.run(DeviceAction::Disconnect),
+                Keycode::T => self.run(DeviceAction::Trust),
+                Keycode::Delete => self.run(DeviceAction::Remove),
+                _ => return Handled::No
+            }
+            _ => return Handled::No
+        }
+        Handled::Yes
+    }
+
+    // Swiping through the list, a tap selects a device or runs an action. Not on the press, a
+    // swipe starting on "Entfernen" would remove the device
+    fn handle_gesture(&mut self, gesture: &Gesture) -> Handled {
+        match *gesture {
+            Gesture::Tap(point) => self.handle_click(point),
+            Gesture::Swipe(Swipe::Up, _) => self.select(1),
+            Gesture::Swipe(Swipe::Down, _) => self.select(-1),
+            _ => return Handled::No
+        }
+        Handled::Yes
+    }
+
+    fn focusable(&self) -> bool {
+        true
+    }
+}
@@ -1,46 +1,362 @@
-use std::thread;
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::{MouseButton, MouseState};
 use crate::color::BG_DARKEST;
-use crate::info_widget::InfoWidget;
-use crate::ui_renderer::{init, UIContext, UIHelper};
-use crate::weather_widget::WeatherWidget;
+use crate::bluetooth::{change_volume, BluetoothController};
+use crate::device_widget::DeviceManagerWidget;
+use crate::config::CONFIG;
+use crate::gestures::{Gesture, GestureRecognizer, Swipe};
+use crate::history_widget::RecentlyPlayedWidget;
+use crate::media::{media_command, MediaCommand};
+use crate::page_manager::PageManager;
+use crate::pairing_widget::PairingOverlay;
+use crate::redraw::{is_wake_event, take_redraw_request, Redraw};
+use crate::state::Store;
+use crate::volume_widget::VolumeOverlay;
+use crate::ui_renderer::{init, pointer_position, Align, Drawable, Handled, Layout, Length, UIContext, UIHelper, USize, WindowMode, EDGE_PADDING};
+use crate::widget_registry::WidgetRegistry;
 
-pub fn video_main() -> Result<(), String> {
-    let (window, mut event_pump) = init()?;
-    let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Screen {
+    Main,
+    Devices,
+    History,
+}
+
+impl Screen {
+    /// In the order swipes and arrow keys page through them
+    pub const ALL: [Screen; 3] = [Screen::Main, Screen::History, Screen::Devices];
+
+    /// The screen `step` pages further, wrapping around
+    pub fn step(self, step: i32) -> Self {
+        let index = Self::ALL.iter().position(|screen| *screen == self).unwrap() as i32;
+        Self::ALL[(index + step).rem_euclid(Self::ALL.len() as i32) as usize]
+    }
+}
+
+impl FromStr for Screen {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "main" => Ok(Screen::Main),
+            "devices" => Ok(Screen::Devices),
+            "history" => Ok(Screen::History),
+            _ => Err(format!("Unknown screen: {value}"))
+        }
+    }
+}
+
+// Texture cache use is logged this often with UNYO_DEBUG set
+const TEXTURE_STATS_INTERVAL: Duration = Duration::from_secs(600);
+// `which` of mouse events SDL generates from touch (SDL_TOUCH_MOUSEID)
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
+
+// Slots of the screens and overlays, stacked. The pages of the main screen are laid out by their config
+fn screen_layout() -> Layout {
+    let volume = Layout::stack(vec![Layout::widget("volume").width(Length::Fraction(0.4)).height(Length::Fraction(0.12))])
+        .padding_sides(0, 0, 0, 4 * EDGE_PADDING())
+        .align(Align::Center, Align::End);
+
+    Layout::stack(vec![
+        Layout::widget("main"),
+        Layout::widget("devices"),
+        Layout::widget("history"),
+        volume,
+        Layout::widget("pairing").width(Length::Fraction(0.5)).height(Length::Fraction(0.5)),
+    ]).align(Align::Center, Align::Center)
+}
+
+/// All widgets: the screens and the overlays on top of them
+pub struct Dashboard {
+    pages: PageManager,
+    device_manager: DeviceManagerWidget,
+    recently_played: RecentlyPlayedWidget,
+    volume_overlay: VolumeOverlay,
+    pairing_overlay: PairingOverlay,
+    // Screen and index of the widget with the keyboard focus
+    focus: Option<(Screen, usize)>,
+    store: Arc<Store>,
+    // None when simulating
+    bluetooth: Option<Arc<BluetoothController<'static>>>,
+}
+
+impl Dashboard {
+    pub fn new(ui_size: USize, store: Arc<Store>, bluetooth: Option<Arc<BluetoothController<'static>>>) -> Self {
+        let placement = screen_layout().resolve(ui_size.to_rect(0, 0));
+        Self {
+            pages: PageManager::new(placement.rect("main"), &CONFIG().pages, &WidgetRegistry::builtin(), &store),
+            device_manager: DeviceManagerWidget::new(placement.rect("devices"), &store, bluetooth.clone()),
+            recently_played: RecentlyPlayedWidget::new(placement.rect("history")),
+            volume_overlay: VolumeOverlay::new(placement.rect("volume"), &store),
+            pairing_overlay: PairingOverlay::new(placement.rect("pairing"), store.clone()),
+            focus: None,
+            store,
+            bluetooth,
+        }
+    }
+
+    // Widgets of a screen in drawing order, the overlays are not part of it
+    fn widgets(&self, screen: Screen) -> Vec<&dyn Drawable> {
+        match screen {
+            Screen::Main => vec![&self.pages],
+            Screen::Devices => vec![&self.device_manager],
+            Screen::History => vec![&self.recently_played],
+        }
+    }
+
+    fn widgets_mut(&mut self, screen: Screen) -> Vec<&mut (dyn Drawable + 'static)> {
+        match screen {
+            Screen::Main => vec![&mut self.pages],
+            Screen::Devices => vec![&mut self.device_manager],
+            Screen::History => vec![&mut self.recently_played],
+        }
+    }
+
+    /// Draws a whole frame (without presenting it)
+    pub fn draw(&self, ui: &mut UIContext, uihelper: &UIHelper, screen: Screen) {
+        ui.clear(BG_DARKEST);
+        for widget in self.widgets(screen) {
+            ui.draw(widget, uihelper);
+        }
+        ui.draw(&self.volume_overlay, uihelper);
+        ui.draw(&self.pairing_overlay, uihelper);
+    }
+
+    pub fn redraw(&self, screen: Screen) -> Redraw {
+        self.widgets(screen).iter().map(|widget| widget.redraw()).min().unwrap_or(Redraw::Idle)
+            .min(self.volume_overlay.redraw()).min(self.pairing_overlay.redraw())
+    }
+
+    /// Whether a widget on `screen` shows store data that changed since the last frame
+    pub fn changed(&self, screen: Screen) -> bool {
+        self.widgets(screen).iter().any(|widget| widget.changed()) || self.volume_overlay.changed() || self.pairing_overlay.changed()
+    }
+
+    pub fn update(&mut self, dt: Duration, screen: Screen) {
+        for widget in self.widgets_mut(screen) {
+            widget.update(dt);
+        }
+        self.volume_overlay.update(dt);
+        self.pairing_overlay.update(dt);
+    }
+
+    fn set_focus(&mut self, focus: Option<(Screen, usize)>) {
+        if focus == self.focus {
+            return;
+        }
+        if let Some((screen, index)) = self.focus {
+            self.widgets_mut(screen)[index].set_focused(false);
+        }
+        if let Some((screen, index)) = focus {
+            self.widgets_mut(screen)[index].set_focused(true);
+        }
+        self.focus = focus;
+    }
+
+    /// Moves the focus to the next widget of `screen` that takes it, the first one if `screen`
+    /// doesn't have it yet
+    pub fn focus_next(&mut self, screen: Screen) {
+        let widgets = self.widgets(screen);
+        let after = match self.focus {
+            Some((focused, index)) if focused == screen => index + 1,
+            _ => 0
+        };
+        let next = (0..widgets.len()).map(|i| (after + i) % widgets.len()).find(|i| widgets[*i].focusable());
+        self.set_focus(next.map(|index| (screen, index)));
+    }
+
+    /// Called when `screen` is shown, gives the focus to its first widget that takes it
+    pub fn reset_focus(&mut self, screen: Screen) {
+        self.set_focus(None);
+        self.focus_next(screen);
+    }
+
+    /// Gives a gesture to the overlays, then to the topmost widget where it started
+    pub fn handle_gesture(&mut self, gesture: &Gesture, screen: Screen) -> Handled {
+        if self.pairing_overlay.handle_gesture(gesture) == Handled::Yes || self.volume_overlay.handle_gesture(gesture) == Handled::Yes {
+            return Handled::Yes;
+        }
+        let point = gesture.position();
+        let Some(index) = self.widgets(screen).iter().rposition(|widget| widget.hit_test(point)) else { return Handled::No };
+        self.widgets_mut(screen)[index].handle_gesture(gesture)
+    }
+
+    fn change_volume(&self, delta: i32) {
+        change_volume(&self.store, self.bluetooth.as_ref(), delta);
+    }
+
+    fn media_command(&self, command: MediaCommand) {
+        media_command(self.store.playback.get().as_ref(), command);
+    }
+
+    /// Routes input: the overlays first, then pointer events to the topmost widget under the
+    /// pointer (which gets the focus) and everything else to the focused widget
+    pub fn handle_event(&mut self, event: &Event, screen: Screen) -> Handled {
+        if self.pairing_overlay.handle_event(event) == Handled::Yes || self.volume_overlay.handle_event(event) == Handled::Yes {
+            return Handled::Yes;
+        }
+
+        if let Some(point) = pointer_position(event) {
+            let Some(index) = self.widgets(screen).iter().rposition(|widget| widget.hit_test(point)) else { return Handled::No };
+            if matches!(event, Event::MouseButtonDown { .. }) && self.widgets(screen)[index].focusable() {
+                self.set_focus(Some((screen, index)));
+            }
+            return self.widgets_mut(screen)[index].handle_event(event);
+        }
+
+        match self.focus {
+            Some((focused, index)) if focused == screen => self.widgets_mut(screen)[index].handle_event(event),
+            _ => Handled::No
+        }
+    }
+}
+
+// Touch as mouse events in pixels. SDL's own touch mouse events are dropped, they would
+// arrive twice otherwise
+fn touch_as_mouse(event: Event, ui_size: USize) -> Option<Event> {
+    let (w, h) = (ui_size.one() as f32, ui_size.two() as f32);
+    match event {
+        Event::MouseButtonDown { which, .. } | Event::MouseButtonUp { which, .. } | Event::MouseMotion { which, .. } if which == TOUCH_MOUSE_ID => None,
+        Event::FingerDown { timestamp, x, y, .. } => Some(Event::MouseButtonDown {
+            timestamp, window_id: 0, which: TOUCH_MOUSE_ID, mouse_btn: MouseButton::Left, clicks: 1, x: (x * w) as i32, y: (y * h) as i32
+        }),
+        Event::FingerUp { timestamp, x, y, .. } => Some(Event::MouseButtonUp {
+            timestamp, window_id: 0, which: TOUCH_MOUSE_ID, mouse_btn: MouseButton::Left, clicks: 1, x: (x * w) as i32, y: (y * h) as i32
+        }),
+        Event::FingerMotion { timestamp, x, y, dx, dy, .. } => Some(Event::MouseMotion {
+            timestamp, window_id: 0, which: TOUCH_MOUSE_ID, mousestate: MouseState::from_sdl_state(1),
+            x: (x * w) as i32, y: (y * h) as i32, xrel: (dx * w) as i32, yrel: (dy * h) as i32
+        }),
+        event => Some(event)
+    }
+}
+
+// Gestures no widget wanted: swipes page through the screens or change the volume, a long-press
+// opens the device settings. Returns the screen to show
+fn handle_gesture(dashboard: &Dashboard, gesture: Gesture, screen: Screen) -> Screen {
+    match gesture {
+        Gesture::Swipe(Swipe::Left, _) => return screen.step(1),
+        Gesture::Swipe(Swipe::Right, _) => return screen.step(-1),
+        Gesture::Swipe(Swipe::Up, _) => dashboard.change_volume(1),
+        Gesture::Swipe(Swipe::Down, _) => dashboard.change_volume(-1),
+        Gesture::LongPress(_) => return Screen::Devices,
+        Gesture::Tap(_) => {}
+    }
+    screen
+}
+
+pub fn video_main(mode: WindowMode, store: Arc<Store>, bluetooth: Option<Arc<BluetoothController<'static>>>) -> Result<(), String> {
+    let (window, mut event_pump) = init(mode)?;
+    let canvas = window.into_canvas().present_vsync().build().map_err(|e| e.to_string())?;
     let texture_creator = canvas.texture_creator();
-    let uihelper = UIHelper::new(&texture_creator);
+    let uihelper = UIHelper::new(&texture_creator)?;
     
     let mut ui = UIContext::new(canvas).unwrap();
     let ui_size = ui.size();
 
     let exit_time = Instant::now() + Duration::from_secs(3600);
 
-    let weather_widget = WeatherWidget::new(&ui_size);
-    let info_widget = InfoWidget::new(&ui_size);
+    let mut dashboard = Dashboard::new(ui_size, store, bluetooth);
+    let mut gestures = GestureRecognizer::new();
+    let mut screen = Screen::Main;
+    dashboard.reset_focus(screen);
+    let mut next_stats = std::env::var_os("UNYO_DEBUG").map(|_| Instant::now() + TEXTURE_STATS_INTERVAL);
+    let mut last_update = Instant::now();
+    // First frame right away
+    let mut redraw = Redraw::Animating;
 
     'running: loop {
         if Instant::now() > exit_time {
             break 'running;
         }
-        
-        for event in event_pump.poll_iter() {
-            if let Event::Quit { .. } = event {
-                break 'running;
+        if next_stats.is_some_and(|at| Instant::now() > at) {
+            let stats = uihelper.texture_cache_stats();
+            println!("Texture cache: {} entries, {} KiB, {} hits, {} misses", stats.entries, stats.bytes / 1024, stats.hits, stats.misses);
+            next_stats = Some(Instant::now() + TEXTURE_STATS_INTERVAL);
+        }
+
+        // Sleep until a widget is due, input arrives or a data source wakes us up
+        let mut events = vec![];
+        let wait_until = match redraw {
+            Redraw::Animating => None,
+            Redraw::At(at) => Some(at.min(exit_time)),
+            Redraw::Idle => Some(exit_time),
+        }.map(|at| [gestures.deadline(), next_stats].into_iter().flatten().fold(at, Instant::min));
+        if let Some(wait_until) = wait_until {
+            let timeout = wait_until.saturating_duration_since(Instant::now()).as_micros().div_ceil(1000) as u32;
+            if timeout > 0 {
+                events.extend(event_pump.wait_event_timeout(timeout));
             }
         }
-        
-        ui.clear(BG_DARKEST);
-        ui.draw(&info_widget, &uihelper);
-        ui.draw(&weather_widget, &uihelper);
-        ui.render();
-        
-        // Wait until next second
-        let next_tick = Instant::now() + Duration::from_millis(200);
-        while Instant::now() < next_tick {
-            thread::sleep(Duration::from_millis(10));
+        events.extend(event_pump.poll_iter());
+
+        // Anything but a wake-up is input, which may change what is shown
+        let mut input = false;
+        let shown = screen;
+        for event in events.into_iter().filter_map(|event| touch_as_mouse(event, ui_size)) {
+            if is_wake_event(&event) {
+                continue;
+            }
+            input = true;
+            // The event that ends a gesture is used up by it, the screen may have changed under it
+            if let Some(gesture) = gestures.handle_event(&event) {
+                if dashboard.handle_gesture(&gesture, screen) == Handled::No {
+                    screen = handle_gesture(&dashboard, gesture, screen);
+                }
+                continue;
+            }
+            if dashboard.handle_event(&event, screen) == Handled::Yes {
+                continue;
+            }
+
+            // Whatever no widget wanted
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => screen = Screen::Main,
+                Event::KeyDown { keycode: Some(Keycode::Left), .. } => screen = screen.step(-1),
+                Event::KeyDown { keycode: Some(Keycode::Right), .. } => screen = screen.step(1),
+                Event::KeyDown { keycode: Some(Keycode::Tab), .. } => dashboard.focus_next(screen),
+                Event::KeyDown { keycode: Some(Keycode::D), .. } => screen = if screen == Screen::Devices { Screen::Main } else { Screen::Devices },
+                Event::KeyDown { keycode: Some(Keycode::H), .. } => screen = if screen == Screen::History { Screen::Main } else { Screen::History },
+                Event::KeyDown { keycode: Some(Keycode::Space | Keycode::AudioPlay), .. } => dashboard.media_command(MediaCommand::PlayPause),
+                Event::KeyDown { keycode: Some(Keycode::N | Keycode::AudioNext), .. } => dashboard.media_command(MediaCommand::Next),
+                Event::KeyDown { keycode: Some(Keycode::B | Keycode::AudioPrev), .. } => dashboard.media_command(MediaCommand::Previous),
+                Event::KeyDown { keycode: Some(Keycode::Plus | Keycode::KpPlus | Keycode::VolumeUp), .. } => dashboard.change_volume(1),
+                Event::KeyDown { keycode: Some(Keycode::Minus | Keycode::KpMinus | Keycode::VolumeDown), .. } => dashboard.change_volume(-1),
+                _ => {}
+            }
+        }
+        // Held long enough
+        if let Some(gesture) = gestures.poll() {
+            input = true;
+            if dashboard.handle_gesture(&gesture, screen) == Handled::No {
+                screen = handle_gesture(&dashboard, gesture, screen);
+            }
+        }
+        if screen != shown {
+            dashboard.reset_focus(screen);
+        }
+
+        let now = Instant::now();
+        dashboard.update(now - last_update, screen);
+        last_update = now;
+
+        // Data sources wake us for every change, only draw if it is on screen
+        let due = match redraw {
+            Redraw::Animating => true,
+            Redraw::At(at) => now >= at,
+            Redraw::Idle => false,
+        };
+        if input || due || take_redraw_request() || dashboard.changed(screen) {
+            dashboard.draw(&mut ui, &uihelper, screen);
+            // Blocks until vsync
+            ui.render();
         }
+        redraw = dashboard.redraw(screen);
     };
     Ok(())
 }
\ No newline at end of file
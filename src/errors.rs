@@ -1,10 +1,13 @@
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum UnyoError {
-    UiLoadFont,
+    UiLoadFont(String),
     ApiReq(String, String),
     ApiReqFmt(String, String),
-    ApiWeatherFmt
+    ApiWeatherFmt,
+    DBus(String),
+    Mpd(String),
+    AirPlay(String)
 }
 
 pub type UnyoResult<T> = Result<T, UnyoError>;
\ No newline at end of file
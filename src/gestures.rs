@@ -0,0 +1,190 @@
+use std::time::{Duration, Instant};
+use sdl2::event::Event;
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Point;
+use crate::ui_renderer::EDGE_PADDING;
+
+const LONG_PRESS_DURATION: Duration = Duration::from_millis(600);
+// In EDGE_PADDINGs: how far a tap / long-press may move, how far a swipe has to
+const TAP_SLOP: i32 = 3;
+const SWIPE_DISTANCE: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Swipe {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    Tap(Point),
+    LongPress(Point),
+    // Direction and where it started
+    Swipe(Swipe, Point),
+}
+
+impl Gesture {
+    /// Where it started, used for hit-testing
+    pub fn position(&self) -> Point {
+        match *self {
+            Gesture::Tap(point) | Gesture::LongPress(point) | Gesture::Swipe(_, point) => point
+        }
+    }
+}
+
+struct Press {
+    start: Point,
+    since: Instant,
+    // Too far for a tap or long-press
+    moved: bool,
+    long_pressed: bool,
+}
+
+/// Turns pointer events (touch arrives as mouse events) into taps, long-presses and swipes
+#[derive(Default)]
+pub struct GestureRecognizer {
+    press: Option<Press>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self {press: None}
+    }
+
+    pub fn handle_event(&mut self, event: &Event) -> Option<Gesture> {
+        match *event {
+            Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                self.press = Some(Press {start: Point::new(x, y), since: Instant::now(), moved: false, long_pressed: false});
+                None
+            }
+            Event::MouseMotion { x, y, .. } => {
+                let press = self.press.as_mut()?;
+                let (dx, dy) = (x - press.start.x, y - press.start.y);
+                press.moved |= dx.abs().max(dy.abs()) > TAP_SLOP * EDGE_PADDING();
+                None
+            }
+            Event::MouseButtonUp { mouse_btn: MouseButton::Left, x, y, .. } => {
+                let press = self.press.take()?;
+                if press.long_pressed {
+                    return None;
+                }
+                let (dx, dy) = (x - press.start.x, y - press.start.y);
+                if dx.abs().max(dy.abs()) >= SWIPE_DISTANCE * EDGE_PADDING() {
+                    let swipe = if dx.abs() > dy.abs() {
+                        if dx < 0 { Swipe::Left } else { Swipe::Right }
+                    } else if dy < 0 { Swipe::Up } else { Swipe::Down };
+                    Some(Gesture::Swipe(swipe, press.start))
+                } else if !press.moved && dx.abs().max(dy.abs()) <= TAP_SLOP * EDGE_PADDING() {
+                    Some(Gesture::Tap(press.start))
+                } else {
+                    None
+                }
+            }
+            _ => None
+        }
+    }
+
+    /// A long-press, once the pointer has been held still long enough
+    pub fn poll(&mut self) -> Option<Gesture> {
+        let press = self.press.as_mut()?;
+        if press.moved || press.long_pressed || press.since.elapsed() < LONG_PRESS_DURATION {
+            return None;
+        }
+        press.long_pressed = true;
+        Some(Gesture::LongPress(press.start))
+    }
+
+    /// When `poll` will report a long-press if nothing happens until then
+    pub fn deadline(&self) -> Option<Instant> {
+        self.press.as_ref().filter(|press| !press.moved && !press.long_pressed).map(|press| press.since + LONG_PRESS_DURATION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sdl2::mouse::MouseState;
+    use crate::ui_renderer::on_screen_height;
+    use super::*;
+
+    fn down(x: i32, y: i32) -> Event {
+        Event::MouseButtonDown {timestamp: 0, window_id: 0, which: 0, mouse_btn: MouseButton::Left, clicks: 1, x, y}
+    }
+
+    fn motion(x: i32, y: i32) -> Event {
+        Event::MouseMotion {timestamp: 0, window_id: 0, which: 0, mousestate: MouseState::from_sdl_state(1), x, y, xrel: 0, yrel: 0}
+    }
+
+    fn up(x: i32, y: i32) -> Event {
+        Event::MouseButtonUp {timestamp: 0, window_id: 0, which: 0, mouse_btn: MouseButton::Left, clicks: 1, x, y}
+    }
+
+    // Press at (500, 500), move through `path` and release at its end, on a 1080p screen
+    // (EDGE_PADDING 10: tap slop 30 px, swipe distance 100 px)
+    fn gesture(path: &[(i32, i32)]) -> Option<Gesture> {
+        on_screen_height(1080, || {
+            let mut recognizer = GestureRecognizer::new();
+            assert_eq!(recognizer.handle_event(&down(500, 500)), None);
+            for (x, y) in path {
+                assert_eq!(recognizer.handle_event(&motion(*x, *y)), None);
+            }
+            let (x, y) = path.last().copied().unwrap_or((500, 500));
+            recognizer.handle_event(&up(x, y))
+        })
+    }
+
+    #[test]
+    fn tap() {
+        let start = Point::new(500, 500);
+        assert_eq!(gesture(&[]), Some(Gesture::Tap(start)));
+        assert_eq!(gesture(&[(520, 490), (525, 510)]), Some(Gesture::Tap(start)));
+    }
+
+    #[test]
+    fn past_the_slop_but_no_swipe() {
+        assert_eq!(gesture(&[(550, 500)]), None);
+        // Moved away and back
+        assert_eq!(gesture(&[(560, 500), (500, 500)]), None);
+    }
+
+    #[test]
+    fn swipes() {
+        let start = Point::new(500, 500);
+        assert_eq!(gesture(&[(380, 510)]), Some(Gesture::Swipe(Swipe::Left, start)));
+        assert_eq!(gesture(&[(620, 490)]), Some(Gesture::Swipe(Swipe::Right, start)));
+        assert_eq!(gesture(&[(510, 380)]), Some(Gesture::Swipe(Swipe::Up, start)));
+        assert_eq!(gesture(&[(490, 620)]), Some(Gesture::Swipe(Swipe::Down, start)));
+    }
+
+    #[test]
+    fn long_press_suppresses_tap() {
+        on_screen_height(1080, || {
+            let mut recognizer = GestureRecognizer::new();
+            recognizer.handle_event(&down(500, 500));
+            assert_eq!(recognizer.poll(), None);
+            let deadline = recognizer.deadline().unwrap();
+            // Held long enough
+            recognizer.press.as_mut().unwrap().since -= LONG_PRESS_DURATION;
+            assert!(recognizer.deadline().unwrap() < deadline);
+            assert_eq!(recognizer.poll(), Some(Gesture::LongPress(Point::new(500, 500))));
+            assert_eq!(recognizer.poll(), None);
+            assert_eq!(recognizer.deadline(), None);
+            assert_eq!(recognizer.handle_event(&up(500, 500)), None);
+        });
+    }
+
+    #[test]
+    fn no_deadline_after_moving() {
+        on_screen_height(1080, || {
+            let mut recognizer = GestureRecognizer::new();
+            assert_eq!(recognizer.deadline(), None);
+            recognizer.handle_event(&down(500, 500));
+            assert!(recognizer.deadline().is_some());
+            recognizer.handle_event(&motion(560, 500));
+            assert_eq!(recognizer.deadline(), None);
+            recognizer.press.as_mut().unwrap().since -= LONG_PRESS_DURATION;
+            assert_eq!(recognizer.poll(), None);
+        });
+    }
+}
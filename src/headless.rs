@@ -0,0 +1,39 @@
+use std::path::Path;
+use std::sync::Arc;
+use sdl2::image::SaveSurface;
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
+use crate::color::BG_DARKEST;
+use crate::display::{Dashboard, Screen};
+use crate::state::Store;
+use crate::ui_renderer::{init_headless, Drawable, UIContext, UIHelper};
+
+// Offscreen canvas of `size`, scaled for a screen `screen_height` px high
+fn render(size: (u32, u32), screen_height: u32, draw: impl FnOnce(&mut UIContext, &UIHelper)) -> Result<Surface<'static>, String> {
+    let window = init_headless(size, screen_height)?;
+    let canvas = window.into_canvas().software().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let uihelper = UIHelper::new(&texture_creator)?;
+
+    let mut ui = UIContext::new(canvas).map_err(|e| format!("{e:?}"))?;
+    draw(&mut ui, &uihelper);
+    ui.read_surface()
+}
+
+/// Draws one frame of `screen` at `size` without a display, from whatever data is in `store`
+pub fn render_frame(size: (u32, u32), screen: Screen, store: &Arc<Store>) -> Result<Surface<'static>, String> {
+    render(size, size.1, |ui, uihelper| Dashboard::new(ui.size(), store.clone(), None).draw(ui, uihelper, screen))
+}
+
+/// Draws a single widget filling `size`, as it would look on a screen `screen_height` px high
+pub fn render_widget(size: (u32, u32), screen_height: u32, widget: impl FnOnce(Rect) -> Box<dyn Drawable>) -> Result<Surface<'static>, String> {
+    render(size, screen_height, |ui, uihelper| {
+        ui.clear(BG_DARKEST);
+        ui.draw(widget(ui.size().to_rect(0, 0)).as_ref(), uihelper);
+    })
+}
+
+/// `render_frame` saved as PNG
+pub fn screenshot(path: &Path, size: (u32, u32), screen: Screen, store: &Arc<Store>) -> Result<(), String> {
+    render_frame(size, screen, store)?.save(path)
+}
@@ -0,0 +1,292 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use serde_json::{json, Value};
+use crate::config::CONFIG;
+use crate::media::{PlaybackData, PlaybackState};
+use crate::redraw::request_redraw;
+use crate::scrobbler;
+
+const HISTORY_FILE: &str = "history.jsonl";
+// A track counts once it played for half its length or 4 minutes (ListenBrainz / Last.fm rules)
+const MAX_THRESHOLD: u32 = 240_000;
+const MIN_DURATION: u32 = 30_000;
+// Sources drop out for a poll now and then, a track that comes back this soon is the same listen
+const LISTENING_GRACE: Duration = Duration::from_secs(10);
+
+static _HISTORY: LazyLock<Mutex<Vec<HistoryEntry>>> = LazyLock::new(|| {Mutex::new(load_history())});
+static _LISTENING: Mutex<ListenTracker> = Mutex::new(ListenTracker::new());
+// Off when simulating, fake tracks are neither saved nor scrobbled
+static _RECORDING: AtomicBool = AtomicBool::new(true);
+
+/// Most recent tracks, newest last
+#[allow(non_snake_case)]
+pub fn HISTORY<'a>() -> MutexGuard<'a, Vec<HistoryEntry>> {
+    _HISTORY.lock().unwrap()
+}
+
+/// A track that was listened to
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub title: String,
+    pub artist: String,
+    pub source: String,
+    pub device: Option<String>,
+    // Unix time at which the track started
+    pub played_at: i64,
+    // ms, 0 if unknown
+    pub duration: u32,
+}
+
+impl HistoryEntry {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "title": self.title,
+            "artist": self.artist,
+            "source": self.source,
+            "device": self.device,
+            "played_at": self.played_at,
+            "duration": self.duration,
+        })
+    }
+
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let head = value.as_object()?;
+        Some(Self {
+            title: head.get("title")?.as_str()?.to_string(),
+            artist: head.get("artist")?.as_str()?.to_string(),
+            source: head.get("source").and_then(|v| v.as_str()).unwrap_or_default().to_string(),
+            device: head.get("device").and_then(|v| v.as_str()).map(str::to_string),
+            played_at: head.get("played_at")?.as_i64()?,
+            duration: head.get("duration").and_then(|v| v.as_u64()).unwrap_or(0) as u32,
+        })
+    }
+}
+
+/// Reads JSON lines, skipping broken ones
+pub fn read_entries(content: &str) -> Vec<HistoryEntry> {
+    content.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter_map(|value| HistoryEntry::from_json(&value))
+        .collect()
+}
+
+fn load_history() -> Vec<HistoryEntry> {
+    let content = std::fs::read_to_string(CONFIG().state_dir.join(HISTORY_FILE)).unwrap_or_default();
+    let mut entries = read_entries(&content);
+    let keep = entries.len().saturating_sub(CONFIG().history.recent as usize);
+    entries.drain(..keep);
+    entries
+}
+
+fn append_history(entry: &HistoryEntry) -> std::io::Result<()> {
+    let state_dir = &CONFIG().state_dir;
+    std::fs::create_dir_all(state_dir)?;
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(state_dir.join(HISTORY_FILE))?;
+    writeln!(file, "{}", entry.to_json())
+}
+
+/// The track being played and how long it was actually listened to
+struct Listening {
+    track: PlaybackData,
+    started_at: i64,
+    listened: u32,
+    last_update: Instant,
+    recorded: bool,
+}
+
+impl Listening {
+    fn entry(&self) -> HistoryEntry {
+        HistoryEntry {
+            title: self.track.title.clone(),
+            artist: self.track.artist.clone(),
+            source: self.track.source.to_string(),
+            device: self.track.device.as_ref().map(|d| d.name.clone()),
+            played_at: self.started_at,
+            duration: self.track.duration,
+        }
+    }
+}
+
+fn threshold(duration: u32) -> u32 {
+    if duration == 0 { MAX_THRESHOLD } else { (duration / 2).min(MAX_THRESHOLD) }
+}
+
+/// Follows the playback updates and tells when a track was listened to long enough
+pub struct ListenTracker {
+    listening: Option<Listening>,
+}
+
+impl Default for ListenTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ListenTracker {
+    pub const fn new() -> Self {
+        Self {listening: None}
+    }
+
+    /// Takes the playback update at `now`, returns the track once it played long enough
+    pub fn observe(&mut self, data: Option<&PlaybackData>, now: Instant) -> Option<HistoryEntry> {
+        let listening = &mut self.listening;
+        let Some(data) = data else {
+            if listening.as_ref().is_some_and(|current| now - current.last_update > LISTENING_GRACE) {
+                *listening = None;
+            }
+            return None;
+        };
+
+        match listening {
+            Some(current) if current.track.is_same_track(data) && now - current.last_update <= LISTENING_GRACE => {
+                // The time since the last update was spent as reported then
+                if current.track.playback_state == PlaybackState::Playing {
+                    current.listened += (now - current.last_update).as_millis() as u32;
+                }
+                current.last_update = now;
+                current.track = data.clone();
+            }
+            _ => {
+                *listening = Some(Listening {
+                    track: data.clone(),
+                    started_at: chrono::Local::now().timestamp() - (data.position() / 1000) as i64,
+                    listened: 0,
+                    last_update: now,
+                    recorded: false,
+                });
+            }
+        }
+
+        let current = listening.as_mut().unwrap();
+        let long_enough = current.track.duration == 0 || current.track.duration >= MIN_DURATION;
+        if current.recorded || !long_enough || current.listened < threshold(current.track.duration) {
+            return None;
+        }
+        current.recorded = true;
+        Some(current.entry())
+    }
+}
+
+fn record(entry: HistoryEntry) {
+    if let Err(e) = append_history(&entry) {
+        eprintln!("Failed to save history: {e}");
+    }
+    if CONFIG().history.scrobbler.is_some() {
+        scrobbler::enqueue(&entry);
+    }
+
+    let mut history = HISTORY();
+    history.push(entry);
+    let keep = history.len().saturating_sub(CONFIG().history.recent as usize);
+    history.drain(..keep);
+    request_redraw();
+}
+
+pub fn set_recording(recording: bool) {
+    _RECORDING.store(recording, Ordering::Relaxed);
+}
+
+/// Called with every playback update, records the track once it played long enough
+pub fn observe(data: Option<&PlaybackData>) {
+    if !_RECORDING.load(Ordering::Relaxed) {
+        return;
+    }
+    let entry = _LISTENING.lock().unwrap().observe(data, Instant::now());
+    if let Some(entry) = entry {
+        record(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: Duration = Duration::from_secs(5);
+
+    fn track(title: &str, state: PlaybackState, duration: u32) -> PlaybackData {
+        PlaybackData::new(title.to_string(), "Queen".to_string(), state, 0, duration)
+    }
+
+    // Updates every 5 s from `start` for `until`, returns how long after `start` the track was recorded
+    fn play(tracker: &mut ListenTracker, data: &PlaybackData, start: Instant, until: u64) -> Option<(u64, HistoryEntry)> {
+        (0..=until).step_by(STEP.as_secs() as usize)
+            .find_map(|at| tracker.observe(Some(data), start + Duration::from_secs(at)).map(|entry| (at, entry)))
+    }
+
+    fn recorded_after(tracker: &mut ListenTracker, data: &PlaybackData, start: Instant, until: u64) -> Option<u64> {
+        play(tracker, data, start, until).map(|(at, _)| at)
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(threshold(0), MAX_THRESHOLD);
+        assert_eq!(threshold(200_000), 100_000);
+        assert_eq!(threshold(600_000), MAX_THRESHOLD);
+    }
+
+    #[test]
+    fn recorded_once_after_half() {
+        let mut tracker = ListenTracker::new();
+        let data = track("a", PlaybackState::Playing, 200_000);
+        let start = Instant::now();
+        assert_eq!(recorded_after(&mut tracker, &data, start, 300), Some(100));
+        // Not again
+        assert_eq!(recorded_after(&mut tracker, &data, start + Duration::from_secs(105), 100), None);
+    }
+
+    #[test]
+    fn long_tracks_after_four_minutes() {
+        let mut tracker = ListenTracker::new();
+        let data = track("a", PlaybackState::Playing, 3_600_000);
+        assert_eq!(recorded_after(&mut tracker, &data, Instant::now(), 600), Some(240));
+    }
+
+    #[test]
+    fn short_tracks_are_not_recorded() {
+        let mut tracker = ListenTracker::new();
+        let data = track("a", PlaybackState::Playing, MIN_DURATION - 1);
+        assert_eq!(recorded_after(&mut tracker, &data, Instant::now(), 60), None);
+    }
+
+    #[test]
+    fn paused_time_does_not_count() {
+        let mut tracker = ListenTracker::new();
+        let start = Instant::now();
+        let paused = track("a", PlaybackState::Paused, 200_000);
+        assert_eq!(recorded_after(&mut tracker, &paused, start, 300), None);
+        let playing = track("a", PlaybackState::Playing, 200_000);
+        assert_eq!(recorded_after(&mut tracker, &playing, start + Duration::from_secs(305), 300), Some(100));
+    }
+
+    #[test]
+    fn grace_period() {
+        let start = Instant::now();
+        let at = |s: u64| start + Duration::from_secs(s);
+        let data = track("a", PlaybackState::Playing, 200_000);
+
+        // Gone for a moment, the same listen goes on and the gap counts
+        let mut tracker = ListenTracker::new();
+        assert_eq!(recorded_after(&mut tracker, &data, at(0), 50), None);
+        assert!(tracker.observe(None, at(55)).is_none());
+        assert!(tracker.observe(Some(&data), at(58)).is_none());
+        assert_eq!(recorded_after(&mut tracker, &data, at(60), 100), Some(40));
+
+        // Gone for longer, it starts over
+        let mut tracker = ListenTracker::new();
+        assert_eq!(recorded_after(&mut tracker, &data, at(0), 90), None);
+        tracker.observe(None, at(95));
+        tracker.observe(None, at(101));
+        assert_eq!(recorded_after(&mut tracker, &data, at(102), 300), Some(100));
+    }
+
+    #[test]
+    fn another_track_starts_over() {
+        let start = Instant::now();
+        let mut tracker = ListenTracker::new();
+        assert_eq!(recorded_after(&mut tracker, &track("a", PlaybackState::Playing, 200_000), start, 90), None);
+        let (at, entry) = play(&mut tracker, &track("b", PlaybackState::Playing, 200_000), start + Duration::from_secs(95), 300).unwrap();
+        assert_eq!((at, entry.title.as_str()), (100, "b"));
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::{Local, TimeZone};
+use sdl2::rect::Rect;
+use crate::color::{BG_DARKEST, BG_SHADED, TXT_DEFAULT, TXT_SUBTEXT};
+use crate::history::HISTORY;
+use crate::ui_renderer::{Drawable, UIContext, UIHelper, EDGE_PADDING};
+
+/// The last tracks that were listened to, newest first
+pub struct RecentlyPlayedWidget {
+    position: Rect,
+}
+
+impl RecentlyPlayedWidget {
+    pub fn new(position: Rect) -> Self {
+        Self {position}
+    }
+
+    fn row_rect(&self, index: usize) -> Rect {
+        let h = 6 * EDGE_PADDING();
+        let y = self.position.y + 10 * EDGE_PADDING() + index as i32 * (h + EDGE_PADDING());
+        Rect::new(self.position.x + 2 * EDGE_PADDING(), y, (self.position.w - 4 * EDGE_PADDING()) as u32, h as u32)
+    }
+}
+
+impl Drawable for RecentlyPlayedWidget {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        let xp = self.position.x + 2 * EDGE_PADDING();
+        ctx.draw_rect(self.position, BG_DARKEST);
+        ctx.draw_text(xp, self.position.y + 2 * EDGE_PADDING(), &uihelper.font_owner.jb_medium_l, "ZULETZT GEHÖRT", TXT_DEFAULT, uihelper);
+
+        let history = HISTORY();
+        if history.is_empty() {
+            ctx.draw_text(xp, self.row_rect(0).y, &uihelper.font_owner.jb_medium_m, "Noch nichts gehört", TXT_SUBTEXT, uihelper);
+            return;
+        }
+
+        let text_h = uihelper.font_owner.jb_medium_m.char_dim().two() as i32;
+        for (i, entry) in history.iter().rev().enumerate() {
+            let row = self.row_rect(i);
+            if row.bottom() > self.position.bottom() - 2 * EDGE_PADDING() {
+                break;
+            }
+            ctx.draw_rect(row, BG_SHADED);
+
+            let y = row.y + (row.h - text_h) / 2;
+            let time = Local.timestamp_opt(entry.played_at, 0).single()
+                .map(|t| t.format("%d.%m. %H:%M").to_string())
+                .unwrap_or_default();
+            let (x, _) = ctx.draw_text(row.x + 2 * EDGE_PADDING(), y, &uihelper.font_owner.jb_medium_m, time.as_str(), TXT_SUBTEXT, uihelper);
+
+            // Device on the right, the track gets what is left
+            let device = entry.device.clone().unwrap_or(entry.source.clone());
+            let device_w = if device.is_empty() { 0 } else { uihelper.font_owner.jb_medium_m.size_of_text(device.as_str()).one() as i32 };
+            if device_w != 0 {
+                ctx.draw_text(row.right() - 2 * EDGE_PADDING() - device_w, y, &uihelper.font_owner.jb_medium_m, device.as_str(), TXT_SUBTEXT, uihelper);
+            }
+
+            let track = format!("{} - {}", entry.artist, entry.title);
+            let x = x + 3 * EDGE_PADDING();
+            let width = (row.right() - 5 * EDGE_PADDING() - device_w - x).max(0) as u32;
+            ctx.draw_text_clipped(x, y, width, 0, &uihelper.font_owner.jb_medium_m, track.as_str(), TXT_DEFAULT, uihelper);
+        }
+    }
+}
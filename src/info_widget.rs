@@ -1,13 +1,42 @@
+use std::cell::Cell;
 use chrono::{Datelike, Weekday};
 use sdl2::rect::{Point, Rect};
-use crate::bluetooth::{BLUETOOTH_DATA};
-use crate::{fraction};
-use crate::color::{BG_SHADED, PB_EMPTY, PB_FULLY, TXT_DEFAULT, TXT_SUBTEXT};
-use crate::ui_renderer::{Drawable, UIContext, UIHelper, USize, EDGE_PADDING};
-use crate::wifi_api::WIFI_STRENGTH;
+use crate::gestures::Gesture;
+use crate::media::{media_command, MediaCommand, PlaybackData, PlaybackState};
+use crate::clock;
+use crate::config::CONFIG;
+use crate::color::{BG_SHADED, BG_TINTED, PB_EMPTY, PB_FULLY, TXT_DEFAULT, TXT_SUBTEXT};
+use crate::redraw::{next_second, Redraw};
+use crate::ui_renderer::{Align, Drawable, Handled, Layout, Length, Marquee, UIContext, UIHelper, EDGE_PADDING};
+use crate::spectrum_widget::SpectrumBars;
+use crate::state::{Store, Subscription};
+use crate::wifi_api::WifiSignalBars;
 
 pub struct InfoWidget {
-    position: Rect
+    position: Rect,
+    title: Marquee,
+    artist: Marquee,
+    spectrum: Option<SpectrumBars>,
+    playback: Subscription<Option<PlaybackData>>,
+    wifi: Subscription<WifiSignalBars>,
+    // Cover, title and progress of the track as last drawn, tapping it plays / pauses
+    track_area: Cell<Option<Rect>>,
+}
+
+fn draw_state_glyph(ctx: &mut UIContext, x: i32, y: i32, size: i32, state: PlaybackState) {
+    match state {
+        PlaybackState::Playing => {
+            ctx.draw_polygon(vec![(x as i16, y as i16), (x as i16, (y + size) as i16), ((x + size) as i16, (y + size / 2) as i16)], TXT_SUBTEXT, true);
+        }
+        PlaybackState::Paused => {
+            let bar = size / 3;
+            ctx.draw_rect(Rect::new(x, y, bar as u32, size as u32), TXT_SUBTEXT);
+            ctx.draw_rect(Rect::new(x + size - bar, y, bar as u32, size as u32), TXT_SUBTEXT);
+        }
+        PlaybackState::Stopped => {
+            ctx.draw_rect(Rect::new(x, y, size as u32, size as u32), TXT_SUBTEXT);
+        }
+    }
 }
 
 fn format_time(seconds: u32) -> String {
@@ -18,12 +47,18 @@ fn format_time(seconds: u32) -> String {
 
 
 impl InfoWidget {
-    pub fn new(screen_size: &USize) -> Self {
-        Self { position: screen_size.scale_1_2(fraction(5, 9), 0.5).to_rect(EDGE_PADDING(), EDGE_PADDING()) }
+    pub fn new(position: Rect, show_spectrum: bool, store: &Store) -> Self {
+        let spectrum = CONFIG().spectrum.capture.as_ref().filter(|_| show_spectrum).map(|_| {
+            let placement = Layout::column(vec![Layout::leaf(), Layout::widget("spectrum").height(Length::Ep(5.0))])
+                .padding(2 * EDGE_PADDING())
+                .resolve(position);
+            SpectrumBars::new(placement.rect("spectrum"))
+        });
+        Self { position, title: Marquee::new(), artist: Marquee::new(), spectrum, playback: store.playback.subscribe(), wifi: store.wifi.subscribe(), track_area: Cell::new(None) }
     }
     
     fn get_time_strs() -> (String, String) {
-        let now = chrono::Local::now();
+        let now = clock::now();
 
         let weekday = match now.weekday() {
             Weekday::Mon => {
@@ -54,8 +89,12 @@ impl InfoWidget {
 }
 
 impl Drawable for InfoWidget {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
     fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
-        let path = WIFI_STRENGTH().to_path();
+        let path = self.wifi.get().to_path();
         let xp = self.position.x + 2 * EDGE_PADDING();
         let jb_large_l_size = uihelper.font_owner.jb_large_l.char_dim();
         
@@ -67,39 +106,97 @@ impl Drawable for InfoWidget {
         ctx.draw_image(x + jb_large_l_size.one() as i32, y - (jb_large_l_size.two() / 7) as i32, jb_large_l_size.scale_1(2f32).into(), path.as_str(), uihelper);
         let (_, y) = ctx.draw_text(xp, y + 2 * jb_large_l_size.one() as i32, &uihelper.font_owner.jb_large_s, date.as_str(), TXT_SUBTEXT, uihelper);
         
-        if let Some(track) = &*BLUETOOTH_DATA() {
+        if let Some(track) = self.playback.get().as_ref() {
             let title_y = y + 2 * jb_large_l_size.one() as i32;
             let artist_y = title_y + (uihelper.font_owner.jb_medium_l.char_dim().two() as f32 * 1.5) as i32;
             let line_y = artist_y + (1.8 * EDGE_PADDING() as f32) as i32;
-            
-            let title_bounds = self.position.w - EDGE_PADDING() * 4 - xp;
-            let artist_bounds = self.position.w / 2 - 3 * xp;
-            
-            let size_of_title_text= uihelper.font_owner.jb_medium_l.size_of_text(track.title.as_str()).one() as i32;
-            let size_of_artist_text= uihelper.font_owner.jb_medium_m.size_of_text(track.artist.as_str()).one() as i32;
-            
-            let delta_title = title_bounds - size_of_title_text;
-            let delta_artist = artist_bounds - size_of_artist_text;
+
+            // Cover art
+            let cover_size = (line_y - title_y + EDGE_PADDING()) as u32;
+            self.track_area.set(Some(Rect::new(xp, title_y, (self.position.right() - 2 * EDGE_PADDING() - xp).max(0) as u32, cover_size)));
+            match track.cover_art_path() {
+                Some(cover) => { ctx.draw_image(xp, title_y, (cover_size, cover_size), cover.as_str(), uihelper); }
+                None => ctx.draw_rect(Rect::new(xp, title_y, cover_size, cover_size), BG_TINTED)
+            }
+            let text_x = xp + cover_size as i32 + 2 * EDGE_PADDING();
+            let text_width = (self.position.right() - 2 * EDGE_PADDING() - text_x).max(0) as u32;
 
             // Title
-            ctx.draw_text(xp + delta_title / 2, title_y, &uihelper.font_owner.jb_medium_l, track.title.as_str(), TXT_DEFAULT, &uihelper);
-            // Artist
-            ctx.draw_text(xp + delta_artist / 2, artist_y, &uihelper.font_owner.jb_medium_m, track.artist.as_str(), TXT_SUBTEXT, &uihelper);
-            // Position
-            let (x, _) = ctx.draw_text(artist_bounds + EDGE_PADDING(), artist_y, &uihelper.font_owner.jb_medium_m, &*format_time(track.position / 1000), TXT_SUBTEXT, &uihelper);
-            // Line
-            let ls = 5 * EDGE_PADDING();
-            let line_end = x + 31 * EDGE_PADDING();
-            let length = track.line_length(line_end - x - ls);
-            ctx.draw_line(Point::new(x + ls, line_y), Point::new(line_end, line_y), EDGE_PADDING(), PB_EMPTY);
+            self.title.draw(ctx, text_x, title_y, text_width, &uihelper.font_owner.jb_medium_l, track.title.as_str(), TXT_DEFAULT, uihelper);
+
+            // Artist, position, state, progress line and duration next to each other
+            let font = &uihelper.font_owner.jb_medium_m;
+            let (position, duration) = (format_time(track.position() / 1000), format_time(track.duration / 1000));
+            let text_length = |text: &str| Length::Px(font.size_of_text(text).one() as i32);
+            let row = Layout::row(vec![
+                Layout::widget("artist"),
+                Layout::widget("position").width(text_length(&position)),
+                Layout::widget("state").width(Length::Ep(2.0)).height(Length::Ep(2.0)),
+                Layout::widget("line"),
+                Layout::widget("duration").width(text_length(&duration)),
+            ]).spacing(2 * EDGE_PADDING()).align(Align::Start, Align::Center)
+                .resolve(Rect::new(text_x, artist_y, text_width, font.char_dim().two()));
+
+            let artist = row.rect("artist");
+            self.artist.draw(ctx, artist.x, artist_y, artist.width(), font, track.artist.as_str(), TXT_SUBTEXT, uihelper);
+            ctx.draw_text(row.rect("position").x, artist_y, font, position.as_str(), TXT_SUBTEXT, uihelper);
+            let state = row.rect("state");
+            draw_state_glyph(ctx, state.x, state.y, state.h, track.playback_state);
+            let line = row.rect("line");
+            let length = track.line_length(line.w);
+            ctx.draw_line(Point::new(line.x, line_y), Point::new(line.right(), line_y), EDGE_PADDING(), PB_EMPTY);
             if length != 0 {
-                ctx.draw_line(Point::new(x + ls, line_y), Point::new(x + ls + length, line_y), EDGE_PADDING(), PB_FULLY);
+                ctx.draw_line(Point::new(line.x, line_y), Point::new(line.x + length, line_y), EDGE_PADDING(), PB_FULLY);
+            }
+            ctx.draw_text(row.rect("duration").x, artist_y, font, duration.as_str(), TXT_SUBTEXT, uihelper);
+            // Queue position
+            if let Some((index, length)) = track.queue_position {
+                ctx.draw_text(text_x, line_y + 2 * EDGE_PADDING(), &uihelper.font_owner.jb_medium_m, format!("{index}/{length}").as_str(), TXT_SUBTEXT, uihelper);
+            }
+            // Source device
+            if let Some(device) = &track.device {
+                let source = match device.battery {
+                    Some(battery) => format!("{} ({battery}%)", device.name),
+                    None => device.name.clone()
+                };
+                let source_width = uihelper.font_owner.jb_medium_m.size_of_text(source.as_str()).one() as i32;
+                ctx.draw_text(self.position.x + self.position.w - 2 * EDGE_PADDING() - source_width, line_y + 2 * EDGE_PADDING(), &uihelper.font_owner.jb_medium_m, source.as_str(), TXT_SUBTEXT, uihelper);
+            }
+            // Spectrum
+            if let Some(spectrum) = &self.spectrum {
+                spectrum.draw(ctx);
             }
-            // Duration
-            ctx.draw_text(line_end + ls, artist_y, &uihelper.font_owner.jb_medium_m, &*format_time(track.duration / 1000), TXT_SUBTEXT, &uihelper);
         } else {
+            self.track_area.set(None);
             ctx.draw_text(xp + 5 * EDGE_PADDING(), y + 2 * jb_large_l_size.one() as i32, &uihelper.font_owner.jb_medium_l, "Suche nach geräten...", TXT_DEFAULT, &uihelper);
-            ctx.draw_text(xp + 5 * EDGE_PADDING(), y + 3 * jb_large_l_size.one() as i32, &uihelper.font_owner.jb_medium_l, "Name: Raspi Audio Player", TXT_SUBTEXT, &uihelper);
+            ctx.draw_text(xp + 5 * EDGE_PADDING(), y + 3 * jb_large_l_size.one() as i32, &uihelper.font_owner.jb_medium_l, format!("Name: {}", CONFIG().bluetooth.alias).as_str(), TXT_SUBTEXT, &uihelper);
         }
     }
-}
\ No newline at end of file
+
+    fn redraw(&self) -> Redraw {
+        // Clock
+        let mut redraw = next_second();
+        if self.playback.peek().is_some() {
+            redraw = redraw.min(self.title.redraw()).min(self.artist.redraw());
+            if let Some(spectrum) = &self.spectrum {
+                redraw = redraw.min(spectrum.redraw());
+            }
+        }
+        redraw
+    }
+
+    fn changed(&self) -> bool {
+        self.playback.changed() || self.wifi.changed()
+    }
+
+    // Tapping the track plays / pauses it, the clock and date don't
+    fn handle_gesture(&mut self, gesture: &Gesture) -> Handled {
+        match gesture {
+            Gesture::Tap(point) if self.playback.peek().is_some() && self.track_area.get().is_some_and(|area| area.contains_point(*point)) => {
+                media_command(self.playback.peek().as_ref(), MediaCommand::PlayPause);
+                Handled::Yes
+            }
+            _ => Handled::No
+        }
+    }
+}
@@ -0,0 +1,38 @@
+pub mod display;
+pub mod ui_renderer;
+pub mod api;
+pub mod errors;
+pub mod weather_widget;
+pub mod info_widget;
+pub mod wifi_api;
+pub mod threads;
+pub mod bluetooth;
+pub mod color;
+pub mod pairing;
+pub mod pairing_widget;
+pub mod config;
+pub mod device_widget;
+pub mod cover_art;
+pub mod media;
+pub mod mpd;
+pub mod mpris;
+pub mod volume_widget;
+pub mod airplay;
+pub mod lyrics;
+pub mod lyrics_widget;
+pub mod history;
+pub mod history_widget;
+pub mod scrobbler;
+pub mod spectrum;
+pub mod spectrum_widget;
+pub mod texture_cache;
+pub mod redraw;
+pub mod widget_registry;
+pub mod headless;
+pub mod clock;
+pub mod snapshot;
+pub mod simulation;
+pub mod recording;
+pub mod state;
+pub mod gestures;
+pub mod page_manager;
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use crate::config::CONFIG;
+use crate::redraw::request_redraw;
+
+// How deep to look into the lyrics directory (e.g. artist/album/title.lrc)
+const SEARCH_DEPTH: usize = 3;
+// Largest edit distance (relative to the name length) still counted as a match
+const FUZZY_THRESHOLD: f32 = 0.25;
+// A track without lyrics is looked up again after this long, e.g. when an .lrc file was added
+const MISSING_TTL: Duration = Duration::from_secs(60);
+
+enum LyricsLookup {
+    Pending,
+    Found(Arc<Lyrics>),
+    // Nothing found at this time
+    Missing(Instant),
+}
+
+// "artist\ntitle" -> lookup result
+static _LYRICS_CACHE: LazyLock<Mutex<HashMap<String, LyricsLookup>>> =
+    LazyLock::new(|| {Mutex::new(HashMap::new())});
+
+/// Synced lyrics, lines sorted by time
+#[derive(Debug, Default)]
+pub struct Lyrics {
+    pub lines: Vec<(u32, String)>,
+}
+
+// [mm:ss], [mm:ss.xx] or [mm:ss:xx] in ms
+fn parse_timestamp(tag: &str) -> Option<u32> {
+    let (minutes, rest) = tag.split_once(':')?;
+    let (seconds, fraction) = rest.split_once(['.', ':']).unwrap_or((rest, "0"));
+    let fraction_ms = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u32>().ok()? * 100,
+        2 => fraction.parse::<u32>().ok()? * 10,
+        _ => fraction.get(..3)?.parse::<u32>().ok()?
+    };
+    Some(minutes.trim().parse::<u32>().ok()? * 60_000 + seconds.trim().parse::<u32>().ok()? * 1000 + fraction_ms)
+}
+
+impl Lyrics {
+    /// Parses LRC, a line may carry several timestamps, `[offset:ms]` shifts all of them
+    pub fn parse(content: &str) -> Self {
+        let mut offset = 0i64;
+        let mut lines = vec![];
+        for line in content.lines() {
+            let mut rest = line.trim();
+            let mut times = vec![];
+            while let Some(tag) = rest.strip_prefix('[').and_then(|r| r.split_once(']')) {
+                let (tag, text) = tag;
+                if let Some(value) = tag.strip_prefix("offset:") {
+                    offset = value.trim().parse().unwrap_or(0);
+                } else if let Some(time) = parse_timestamp(tag) {
+                    times.push(time);
+                }
+                rest = text;
+            }
+            for time in times {
+                lines.push((time, rest.trim().to_string()));
+            }
+        }
+
+        // A positive offset shows the lyrics earlier
+        let lines = lines.into_iter()
+            .map(|(time, text)| ((time as i64 - offset).max(0) as u32, text))
+            .collect::<Vec<_>>();
+        let mut lyrics = Self {lines};
+        lyrics.lines.sort_by_key(|(time, _)| *time);
+        lyrics
+    }
+
+    /// Index of the line sung at `position` (ms)
+    pub fn current_line(&self, position: u32) -> Option<usize> {
+        self.lines.partition_point(|(time, _)| *time <= position).checked_sub(1)
+    }
+}
+
+// Lowercase letters and digits only, so "AC/DC - Back In Black" ~ "acdc_back_in_black"
+fn normalize(text: &str) -> String {
+    text.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb { previous } else { 1 + previous.min(row[j]).min(row[j + 1]) };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+fn collect_lrc_files(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.is_dir() && depth > 1 {
+            collect_lrc_files(&path, depth - 1, files);
+        } else if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("lrc")) {
+            files.push(path);
+        }
+    }
+}
+
+/// Finds the best matching `.lrc` file, by its path relative to `dir`
+/// (e.g. "Artist - Title.lrc" or "Artist/Title.lrc")
+pub fn find_lyrics_file(dir: &Path, artist: &str, title: &str) -> Option<PathBuf> {
+    let (artist, title) = (normalize(artist), normalize(title));
+    if title.is_empty() {
+        return None;
+    }
+    let wanted = format!("{artist}{title}");
+
+    let mut files = vec![];
+    collect_lrc_files(dir, SEARCH_DEPTH, &mut files);
+
+    let mut best: Option<(f32, PathBuf)> = None;
+    for file in files {
+        let relative = file.strip_prefix(dir).unwrap_or(&file).with_extension("");
+        let name = normalize(&relative.to_string_lossy());
+        let score = if name == wanted || name.ends_with(&wanted) { 0.0 }
+            else if name.contains(&title) && name.contains(&artist) { 0.05 }
+            else if name == title { 0.1 }
+            else { edit_distance(&name, &wanted) as f32 / wanted.chars().count().max(1) as f32 };
+
+        if score <= FUZZY_THRESHOLD && best.as_ref().is_none_or(|(b, _)| score < *b) {
+            best = Some((score, file));
+        }
+    }
+    best.map(|(_, file)| file)
+}
+
+/// Lyrics of the track, looked up in the background the first time it is asked for
+pub fn lyrics_for(artist: &str, title: &str) -> Option<Arc<Lyrics>> {
+    let key = format!("{artist}\n{title}");
+    let mut cache = _LYRICS_CACHE.lock().unwrap();
+    match cache.get(&key) {
+        Some(LyricsLookup::Found(lyrics)) => Some(lyrics.clone()),
+        Some(LyricsLookup::Pending) => None,
+        Some(LyricsLookup::Missing(since)) if since.elapsed() < MISSING_TTL => None,
+        _ => {
+            cache.insert(key.clone(), LyricsLookup::Pending);
+            let (artist, title) = (artist.to_string(), title.to_string());
+            // A thread of its own, this is called while drawing, which may happen outside the runtime
+            std::thread::spawn(move || {
+                let result = find_lyrics_file(&CONFIG().lyrics_dir, &artist, &title)
+                    .and_then(|file| std::fs::read_to_string(file).ok())
+                    .map(|content| Lyrics::parse(&content))
+                    .filter(|lyrics| !lyrics.lines.is_empty());
+                let result = match result {
+                    Some(lyrics) => LyricsLookup::Found(Arc::new(lyrics)),
+                    None => LyricsLookup::Missing(Instant::now())
+                };
+                _LYRICS_CACHE.lock().unwrap().insert(key, result);
+                request_redraw();
+            });
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Directory of its own per test and run, removed afterwards
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("unyo-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn add(&self, file: &str) -> PathBuf {
+            let path = self.0.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "[00:01.00]x").unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn times(lyrics: &Lyrics) -> Vec<u32> {
+        lyrics.lines.iter().map(|(time, _)| *time).collect()
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("01:02"), Some(62_000));
+        assert_eq!(parse_timestamp("01:02.5"), Some(62_500));
+        assert_eq!(parse_timestamp("01:02.25"), Some(62_250));
+        assert_eq!(parse_timestamp("01:02.125"), Some(62_125));
+        assert_eq!(parse_timestamp("01:02:50"), Some(62_500));
+        assert_eq!(parse_timestamp("ar:Queen"), None);
+    }
+
+    #[test]
+    fn several_timestamps_on_a_line() {
+        let lyrics = Lyrics::parse("[00:10.00][00:30.00]Chorus\n[00:20.00]Verse");
+        assert_eq!(times(&lyrics), [10_000, 20_000, 30_000]);
+        assert_eq!(lyrics.lines[0].1, "Chorus");
+        assert_eq!(lyrics.lines[1].1, "Verse");
+        assert_eq!(lyrics.lines[2].1, "Chorus");
+    }
+
+    #[test]
+    fn metadata_is_ignored() {
+        let lyrics = Lyrics::parse("[ar:Queen]\n[ti:Bohemian Rhapsody]\n[length: 03:20]\n[00:01.00]Is this the real life?");
+        assert_eq!(times(&lyrics), [1000]);
+    }
+
+    #[test]
+    fn offset() {
+        // Earlier, but not before the start
+        let lyrics = Lyrics::parse("[offset:+1500]\n[00:01.00]a\n[00:05.00]b");
+        assert_eq!(times(&lyrics), [0, 3500]);
+        let lyrics = Lyrics::parse("[offset:-500]\n[00:01.00]a");
+        assert_eq!(times(&lyrics), [1500]);
+    }
+
+    #[test]
+    fn current_line() {
+        let lyrics = Lyrics::parse("[00:10.00]a\n[00:20.00]b");
+        assert_eq!(lyrics.current_line(0), None);
+        assert_eq!(lyrics.current_line(9_999), None);
+        assert_eq!(lyrics.current_line(10_000), Some(0));
+        assert_eq!(lyrics.current_line(25_000), Some(1));
+    }
+
+    #[test]
+    fn find_file() {
+        let dir = TempDir::new("lyrics-find");
+        let exact = dir.add("Queen - Bohemian Rhapsody.lrc");
+        let nested = dir.add("Queen/A Night at the Opera/Bohemian Rhapsody.lrc");
+        let typo = dir.add("New Order - Blue Mondey.lrc");
+        dir.add("Trio - Da Da Da.lrc");
+        let title_only = dir.add("Kids.lrc");
+
+        assert_eq!(find_lyrics_file(&dir.0, "Queen", "Bohemian Rhapsody"), Some(exact.clone()));
+        std::fs::remove_file(&exact).unwrap();
+        assert_eq!(find_lyrics_file(&dir.0, "Queen", "Bohemian Rhapsody"), Some(nested));
+        assert_eq!(find_lyrics_file(&dir.0, "New Order", "Blue Monday"), Some(typo));
+        assert_eq!(find_lyrics_file(&dir.0, "", "Kids"), Some(title_only));
+        assert_eq!(find_lyrics_file(&dir.0, "Queen", "Under Pressure"), None);
+        assert_eq!(find_lyrics_file(&dir.0, "Queen", ""), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+use sdl2::rect::Rect;
+use crate::color::{BG_SHADED, DIV_LINE, TXT_DEFAULT, TXT_SUBTEXT};
+use crate::lyrics::lyrics_for;
+use crate::media::{PlaybackData, PlaybackState};
+use crate::redraw::Redraw;
+use crate::state::{Store, Subscription};
+use crate::ui_renderer::{Drawable, Marquee, UIContext, UIHelper, EDGE_PADDING};
+
+// Time (ms) the lines take to move up by one
+const SCROLL_DURATION: u32 = 300;
+
+/// Lyrics of the current track, the line being sung is highlighted and kept in the middle
+pub struct LyricsWidget {
+    position: Rect,
+    current: Marquee,
+    playback: Subscription<Option<PlaybackData>>,
+}
+
+impl LyricsWidget {
+    pub fn new(position: Rect, store: &Store) -> Self {
+        Self {position, current: Marquee::new(), playback: store.playback.subscribe()}
+    }
+}
+
+impl Drawable for LyricsWidget {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        let playback = self.playback.get();
+        let Some(track) = playback.as_ref() else { return };
+        let Some(lyrics) = lyrics_for(&track.artist, &track.title) else { return };
+
+        let xp = self.position.x + 2 * EDGE_PADDING();
+        let width = (self.position.w - 4 * EDGE_PADDING()) as u32;
+        ctx.draw_rect(self.position, BG_SHADED);
+        ctx.draw_line(self.position.top_left(), self.position.top_right(), EDGE_PADDING() / 2, DIV_LINE);
+        let (_, y) = ctx.draw_text(xp, self.position.y + 2 * EDGE_PADDING(), &uihelper.font_owner.jb_medium_l, "LIEDTEXT", TXT_DEFAULT, uihelper);
+
+        let top = y + 2 * uihelper.font_owner.jb_medium_l.char_dim().two() as i32;
+        let bottom = self.position.bottom() - 2 * EDGE_PADDING();
+        let line_h = (uihelper.font_owner.jb_medium_l.char_dim().two() as f32 * 1.6) as i32;
+        let middle = top + (bottom - top - line_h) / 2;
+
+        let position = track.position();
+        let current = lyrics.current_line(position);
+        // Slide in from below right after a line change
+        let scroll = match current {
+            Some(index) => line_h - line_h * (position - lyrics.lines[index].0).min(SCROLL_DURATION) as i32 / SCROLL_DURATION as i32,
+            None => 0
+        };
+
+        let rows_around = (middle - top) / line_h + 1;
+        let current_index = current.map(|i| i as i32).unwrap_or(-1);
+        for row in -rows_around..=rows_around {
+            let index = current_index + row;
+            let y = middle + row * line_h + scroll;
+            if index < 0 || y < top || y + line_h > bottom {
+                continue;
+            }
+            let Some((_, text)) = lyrics.lines.get(index as usize).filter(|(_, text)| !text.is_empty()) else { continue };
+            if row == 0 {
+                self.current.draw(ctx, xp, y, width, &uihelper.font_owner.jb_medium_l, text.as_str(), TXT_DEFAULT, uihelper);
+            } else {
+                ctx.draw_text_clipped(xp, y, width, 0, &uihelper.font_owner.jb_medium_m, text.as_str(), TXT_SUBTEXT, uihelper);
+            }
+        }
+    }
+
+    fn redraw(&self) -> Redraw {
+        let playback = self.playback.peek();
+        let Some(track) = playback.as_ref() else { return Redraw::Idle };
+        let Some(lyrics) = lyrics_for(&track.artist, &track.title) else { return Redraw::Idle };
+        let marquee = self.current.redraw();
+        if track.playback_state != PlaybackState::Playing {
+            return marquee;
+        }
+
+        let position = track.position();
+        let current = lyrics.current_line(position);
+        if current.is_some_and(|i| position - lyrics.lines[i].0 < SCROLL_DURATION) {
+            return Redraw::Animating;
+        }
+        // Next line change
+        let next = current.map(|i| i + 1).unwrap_or(0);
+        match lyrics.lines.get(next) {
+            Some((time, _)) => marquee.min(Redraw::At(Instant::now() + Duration::from_millis((time - position) as u64))),
+            None => marquee
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.playback.changed()
+    }
+}
\ No newline at end of file
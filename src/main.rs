@@ -1,28 +1,133 @@
-use crate::bluetooth::{BluetoothController};
-use crate::display::video_main;
-use crate::threads::{init_threads};
-
-mod display;
-mod ui_renderer;
-mod api;
-mod errors;
-mod weather_widget;
-mod info_widget;
-mod wifi_api;
-mod threads;
-mod bluetooth;
-mod color;
-
-pub(crate) const fn fraction(a: i32, b: i32) -> f32 {
-    a as f32 / b as f32
+use unyo::airplay::ShairportSource;
+use unyo::bluetooth::{BluetoothController, BluezSource};
+use unyo::config::CONFIG;
+use unyo::cover_art::CoverArtFetcher;
+use std::path::PathBuf;
+use std::sync::Arc;
+use unyo::{cover_art, pairing};
+use unyo::display::{video_main, Screen};
+use unyo::recording::{start_recording, start_replay};
+use unyo::simulation::{start_simulation, WeatherScenario};
+use unyo::ui_renderer::WindowMode;
+use unyo::headless::screenshot;
+use unyo::snapshot::{run_snapshots, DEFAULT_TOLERANCE};
+use unyo::media::{init_media_sources, MediaSource};
+use unyo::mpd::MpdSource;
+use unyo::mpris::MprisSource;
+use unyo::state::Store;
+use unyo::threads::{init_threads};
+
+// Value after `flag` on the command line
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next()?;
+    args.next()
+}
+
+// `--size 1920x1080`
+fn size_arg() -> Option<(u32, u32)> {
+    arg_value("--size").and_then(|size| size.split_once('x').and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?))))
+}
+
+// `--screenshot out.png [--size 1920x1080] [--screen main|devices|history]`
+fn screenshot_args() -> Option<(PathBuf, (u32, u32), Screen)> {
+    let path = PathBuf::from(arg_value("--screenshot")?);
+    let size = size_arg().unwrap_or((1920, 1080));
+    let screen = arg_value("--screen").and_then(|screen| screen.parse().map_err(|e| eprintln!("{e}")).ok()).unwrap_or(Screen::Main);
+    Some((path, size, screen))
+}
+
+// The window for `--replay` and `--simulate`, a missing font ends it with a message
+fn run_desktop((w, h): (u32, u32), store: Arc<Store>) {
+    if let Err(e) = video_main(WindowMode::Desktop(w, h), store, None) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+async fn media_sources(bluetooth: &Arc<BluetoothController<'static>>) -> Vec<Box<dyn MediaSource>> {
+    let mut sources: Vec<Box<dyn MediaSource>> = vec![];
+    for name in &CONFIG().media.sources {
+        match name.as_str() {
+            "bluetooth" => sources.push(Box::new(BluezSource(bluetooth.clone()))),
+            "mpris" => match MprisSource::new().await {
+                Ok(source) => sources.push(Box::new(source)),
+                Err(e) => eprintln!("MPRIS unavailable: {e}")
+            },
+            "mpd" => sources.push(Box::new(MpdSource::new(CONFIG().media.mpd_address.clone(), CONFIG().media.mpd_password.clone()))),
+            "airplay" => sources.push(Box::new(ShairportSource::new(CONFIG().media.shairport_pipe.clone(), CONFIG().media.shairport_udp.clone()))),
+            _ => eprintln!("Unknown media source: {name}")
+        }
+    }
+    sources
 }
 
 #[tokio::main]
 async fn main() {
-    // Init and set Bluetooth controller
-    bluetooth::_BLUETOOTH_CTL.set(BluetoothController::new().await.expect("Failed to init bt-ctl")).expect("Failed to set bt-ctl");
-    init_threads();
-    video_main().expect("FAILED");
+    let store = Arc::new(Store::new());
+    // One frame offscreen, without Bluetooth, media sources or background threads
+    if let Some((path, size, screen)) = screenshot_args() {
+        if let Err(e) = screenshot(&path, size, screen, &store) {
+            eprintln!("Failed to take screenshot: {e}");
+            std::process::exit(1);
+        }
+        println!("Saved {}", path.display());
+        return;
+    }
+    // `--snapshots <dir> [--update] [--tolerance 8]`: compares the widgets against reference images
+    if let Some(dir) = arg_value("--snapshots") {
+        let update = std::env::args().any(|arg| arg == "--update");
+        let tolerance = arg_value("--tolerance").and_then(|t| t.parse().ok()).unwrap_or(DEFAULT_TOLERANCE);
+        let passed = run_snapshots(&PathBuf::from(dir), update, tolerance);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    // `--replay log.jsonl [--speed 4] [--size 1280x720]`: a recording instead of the data sources
+    if let Some(path) = arg_value("--replay") {
+        let speed = arg_value("--speed").and_then(|s| s.parse().ok()).filter(|s: &f64| *s > 0.0).unwrap_or(1.0);
+        if let Err(e) = start_replay(&PathBuf::from(&path), speed, store.clone()) {
+            eprintln!("Failed to read recording {path}: {e}");
+            std::process::exit(1);
+        }
+        run_desktop(size_arg().unwrap_or((1280, 720)), store);
+        return;
+    }
+    // `--record log.jsonl`: logs every data source update for `--replay`
+    if let Some(path) = arg_value("--record") {
+        if let Err(e) = start_recording(&PathBuf::from(&path), &store) {
+            eprintln!("Failed to start recording to {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+    // `--simulate [storm|snow|heat] [--size 1280x720]`: scripted data in a desktop window
+    if std::env::args().any(|arg| arg == "--simulate") {
+        let scenario = arg_value("--simulate").filter(|scenario| !scenario.starts_with("--"))
+            .and_then(|scenario| scenario.parse::<WeatherScenario>().map_err(|e| eprintln!("{e}")).ok());
+        start_simulation(scenario, store.clone());
+        run_desktop(size_arg().unwrap_or((1280, 720)), store);
+        return;
+    }
+
+    // Init Bluetooth controller
+    let bluetooth = Arc::new(BluetoothController::new(store.clone()).await.expect("Failed to init bt-ctl"));
+    if let Err(e) = pairing::init_pairing(bluetooth.connection(), store.clone()).await {
+        eprintln!("Failed to set up pairing: {e}");
+    }
+    if CONFIG().bluetooth.auto_reconnect {
+        let bluetooth = bluetooth.clone();
+        tokio::spawn(async move {
+            if let Err(e) = bluetooth.reconnect_last_device().await {
+                eprintln!("Failed to reconnect last device: {e}");
+            }
+        });
+    }
+    // Cover art is optional, obexd lives on the session bus which might not be there
+    match CoverArtFetcher::new().await {
+        Ok(fetcher) => cover_art::_COVER_ART_FETCHER.set(fetcher).expect("Failed to set cover-art fetcher"),
+        Err(e) => eprintln!("Cover art unavailable: {e}")
+    }
+    init_media_sources(media_sources(&bluetooth).await);
+    init_threads(store.clone());
+    video_main(WindowMode::Panel, store, Some(bluetooth)).expect("FAILED");
 }
 
 /*
@@ -0,0 +1,316 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::time::Instant;
+use crate::config::{MediaPolicy, CONFIG};
+use crate::cover_art::CoverArtSource;
+use crate::errors::UnyoResult;
+use crate::history;
+use crate::recording::{record, Event};
+use crate::state::Store;
+
+pub type PollFuture<'a> = Pin<Box<dyn Future<Output = Option<PlaybackData>> + Send + 'a>>;
+pub type ControlFuture<'a> = Pin<Box<dyn Future<Output = UnyoResult<()>> + Send + 'a>>;
+
+static _MEDIA_SOURCES: OnceLock<Vec<Box<dyn MediaSource>>> = OnceLock::new();
+
+/// Something that can tell what is currently playing
+pub trait MediaSource: Send + Sync {
+    /// Name used in the config to enable / order sources
+    fn name(&self) -> &'static str;
+
+    fn poll(&self) -> PollFuture<'_>;
+
+    /// Transport control of whatever the last poll returned, ignored by default
+    fn control(&self, _command: MediaCommand) -> ControlFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaCommand {
+    PlayPause,
+    Next,
+    Previous,
+}
+
+/// Sends the command to the source of `playback`, the one shown
+pub fn media_command(playback: Option<&PlaybackData>, command: MediaCommand) {
+    let Some(source_name) = playback.map(|d| d.source) else { return };
+    let Some(source) = _MEDIA_SOURCES.get().and_then(|s| s.iter().find(|s| s.name() == source_name)) else { return };
+    tokio::spawn(async move {
+        if let Err(e) = source.control(command).await {
+            eprintln!("{command:?} failed on {source_name}: {e:?}");
+        }
+    });
+}
+
+/// Sets the sources to poll, highest priority first
+pub fn init_media_sources(sources: Vec<Box<dyn MediaSource>>) {
+    _MEDIA_SOURCES.set(sources).unwrap_or_else(|_| panic!("Failed to set media sources"));
+}
+
+#[allow(non_snake_case)]
+pub async fn UPDATE_PLAYBACK_DATA(store: &Store) {
+    let Some(sources) = _MEDIA_SOURCES.get() else { return };
+
+    let mut candidates = vec![];
+    for (priority, source) in sources.iter().enumerate() {
+        if let Some(mut data) = source.poll().await {
+            data.source = source.name();
+            candidates.push((priority, data));
+        }
+    }
+
+    let mut data = match CONFIG().media.policy {
+        MediaPolicy::PlayingFirst => candidates.into_iter().min_by_key(|(priority, data)| (data.playback_state.rank(), *priority)),
+        MediaPolicy::Priority => candidates.into_iter().min_by_key(|(priority, _)| *priority),
+    }.map(|(_, data)| data);
+    history::observe(data.as_ref());
+
+    let current = store.playback.get();
+    // A new reported position (seek) is recorded too, the rest is extrapolated
+    let seeked = matches!((&data, &*current), (Some(data), Some(previous)) if data.reported_position != previous.reported_position);
+    if let (Some(data), Some(previous)) = (&mut data, &*current) {
+        data.carry_anchor(previous);
+    }
+    let changed = match (&data, &*current) {
+        (Some(data), Some(previous)) => !data.looks_same(previous),
+        (data, previous) => data.is_some() != previous.is_some()
+    };
+    if changed || seeked {
+        record(|| Event::Playback(data.clone().map(Box::new)));
+    }
+    // Position changes are picked up by the clock tick
+    if changed {
+        store.playback.set(data);
+    } else {
+        store.playback.refresh(data);
+    }
+}
+
+/// Replaces the playback as is, without asking the sources
+pub fn set_playback_data(store: &Store, data: Option<PlaybackData>) {
+    record(|| Event::Playback(data.clone().map(Box::new)));
+    store.playback.set(data);
+}
+
+/// Where the playback comes from (phone, local player, ...)
+#[derive(Debug, Clone)]
+pub struct SourceDevice {
+    pub name: String,
+    // Percentage, only if the device reports it
+    pub battery: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackState {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl From<String> for PlaybackState {
+    fn from(value: String) -> Self {
+        let value = value.to_lowercase();
+        if value == "playing" { PlaybackState::Playing }
+        else if value == "paused" { PlaybackState::Paused }
+        else { PlaybackState::Stopped }
+    }
+}
+
+impl PlaybackState {
+    /// Lower is more relevant
+    pub fn rank(&self) -> u8 {
+        match self {
+            PlaybackState::Playing => 0,
+            PlaybackState::Paused => 1,
+            PlaybackState::Stopped => 2
+        }
+    }
+}
+
+#[inline]
+pub fn limit_string_size(input: String, max_length: usize) -> String {
+    if input.chars().count() > max_length {
+        let truncated = input.chars().take(max_length - 3).collect::<String>();
+        format!("{}...", truncated)
+    } else {
+        input.to_string()
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct PlaybackData {
+    pub title: String,
+    pub artist: String,
+    // Only from sources that report it
+    pub album: Option<String>,
+    pub playback_state: PlaybackState,
+    // Position as last reported by the player
+    reported_position: u32,
+    // Position and (monotonic) time from which the position is extrapolated
+    anchor_position: u32,
+    anchor_time: Instant,
+    pub duration: u32,
+    pub cover_art: Option<CoverArtSource>,
+    // Device / player the playback comes from
+    pub device: Option<SourceDevice>,
+    // Name of the media source
+    pub source: &'static str,
+    // Position in the play queue (1-based) and its length, if the source has one
+    pub queue_position: Option<(u32, u32)>
+}
+
+impl PlaybackData {
+    pub fn new(title: String,
+               artist: String,
+               playback_state: PlaybackState,
+               position: u32,
+               duration: u32) -> Self {
+        Self { title, artist, album: None, playback_state, reported_position: position, anchor_position: position, anchor_time: Instant::now(), duration, cover_art: None, device: None, source: "", queue_position: None }
+    }
+
+    pub fn is_same_track(&self, other: &PlaybackData) -> bool {
+        self.source == other.source && self.title == other.title && self.artist == other.artist && self.duration == other.duration
+    }
+
+    /// Same track, state and details, only the position may differ
+    pub fn looks_same(&self, other: &PlaybackData) -> bool {
+        self.is_same_track(other)
+            && self.album == other.album
+            && self.playback_state == other.playback_state
+            && self.cover_art_path() == other.cover_art_path()
+            && self.device.as_ref().map(|d| (&d.name, d.battery)) == other.device.as_ref().map(|d| (&d.name, d.battery))
+            && self.queue_position == other.queue_position
+    }
+
+    /// Keeps extrapolating from the previous anchor as long as the player reports nothing new.
+    /// A track change or a new reported position (seek) re-anchors at the reported position.
+    pub fn carry_anchor(&mut self, previous: &PlaybackData) {
+        if !self.is_same_track(previous) || self.reported_position != previous.reported_position {
+            return;
+        }
+
+        if self.playback_state == previous.playback_state {
+            self.anchor_position = previous.anchor_position;
+            self.anchor_time = previous.anchor_time;
+        } else {
+            // Paused / resumed without a position update, continue from where we were
+            self.anchor_position = previous.position();
+            self.anchor_time = Instant::now();
+        }
+    }
+
+    /// Path of the fetched cover art image, if there is one
+    pub fn cover_art_path(&self) -> Option<String> {
+        self.cover_art.as_ref().and_then(CoverArtSource::path)
+    }
+
+    /// Current position in ms, extrapolated while playing
+    pub fn position(&self) -> u32 {
+        match self.playback_state {
+            PlaybackState::Playing => {
+                let elapsed = self.anchor_time.elapsed().as_millis() as u32;
+                let position = self.anchor_position.saturating_add(elapsed);
+                if self.duration != 0 { position.min(self.duration) } else { position }
+            }
+            PlaybackState::Paused | PlaybackState::Stopped => self.anchor_position
+        }
+    }
+
+    pub fn line_length(&self, full: i32) -> i32 {
+        if self.duration == 0 {
+            return 0;
+        }
+        ((self.position() as f32 / self.duration as f32) * full as f32) as i32
+    }
+}
+
+impl From<(String, String, PlaybackState, u32, u32)> for PlaybackData {
+    fn from(value: (String, String, PlaybackState, u32, u32)) -> Self {
+        Self::new(value.0, value.1, value.2, value.3, value.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn track(title: &str, state: PlaybackState, position: u32) -> PlaybackData {
+        PlaybackData::new(title.to_string(), "Queen".to_string(), state, position, 200_000)
+    }
+
+    // As if anchored `ms` ago
+    fn age(data: &mut PlaybackData, ms: u64) {
+        data.anchor_time -= Duration::from_millis(ms);
+    }
+
+    fn assert_near(position: u32, expected: u32) {
+        assert!(position.abs_diff(expected) < 500, "{position} is not about {expected}");
+    }
+
+    #[test]
+    fn extrapolates_while_playing() {
+        let mut data = track("a", PlaybackState::Playing, 10_000);
+        age(&mut data, 5_000);
+        assert_near(data.position(), 15_000);
+        // Not past the end
+        age(&mut data, 500_000);
+        assert_eq!(data.position(), 200_000);
+
+        let mut paused = track("a", PlaybackState::Paused, 10_000);
+        age(&mut paused, 5_000);
+        assert_eq!(paused.position(), 10_000);
+    }
+
+    #[test]
+    fn keeps_anchor_without_news() {
+        let mut previous = track("a", PlaybackState::Playing, 10_000);
+        age(&mut previous, 5_000);
+        let mut data = track("a", PlaybackState::Playing, 10_000);
+        data.carry_anchor(&previous);
+        assert_near(data.position(), 15_000);
+    }
+
+    #[test]
+    fn seek_reanchors() {
+        let mut previous = track("a", PlaybackState::Playing, 10_000);
+        age(&mut previous, 5_000);
+        let mut data = track("a", PlaybackState::Playing, 60_000);
+        data.carry_anchor(&previous);
+        assert_near(data.position(), 60_000);
+    }
+
+    #[test]
+    fn pause_and_resume_continue() {
+        // Paused, the player still reports the position of the last update
+        let mut playing = track("a", PlaybackState::Playing, 10_000);
+        age(&mut playing, 5_000);
+        let mut paused = track("a", PlaybackState::Paused, 10_000);
+        paused.carry_anchor(&playing);
+        assert_near(paused.position(), 15_000);
+
+        // Stays there while paused
+        age(&mut paused, 30_000);
+        assert_near(paused.position(), 15_000);
+
+        // And goes on from there
+        let mut resumed = track("a", PlaybackState::Playing, 10_000);
+        resumed.carry_anchor(&paused);
+        assert_near(resumed.position(), 15_000);
+        age(&mut resumed, 2_000);
+        assert_near(resumed.position(), 17_000);
+    }
+
+    #[test]
+    fn track_change_reanchors() {
+        let mut previous = track("a", PlaybackState::Playing, 10_000);
+        age(&mut previous, 5_000);
+        let mut data = track("b", PlaybackState::Playing, 10_000);
+        data.carry_anchor(&previous);
+        assert_near(data.position(), 10_000);
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
+use crate::errors::{UnyoError, UnyoResult};
+use crate::media::{ControlFuture, MediaCommand, MediaSource, PlaybackData, PlaybackState, PollFuture, SourceDevice};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+trait MpdStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> MpdStream for T {}
+
+type Response = HashMap<String, String>;
+
+fn mpd_err(e: impl ToString) -> UnyoError {
+    UnyoError::Mpd(e.to_string())
+}
+
+// An argument in double quotes, backslashes first so the ones added for quotes stay
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A connection speaking the MPD text protocol
+struct MpdConnection {
+    stream: BufReader<Box<dyn MpdStream>>,
+}
+
+impl MpdConnection {
+    /// `address` is either `host:port` or the path of a Unix socket
+    async fn connect(address: &str, password: Option<&str>) -> UnyoResult<Self> {
+        let stream: Box<dyn MpdStream> = if address.starts_with('/') {
+            Box::new(UnixStream::connect(address).await.map_err(mpd_err)?)
+        } else {
+            Box::new(TcpStream::connect(address).await.map_err(mpd_err)?)
+        };
+        Self::handshake(stream, password).await
+    }
+
+    async fn handshake(stream: Box<dyn MpdStream>, password: Option<&str>) -> UnyoResult<Self> {
+        let mut connection = Self {stream: BufReader::new(stream)};
+        let greeting = connection.read_line().await?;
+        if !greeting.starts_with("OK MPD") {
+            return Err(mpd_err(format!("Unexpected greeting: {greeting}")));
+        }
+        if let Some(password) = password {
+            connection.command(&format!("password {}", quote(password))).await?;
+        }
+        Ok(connection)
+    }
+
+    async fn read_line(&mut self) -> UnyoResult<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await.map_err(mpd_err)? == 0 {
+            return Err(mpd_err("Connection closed"));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Sends a command and collects the `key: value` lines of the response
+    async fn command(&mut self, command: &str) -> UnyoResult<Response> {
+        self.stream.get_mut().write_all(format!("{command}\n").as_bytes()).await.map_err(mpd_err)?;
+
+        let mut response = Response::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == "OK" {
+                return Ok(response);
+            }
+            if line.starts_with("ACK") {
+                return Err(mpd_err(line));
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                // Keep the first one (e.g. multiple Artist tags)
+                response.entry(key.to_string()).or_insert(value.to_string());
+            }
+        }
+    }
+
+    async fn playback_data(&mut self) -> UnyoResult<Option<PlaybackData>> {
+        let status = self.command("status").await?;
+        let song = self.command("currentsong").await?;
+        Ok(playback_data(&status, &song))
+    }
+}
+
+fn seconds_to_ms(value: Option<&String>) -> Option<u32> {
+    value.and_then(|v| v.parse::<f64>().ok()).map(|s| (s * 1000.0) as u32)
+}
+
+fn playback_data(status: &Response, song: &Response) -> Option<PlaybackData> {
+    let file = song.get("file")?;
+    let state = match status.get("state").map(String::as_str) {
+        Some("play") => PlaybackState::Playing,
+        Some("pause") => PlaybackState::Paused,
+        _ => PlaybackState::Stopped
+    };
+
+    let title = song.get("Title").or(song.get("Name")).cloned()
+        .unwrap_or(file.rsplit('/').next().unwrap_or(file).to_string());
+    let artist = song.get("Artist").or(song.get("AlbumArtist")).cloned().unwrap_or("Unknown".to_string());
+    let position = seconds_to_ms(status.get("elapsed")).unwrap_or(0);
+    let duration = seconds_to_ms(status.get("duration").or(song.get("duration"))).unwrap_or(0);
+
+    let mut data = PlaybackData::from((title, artist, state, position, duration));
+    data.device = Some(SourceDevice {name: "MPD".to_string(), battery: None});
+    let song_index = status.get("song").and_then(|v| v.parse::<u32>().ok());
+    let queue_length = status.get("playlistlength").and_then(|v| v.parse::<u32>().ok());
+    if let (Some(song_index), Some(queue_length)) = (song_index, queue_length) {
+        data.queue_position = Some((song_index + 1, queue_length));
+    }
+    Some(data)
+}
+
+/// MPD (also when it feeds a Snapcast server), updated through `idle`
+pub struct MpdSource {
+    address: String,
+    password: Option<String>,
+    current: Arc<Mutex<Option<PlaybackData>>>,
+}
+
+impl MpdSource {
+    /// Starts listening for changes in the background
+    pub fn new(address: String, password: Option<String>) -> Self {
+        let current = Arc::new(Mutex::new(None));
+        let source = Self {address, password, current};
+
+        let (address, password, current) = (source.address.clone(), source.password.clone(), source.current.clone());
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = Self::watch(&address, password.as_deref(), &current).await {
+                    eprintln!("MPD ({address}): {e:?}");
+                }
+                *current.lock().unwrap() = None;
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+
+        source
+    }
+
+    async fn watch(address: &str, password: Option<&str>, current: &Mutex<Option<PlaybackData>>) -> UnyoResult<()> {
+        let mut connection = MpdConnection::connect(address, password).await?;
+        loop {
+            *current.lock().unwrap() = connection.playback_data().await?;
+            // Blocks until something changed
+            connection.command("idle player playlist options").await?;
+        }
+    }
+
+    async fn control(&self, command: MediaCommand) -> UnyoResult<()> {
+        let mut connection = MpdConnection::connect(&self.address, self.password.as_deref()).await?;
+        let command = match command {
+            MediaCommand::PlayPause => {
+                let status = connection.command("status").await?;
+                if status.get("state").is_some_and(|s| s == "play") { "pause 1" } else { "play" }
+            }
+            MediaCommand::Next => "next",
+            MediaCommand::Previous => "previous",
+        };
+        connection.command(command).await.map(|_| ())
+    }
+}
+
+impl MediaSource for MpdSource {
+    fn name(&self) -> &'static str {
+        "mpd"
+    }
+
+    fn poll(&self) -> PollFuture<'_> {
+        let data = self.current.lock().unwrap().clone();
+        Box::pin(async move { data })
+    }
+
+    fn control(&self, command: MediaCommand) -> ControlFuture<'_> {
+        Box::pin(MpdSource::control(self, command))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    fn response(lines: &[(&str, &str)]) -> Response {
+        lines.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    // Plays MPD: greets, then expects each command and answers with the given lines
+    async fn stand_in(stream: DuplexStream, script: Vec<(&'static str, &'static str)>) {
+        let mut stream = BufReader::new(stream);
+        stream.get_mut().write_all(b"OK MPD 0.23.5\n").await.unwrap();
+        for (command, answer) in script {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            assert_eq!(line.trim_end(), command);
+            stream.get_mut().write_all(answer.as_bytes()).await.unwrap();
+        }
+    }
+
+    async fn connect(script: Vec<(&'static str, &'static str)>, password: Option<&str>) -> (UnyoResult<MpdConnection>, tokio::task::JoinHandle<()>) {
+        let (client, server) = duplex(4096);
+        let server = tokio::spawn(stand_in(server, script));
+        (MpdConnection::handshake(Box::new(client), password).await, server)
+    }
+
+    #[test]
+    fn password_is_quoted() {
+        assert_eq!(quote("geheim"), r#""geheim""#);
+        assert_eq!(quote(r#"sag "hallo""#), r#""sag \"hallo\"""#);
+        assert_eq!(quote(r"ende\"), r#""ende\\""#);
+    }
+
+    #[test]
+    fn playing_song() {
+        let status = response(&[("state", "play"), ("elapsed", "12.5"), ("duration", "201.3"), ("song", "2"), ("playlistlength", "9")]);
+        let song = response(&[("file", "Ton Steine Scherben/Keine Macht für Niemand/01.flac"), ("Title", "Macht kaputt was euch kaputt macht"),
+                              ("Artist", "Ton Steine Scherben"), ("duration", "200.000")]);
+        let data = playback_data(&status, &song).unwrap();
+        assert_eq!(data.title, "Macht kaputt was euch kaputt macht");
+        assert_eq!(data.artist, "Ton Steine Scherben");
+        assert_eq!(data.playback_state, PlaybackState::Playing);
+        assert_eq!(data.duration, 201_300);
+        assert_eq!(data.queue_position, Some((3, 9)));
+    }
+
+    #[test]
+    fn stream_without_tags() {
+        let status = response(&[("state", "pause")]);
+        let song = response(&[("file", "http://radio.example/live.mp3"), ("Name", "Radio Eins")]);
+        let data = playback_data(&status, &song).unwrap();
+        assert_eq!((data.title.as_str(), data.artist.as_str()), ("Radio Eins", "Unknown"));
+        assert_eq!(data.playback_state, PlaybackState::Paused);
+        assert_eq!((data.duration, data.queue_position), (0, None));
+
+        let data = playback_data(&response(&[("state", "stop")]), &response(&[("file", "Musik/track.ogg")])).unwrap();
+        assert_eq!(data.title, "track.ogg");
+        assert_eq!(data.playback_state, PlaybackState::Stopped);
+    }
+
+    #[test]
+    fn nothing_queued() {
+        assert!(playback_data(&response(&[("state", "stop")]), &Response::new()).is_none());
+    }
+
+    #[tokio::test]
+    async fn idle_reports_changes() {
+        let (connection, server) = connect(vec![
+            (r#"password "a\\b""#, "OK\n"),
+            ("idle player playlist options", "changed: player\nchanged: playlist\nOK\n"),
+        ], Some(r"a\b")).await;
+        let changed = connection.unwrap().command("idle player playlist options").await.unwrap();
+        // The first of several lines with the same key
+        assert_eq!(changed.get("changed").map(String::as_str), Some("player"));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn ack_is_an_error() {
+        let (connection, server) = connect(vec![("password \"falsch\"", "ACK [3@0] {password} incorrect password\n")], Some("falsch")).await;
+        assert!(connection.is_err());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn status_and_song() {
+        let (connection, server) = connect(vec![
+            ("status", "volume: 80\nstate: play\nsong: 0\nplaylistlength: 1\nelapsed: 3.000\nduration: 60.000\nOK\n"),
+            ("currentsong", "file: a.flac\nTitle: Eins\nArtist: Zwei\nOK\n"),
+        ], None).await;
+        let data = connection.unwrap().playback_data().await.unwrap().unwrap();
+        assert_eq!((data.title.as_str(), data.artist.as_str(), data.duration), ("Eins", "Zwei", 60_000));
+        assert_eq!(data.queue_position, Some((1, 1)));
+        server.await.unwrap();
+    }
+}
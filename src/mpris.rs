@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use percent_encoding::percent_decode_str;
+use zbus::{Connection, Proxy};
+use zvariant::OwnedValue;
+use crate::cover_art::CoverArtSource;
+use crate::errors::UnyoError;
+use crate::media::{ControlFuture, MediaCommand, MediaSource, PlaybackData, PlaybackState, PollFuture, SourceDevice};
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+
+type Properties = HashMap<String, OwnedValue>;
+
+/// Local players (spotifyd, librespot, mpv, VLC, ...) over MPRIS2
+#[derive(Debug)]
+pub struct MprisSource {
+    connection: Connection,
+    // Bus name of the player shown last, target of transport controls
+    player: Mutex<Option<String>>,
+    // Properties proxy of each player seen in the last poll, by bus name
+    proxies: Mutex<HashMap<String, Proxy<'static>>>,
+}
+
+fn get_string(properties: &Properties, name: &str) -> Option<String> {
+    properties.get(name).and_then(|v| v.downcast_ref::<String>().ok())
+}
+
+// MPRIS times are in µs, some players use unsigned, some signed integers
+fn get_micros(properties: &Properties, name: &str) -> Option<u32> {
+    let value = properties.get(name)?;
+    let micros = value.downcast_ref::<i64>().ok()
+        .or_else(|| value.downcast_ref::<u64>().ok().map(|v| v as i64))?;
+    Some((micros.max(0) / 1000) as u32)
+}
+
+// Local path of an `mpris:artUrl`, which is percent-encoded like any URL
+fn art_path(url: &str) -> Option<String> {
+    let path = url.strip_prefix("file://")?;
+    Some(percent_decode_str(path).decode_utf8_lossy().into_owned())
+}
+
+impl MprisSource {
+    pub async fn new() -> zbus::Result<Self> {
+        Ok(Self::with_connection(Connection::session().await?))
+    }
+
+    pub fn with_connection(connection: Connection) -> Self {
+        Self {connection, player: Mutex::new(None), proxies: Mutex::new(HashMap::new())}
+    }
+
+    async fn player_names(&self) -> zbus::Result<Vec<String>> {
+        let dbus = Proxy::new(&self.connection, "org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus").await?;
+        let names: Vec<String> = dbus.call("ListNames", &()).await?;
+        Ok(names.into_iter().filter(|name| name.starts_with(MPRIS_PREFIX)).collect())
+    }
+
+    async fn get_all(&self, name: &str, interface: &str) -> zbus::Result<Properties> {
+        // Not the properties cache of a player proxy, Position is never announced through PropertiesChanged
+        let cached = self.proxies.lock().unwrap().get(name).cloned();
+        let properties = match cached {
+            Some(properties) => properties,
+            None => {
+                let properties = Proxy::new(&self.connection, name.to_string(), MPRIS_PATH, "org.freedesktop.DBus.Properties").await?;
+                self.proxies.lock().unwrap().insert(name.to_string(), properties.clone());
+                properties
+            }
+        };
+        properties.call("GetAll", &(interface,)).await
+    }
+
+    async fn player_data(&self, name: &str) -> zbus::Result<Option<PlaybackData>> {
+        let player = self.get_all(name, "org.mpris.MediaPlayer2.Player").await?;
+        let Some(metadata) = player.get("Metadata") else { return Ok(None) };
+        let metadata: Properties = metadata.try_clone()?.try_into()?;
+
+        let Some(title) = get_string(&metadata, "xesam:title") else { return Ok(None) };
+        let artist = metadata.get("xesam:artist")
+            .and_then(|v| v.try_clone().ok())
+            .and_then(|v| Vec::<String>::try_from(v).ok())
+            .map(|artists| artists.join(", "))
+            .unwrap_or("Unknown".to_string());
+        let duration = get_micros(&metadata, "mpris:length").unwrap_or(0);
+        let position = get_micros(&player, "Position").unwrap_or(0);
+        let state = get_string(&player, "PlaybackStatus").map(PlaybackState::from).unwrap_or(PlaybackState::Stopped);
+
+        let identity = self.get_all(name, "org.mpris.MediaPlayer2").await.ok()
+            .and_then(|root| get_string(&root, "Identity"))
+            .unwrap_or(name.trim_start_matches(MPRIS_PREFIX).to_string());
+
+        let mut data = PlaybackData::from((title, artist, state, position, duration));
+        data.cover_art = get_string(&metadata, "mpris:artUrl")
+            .and_then(|url| art_path(&url))
+            .map(CoverArtSource::File);
+        data.device = Some(SourceDevice {name: identity, battery: None});
+        Ok(Some(data))
+    }
+
+    /// The most relevant player (playing before paused before stopped)
+    pub async fn poll(&self) -> Option<PlaybackData> {
+        let mut best: Option<(String, PlaybackData)> = None;
+        let names = self.player_names().await.ok()?;
+        // Players that quit
+        self.proxies.lock().unwrap().retain(|name, _| names.contains(name));
+        for name in names {
+            if let Ok(Some(data)) = self.player_data(&name).await {
+                if best.as_ref().is_none_or(|(_, b)| data.playback_state.rank() < b.playback_state.rank()) {
+                    best = Some((name, data));
+                }
+            }
+        }
+        *self.player.lock().unwrap() = best.as_ref().map(|(name, _)| name.clone());
+        best.map(|(_, data)| data)
+    }
+
+    async fn control(&self, command: MediaCommand) -> zbus::Result<()> {
+        let Some(name) = self.player.lock().unwrap().clone() else { return Ok(()) };
+        let player = Proxy::new(&self.connection, name, MPRIS_PATH, "org.mpris.MediaPlayer2.Player").await?;
+        let method = match command {
+            MediaCommand::PlayPause => "PlayPause",
+            MediaCommand::Next => "Next",
+            MediaCommand::Previous => "Previous",
+        };
+        player.call(method, &()).await
+    }
+}
+
+impl MediaSource for MprisSource {
+    fn name(&self) -> &'static str {
+        "mpris"
+    }
+
+    fn poll(&self) -> PollFuture<'_> {
+        Box::pin(MprisSource::poll(self))
+    }
+
+    fn control(&self, command: MediaCommand) -> ControlFuture<'_> {
+        Box::pin(async move {
+            MprisSource::control(self, command).await.map_err(|e| UnyoError::DBus(e.to_string()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn art_url_is_decoded() {
+        assert_eq!(art_path("file:///home/pi/Musik/Gr%C3%B6nemeyer/Cover%20Art.jpg").as_deref(), Some("/home/pi/Musik/Grönemeyer/Cover Art.jpg"));
+        assert_eq!(art_path("file:///tmp/a+b.png").as_deref(), Some("/tmp/a+b.png"));
+        assert_eq!(art_path("https://i.scdn.co/image/ab67616d"), None);
+    }
+}
@@ -0,0 +1,378 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::rect::{Point, Rect};
+use crate::color::{BG_DARKEST, BG_TINTED, TXT_DEFAULT};
+use crate::config::{PageTransition, PagesConfig};
+use crate::gestures::{Gesture, Swipe};
+use crate::media::{PlaybackData, PlaybackState};
+use crate::redraw::Redraw;
+use crate::state::{Store, Subscription};
+use crate::ui_renderer::{pointer_position, Drawable, Handled, Layout, Length, UIContext, UIHelper, EDGE_PADDING};
+use crate::widget_registry::WidgetRegistry;
+
+struct Page {
+    // Shown while something is playing
+    media: bool,
+    widgets: Vec<Box<dyn Drawable>>,
+}
+
+impl Page {
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        for widget in &self.widgets {
+            ctx.draw(widget.as_ref(), uihelper);
+        }
+    }
+
+    fn widget_at(&mut self, point: Point) -> Option<&mut Box<dyn Drawable>> {
+        self.widgets.iter_mut().rev().find(|widget| widget.hit_test(point))
+    }
+}
+
+/// The pages of the main screen, switched by swipes, arrow / page keys or on a schedule,
+/// with a row of dots at the bottom showing which one is shown. Swipes and arrow keys past
+/// the first or last page go on to the other screens
+pub struct PageManager {
+    position: Rect,
+    indicator: Rect,
+    pages: Vec<Page>,
+    current: usize,
+    // Page being left, direction (1 = next, -1 = previous) and when the transition started
+    transition: Option<(usize, i32, Instant)>,
+    transition_kind: PageTransition,
+    transition_duration: Duration,
+    cycle: Option<Duration>,
+    pin_media: bool,
+    shown_since: Instant,
+    was_playing: bool,
+    playback: Subscription<Option<PlaybackData>>,
+    // The page changed and hasn't been drawn yet
+    dirty: Cell<bool>,
+}
+
+impl PageManager {
+    pub fn new(position: Rect, config: &PagesConfig, registry: &WidgetRegistry, store: &Store) -> Self {
+        // The dots only take room if there is something to switch to
+        let indicator_height = if config.pages.len() > 1 { Length::Ep(3.0) } else { Length::Px(0) };
+        let placement = Layout::column(vec![
+            Layout::widget("page"),
+            Layout::widget("indicator").height(indicator_height),
+        ]).resolve(position);
+        let pages = config.pages.iter()
+            .map(|page| Page {media: page.media, widgets: registry.build(&page.screen, placement.rect("page"), store)})
+            .collect();
+        Self {
+            position,
+            indicator: placement.rect("indicator"),
+            pages,
+            current: 0,
+            transition: None,
+            transition_kind: config.transition,
+            transition_duration: config.transition_duration,
+            cycle: config.cycle,
+            pin_media: config.pin_media,
+            shown_since: Instant::now(),
+            was_playing: false,
+            playback: store.playback.subscribe(),
+            dirty: Cell::new(false),
+        }
+    }
+
+    /// Switches to page `index`, sliding in from the side of `direction`
+    pub fn show(&mut self, index: usize, direction: i32) {
+        if index == self.current || index >= self.pages.len() {
+            return;
+        }
+        if self.transition_kind != PageTransition::None {
+            self.transition = Some((self.current, direction, Instant::now()));
+        }
+        self.current = index;
+        self.shown_since = Instant::now();
+        self.dirty.set(true);
+    }
+
+    /// The page `step` pages further, wrapping around
+    pub fn step(&mut self, step: i32) {
+        let index = (self.current as i32 + step).rem_euclid(self.pages.len() as i32) as usize;
+        self.show(index, step.signum());
+    }
+
+    // Like `step`, but past the first or last page it is left to the screens
+    fn turn(&mut self, step: i32) -> Handled {
+        let index = self.current as i32 + step;
+        if index < 0 || index >= self.pages.len() as i32 {
+            return Handled::No;
+        }
+        self.show(index as usize, step.signum());
+        Handled::Yes
+    }
+
+    fn media_page(&self) -> Option<usize> {
+        self.pages.iter().position(|page| page.media)
+    }
+
+    // Page being left and how far the transition is (0 - 1)
+    fn transition_progress(&self) -> Option<(usize, i32, f32)> {
+        let (from, direction, since) = self.transition?;
+        let progress = since.elapsed().as_secs_f32() / self.transition_duration.as_secs_f32().max(0.001);
+        (progress < 1.0).then_some((from, direction, progress))
+    }
+
+    fn draw_indicator(&self, ctx: &mut UIContext) {
+        if self.pages.len() < 2 {
+            return;
+        }
+        let (size, gap) = (EDGE_PADDING(), EDGE_PADDING());
+        let width = self.pages.len() as i32 * (size + gap) - gap;
+        let x = self.indicator.x + (self.indicator.w - width) / 2;
+        let y = self.indicator.y + (self.indicator.h - size) / 2;
+        for i in 0..self.pages.len() {
+            let color = if i == self.current { TXT_DEFAULT } else { BG_TINTED };
+            ctx.draw_rect(Rect::new(x + i as i32 * (size + gap), y, size as u32, size as u32), color);
+        }
+    }
+}
+
+impl Drawable for PageManager {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        self.dirty.set(false);
+        let current = &self.pages[self.current];
+        match self.transition_progress() {
+            Some((from, direction, progress)) if self.transition_kind == PageTransition::Slide => {
+                let offset = (progress * self.position.w as f32) as i32 * direction;
+                ctx.set_offset(-offset, 0);
+                self.pages[from].draw(ctx, uihelper);
+                ctx.set_offset(direction * self.position.w - offset, 0);
+                current.draw(ctx, uihelper);
+                ctx.set_offset(0, 0);
+            }
+            // Through the background: the old page fades out, then the new one in
+            Some((from, _, progress)) => {
+                let page = if progress < 0.5 { &self.pages[from] } else { current };
+                page.draw(ctx, uihelper);
+                let alpha = (1.0 - (progress * 2.0 - 1.0).abs()) * 255.0;
+                ctx.fade_rect(self.position, BG_DARKEST, alpha as u8);
+            }
+            None => current.draw(ctx, uihelper)
+        }
+        self.draw_indicator(ctx);
+    }
+
+    fn redraw(&self) -> Redraw {
+        if self.transition_progress().is_some() {
+            return Redraw::Animating;
+        }
+        let widgets = self.pages[self.current].widgets.iter().map(|widget| widget.redraw()).min().unwrap_or(Redraw::Idle);
+        match self.cycle.filter(|_| self.pages.len() > 1) {
+            Some(cycle) => widgets.min(Redraw::At(self.shown_since + cycle)),
+            None => widgets
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.dirty.get() || self.pages[self.current].widgets.iter().any(|widget| widget.changed())
+    }
+
+    fn update(&mut self, dt: Duration) {
+        // One more frame without the old page
+        if self.transition.is_some() && self.transition_progress().is_none() {
+            self.transition = None;
+            self.dirty.set(true);
+        }
+        for widget in &mut self.pages[self.current].widgets {
+            widget.update(dt);
+        }
+
+        let playing = self.playback.peek().as_ref().is_some_and(|data| data.playback_state == PlaybackState::Playing);
+        let media_page = self.media_page().filter(|_| self.pin_media);
+        // Playback started
+        if let (Some(media_page), true, false) = (media_page, playing, self.was_playing) {
+            self.show(media_page, if media_page > self.current { 1 } else { -1 });
+        }
+        self.was_playing = playing;
+
+        // While playing the schedule leads back to the media page, not on
+        if self.cycle.is_some_and(|cycle| self.shown_since.elapsed() >= cycle) {
+            match media_page.filter(|_| playing) {
+                Some(media_page) if media_page != self.current => self.show(media_page, if media_page > self.current { 1 } else { -1 }),
+                Some(_) => self.shown_since = Instant::now(),
+                None => self.step(1)
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event) -> Handled {
+        if let Some(point) = pointer_position(event) {
+            return match self.pages[self.current].widget_at(point) {
+                Some(widget) => widget.handle_event(event),
+                None => Handled::No
+            };
+        }
+        if self.pages.len() < 2 {
+            return Handled::No;
+        }
+        match *event {
+            Event::KeyDown { keycode: Some(Keycode::Left), .. } => self.turn(-1),
+            Event::KeyDown { keycode: Some(Keycode::Right), .. } => self.turn(1),
+            // What remotes usually send, they only page through the pages
+            Event::KeyDown { keycode: Some(Keycode::PageUp | Keycode::AcBack), .. } => {
+                self.step(-1);
+                Handled::Yes
+            }
+            Event::KeyDown { keycode: Some(Keycode::PageDown | Keycode::AcForward), .. } => {
+                self.step(1);
+                Handled::Yes
+            }
+            _ => Handled::No
+        }
+    }
+
+    fn handle_gesture(&mut self, gesture: &Gesture) -> Handled {
+        if let Some(widget) = self.pages[self.current].widget_at(gesture.position()) {
+            if widget.handle_gesture(gesture) == Handled::Yes {
+                return Handled::Yes;
+            }
+        }
+        match gesture {
+            Gesture::Swipe(Swipe::Left, _) => self.turn(1),
+            Gesture::Swipe(Swipe::Right, _) => self.turn(-1),
+            _ => Handled::No
+        }
+    }
+
+    fn focusable(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::config::{PageConfig, ScreenNode};
+    use super::*;
+
+    // Empty pages, the media page is `media`
+    fn pages(count: usize, media: Option<usize>, cycle: Option<Duration>, store: &Store) -> PageManager {
+        let pages = (0..count).map(|i| PageConfig {
+            name: i.to_string(),
+            media: Some(i) == media,
+            screen: ScreenNode::from_json(&json!({"type": "row"})).unwrap(),
+        }).collect();
+        let config = PagesConfig {pages, transition: PageTransition::Slide, transition_duration: Duration::from_millis(350), cycle, pin_media: true};
+        PageManager::new(Rect::new(0, 0, 1280, 720), &config, &WidgetRegistry::new(), store)
+    }
+
+    fn swipe(direction: Swipe) -> Gesture {
+        Gesture::Swipe(direction, Point::new(600, 300))
+    }
+
+    fn play(store: &Store, state: PlaybackState) {
+        store.playback.set(Some(PlaybackData::new("Kids".to_string(), "MGMT".to_string(), state, 0, 302_000)));
+    }
+
+    // As if the page was shown `duration` ago
+    fn age(manager: &mut PageManager, duration: Duration) {
+        manager.shown_since -= duration;
+    }
+
+    #[test]
+    fn stepping_wraps_around() {
+        let store = Store::new();
+        let mut manager = pages(3, None, None, &store);
+        manager.step(1);
+        assert_eq!(manager.current, 1);
+        manager.step(-2);
+        assert_eq!(manager.current, 2);
+        manager.step(1);
+        assert_eq!(manager.current, 0);
+    }
+
+    #[test]
+    fn swipes_stop_at_the_ends() {
+        let store = Store::new();
+        let mut manager = pages(2, None, None, &store);
+        assert_eq!(manager.handle_gesture(&swipe(Swipe::Right)), Handled::No);
+        assert_eq!(manager.handle_gesture(&swipe(Swipe::Left)), Handled::Yes);
+        assert_eq!(manager.current, 1);
+        // Left to the screens
+        assert_eq!(manager.handle_gesture(&swipe(Swipe::Left)), Handled::No);
+        assert_eq!(manager.current, 1);
+    }
+
+    #[test]
+    fn cycles_on_schedule() {
+        let store = Store::new();
+        let cycle = Duration::from_secs(30);
+        let mut manager = pages(3, None, Some(cycle), &store);
+        age(&mut manager, cycle - Duration::from_secs(1));
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 0);
+        age(&mut manager, Duration::from_secs(1));
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 1);
+        assert!(matches!(manager.redraw(), Redraw::Animating));
+        // Around again
+        for _ in 0..2 {
+            age(&mut manager, cycle);
+            manager.update(Duration::ZERO);
+        }
+        assert_eq!(manager.current, 0);
+    }
+
+    #[test]
+    fn pinned_to_media_page() {
+        let store = Store::new();
+        let cycle = Duration::from_secs(30);
+        let mut manager = pages(3, Some(2), Some(cycle), &store);
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 0);
+
+        // Playback starts
+        play(&store, PlaybackState::Playing);
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 2);
+
+        // A swipe still leaves it, until the schedule leads back
+        manager.handle_gesture(&swipe(Swipe::Right));
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 1);
+        age(&mut manager, cycle);
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 2);
+        // And stays there
+        age(&mut manager, cycle);
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 2);
+
+        // Paused, the schedule goes on again
+        play(&store, PlaybackState::Paused);
+        age(&mut manager, cycle);
+        manager.update(Duration::ZERO);
+        assert_eq!(manager.current, 0);
+    }
+
+    #[test]
+    fn transition_ends() {
+        let store = Store::new();
+        let mut manager = pages(2, None, None, &store);
+        manager.step(1);
+        assert_eq!(manager.transition_progress().map(|(from, direction, _)| (from, direction)), Some((0, 1)));
+        manager.dirty.set(false);
+        manager.update(Duration::ZERO);
+        assert!(manager.transition.is_some());
+
+        // Over, one more frame without the old page
+        let (from, direction, since) = manager.transition.unwrap();
+        manager.transition = Some((from, direction, since - manager.transition_duration));
+        assert!(manager.transition_progress().is_none());
+        manager.update(Duration::ZERO);
+        assert!(manager.transition.is_none());
+        assert!(manager.changed());
+        assert!(matches!(manager.redraw(), Redraw::Idle));
+    }
+}
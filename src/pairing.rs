@@ -0,0 +1,215 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
+use zbus::{interface, Connection, Proxy};
+use zvariant::{OwnedObjectPath, Value};
+use crate::config::CONFIG;
+use crate::state::Store;
+
+const AGENT_PATH: &str = "/unyo/agent";
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Taken by the first answer
+type Responder = Arc<Mutex<Option<oneshot::Sender<bool>>>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AgentMode {
+    // Accept everything without asking
+    AutoAccept,
+    // Show the passkey, the user has to accept / reject on screen
+    Confirm,
+    // Only show the passkey / PIN
+    PinDisplay,
+}
+
+impl FromStr for AgentMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto-accept" => Ok(AgentMode::AutoAccept),
+            "confirm" => Ok(AgentMode::Confirm),
+            "pin-display" => Ok(AgentMode::PinDisplay),
+            _ => Err(format!("Unknown agent mode: {value}"))
+        }
+    }
+}
+
+impl AgentMode {
+    fn capability(&self) -> &'static str {
+        match self {
+            AgentMode::AutoAccept => "NoInputNoOutput",
+            AgentMode::Confirm => "DisplayYesNo",
+            AgentMode::PinDisplay => "DisplayOnly",
+        }
+    }
+}
+
+/// What is shown in the pairing overlay
+#[derive(Debug)]
+pub struct PairingRequest {
+    pub device: String,
+    pub code: Option<String>,
+    // Only set if the user has to accept / reject
+    responder: Option<Responder>,
+    pub expires: Instant,
+}
+
+impl PairingRequest {
+    fn new(device: String, code: Option<String>, responder: Option<oneshot::Sender<bool>>) -> Self {
+        let responder = responder.map(|responder| Arc::new(Mutex::new(Some(responder))));
+        Self {device, code, responder, expires: Instant::now() + CONFIRMATION_TIMEOUT}
+    }
+
+    pub fn needs_response(&self) -> bool {
+        self.responder.is_some()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        Instant::now() > self.expires
+    }
+}
+
+/// Answers the current pairing request (accept or reject) and hides it
+pub fn respond_pairing(store: &Store, accept: bool) {
+    let Some(request) = &*store.pairing.get() else { return };
+    if let Some(responder) = request.responder.as_ref().and_then(|responder| responder.lock().unwrap().take()) {
+        let _ = responder.send(accept);
+    }
+    store.pairing.set(None);
+}
+
+// Random six digits for legacy PIN / passkey pairing, the hasher keys are seeded by the OS
+fn random_passkey() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+    (hasher.finish() % 1_000_000) as u32
+}
+
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.bluez.Error")]
+enum AgentError {
+    Rejected(String),
+    Canceled(String),
+}
+
+struct Agent {
+    mode: AgentMode,
+    connection: Connection,
+    store: Arc<Store>,
+}
+
+impl Agent {
+    async fn device_alias(&self, device: &OwnedObjectPath) -> String {
+        let alias = async {
+            let proxy = Proxy::new(&self.connection, "org.bluez", device, "org.bluez.Device1").await?;
+            proxy.get_property::<String>("Alias").await
+        };
+        alias.await.unwrap_or(device.to_string())
+    }
+
+    fn show(&self, device: String, code: Option<String>) {
+        self.store.pairing.set(Some(PairingRequest::new(device, code, None)));
+    }
+
+    /// Asks the user (in confirm mode) and waits for the answer
+    async fn confirm(&self, device: &OwnedObjectPath, code: Option<String>) -> Result<(), AgentError> {
+        if self.mode != AgentMode::Confirm {
+            if code.is_some() {
+                self.show(self.device_alias(device).await, code);
+            }
+            return Ok(());
+        }
+
+        let (responder, response) = oneshot::channel();
+        let device = self.device_alias(device).await;
+        self.store.pairing.set(Some(PairingRequest::new(device, code, Some(responder))));
+
+        match tokio::time::timeout(CONFIRMATION_TIMEOUT, response).await {
+            Ok(Ok(true)) => Ok(()),
+            Ok(_) => Err(AgentError::Rejected("Rejected by user".to_string())),
+            Err(_) => {
+                self.store.pairing.set(None);
+                Err(AgentError::Canceled("No response".to_string()))
+            }
+        }
+    }
+}
+
+#[interface(name = "org.bluez.Agent1")]
+impl Agent {
+    fn release(&self) {
+        self.store.pairing.set(None);
+    }
+
+    // The code has to be entered on the device, so it is always shown
+    async fn request_pin_code(&self, device: OwnedObjectPath) -> Result<String, AgentError> {
+        let pincode = format!("{:06}", random_passkey());
+        self.confirm(&device, Some(pincode.clone())).await?;
+        Ok(pincode)
+    }
+
+    async fn display_pin_code(&self, device: OwnedObjectPath, pincode: String) {
+        self.show(self.device_alias(&device).await, Some(pincode));
+    }
+
+    async fn request_passkey(&self, device: OwnedObjectPath) -> Result<u32, AgentError> {
+        let passkey = random_passkey();
+        self.confirm(&device, Some(format!("{passkey:06}"))).await?;
+        Ok(passkey)
+    }
+
+    async fn display_passkey(&self, device: OwnedObjectPath, passkey: u32, _entered: u16) {
+        self.show(self.device_alias(&device).await, Some(format!("{passkey:06}")));
+    }
+
+    async fn request_confirmation(&self, device: OwnedObjectPath, passkey: u32) -> Result<(), AgentError> {
+        self.confirm(&device, Some(format!("{passkey:06}"))).await
+    }
+
+    async fn request_authorization(&self, device: OwnedObjectPath) -> Result<(), AgentError> {
+        self.confirm(&device, None).await
+    }
+
+    fn authorize_service(&self, _device: OwnedObjectPath, _uuid: String) {}
+
+    fn cancel(&self) {
+        self.store.pairing.set(None);
+    }
+}
+
+/// Applies alias / discoverability from the config to the adapter
+async fn setup_adapter(connection: &Connection) -> zbus::Result<()> {
+    let config = &CONFIG().bluetooth;
+    let adapter_path = format!("/org/bluez/{}", config.adapter);
+    let properties = Proxy::new(connection, "org.bluez", adapter_path.as_str(), "org.freedesktop.DBus.Properties").await?;
+
+    let values: [(&str, Value); 5] = [
+        ("Alias", config.alias.as_str().into()),
+        ("Pairable", config.pairable.into()),
+        ("DiscoverableTimeout", config.discoverable_timeout.into()),
+        // BlueZ resets Discoverable after the timeout
+        ("Discoverable", config.discoverable.into()),
+        ("PairableTimeout", 0u32.into()),
+    ];
+    for (name, value) in values {
+        properties.call::<_, _, ()>("Set", &("org.bluez.Adapter1", name, value)).await?;
+    }
+    Ok(())
+}
+
+pub async fn init_pairing(connection: &Connection, store: Arc<Store>) -> zbus::Result<()> {
+    setup_adapter(connection).await?;
+
+    let mode = CONFIG().bluetooth.agent;
+    connection.object_server().at(AGENT_PATH, Agent {mode, connection: connection.clone(), store}).await?;
+
+    let agent_manager = Proxy::new(connection, "org.bluez", "/org/bluez", "org.bluez.AgentManager1").await?;
+    let agent_path = OwnedObjectPath::try_from(AGENT_PATH)?;
+    agent_manager.call::<_, _, ()>("RegisterAgent", &(&agent_path, mode.capability())).await?;
+    agent_manager.call::<_, _, ()>("RequestDefaultAgent", &(&agent_path,)).await?;
+    Ok(())
+}
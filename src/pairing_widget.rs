@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::rect::{Point, Rect};
+use crate::color::{BG_DARKEST, BG_SHADED, DIV_LINE, PB_EMPTY, PB_FULLY, TXT_DEFAULT, TXT_SUBTEXT};
+use crate::gestures::Gesture;
+use crate::pairing::{respond_pairing, PairingRequest};
+use crate::redraw::Redraw;
+use crate::state::{Store, Subscription};
+use crate::ui_renderer::{Drawable, Handled, Layout, Length, UIContext, UIHelper, EDGE_PADDING};
+
+pub struct PairingOverlay {
+    position: Rect,
+    // Accept, Reject
+    buttons: (Rect, Rect),
+    // The current press went to the overlay, so do its gestures
+    pressed: bool,
+    request: Subscription<Option<PairingRequest>>,
+    // To answer the request
+    store: Arc<Store>,
+}
+
+impl PairingOverlay {
+    pub fn new(position: Rect, store: Arc<Store>) -> Self {
+        let placement = Layout::column(vec![
+            Layout::leaf(),
+            Layout::row(vec![Layout::leaf(), Layout::leaf()]).named("buttons").height(Length::Ep(8.0)).spacing(2 * EDGE_PADDING()),
+        ]).padding(2 * EDGE_PADDING()).resolve(position);
+        let buttons = placement.children("buttons");
+        Self {position, buttons: (buttons[0], buttons[1]), pressed: false, request: store.pairing.subscribe(), store}
+    }
+
+    // While shown every tap goes to the overlay, outside the buttons it dismisses a notice
+    fn handle_click(&self, point: Point, needs_response: bool) {
+        let (accept, reject) = self.buttons;
+        if needs_response && accept.contains_point(point) {
+            respond_pairing(&self.store, true);
+        } else if !needs_response || reject.contains_point(point) {
+            respond_pairing(&self.store, false);
+        }
+    }
+}
+
+fn draw_button(ctx: &mut UIContext, uihelper: &UIHelper, rect: Rect, text: &str, color: sdl2::pixels::Color) {
+    ctx.draw_rect(rect, color);
+    let size = uihelper.font_owner.jb_medium_l.size_of_text(text);
+    ctx.draw_text(rect.x + (rect.w - size.one() as i32) / 2, rect.y + (rect.h - size.two() as i32) / 2,
+                  &uihelper.font_owner.jb_medium_l, text, BG_DARKEST, uihelper);
+}
+
+impl Drawable for PairingOverlay {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        if let Some(request) = &*self.request.get() {
+            if request.is_expired() {
+                return;
+            }
+
+            let xp = self.position.x + 2 * EDGE_PADDING();
+            ctx.draw_rect(self.position, BG_SHADED);
+            ctx.draw_line(self.position.top_left(), self.position.top_right(), EDGE_PADDING() / 2, DIV_LINE);
+
+            let (_, y) = ctx.draw_text(xp, self.position.y + 2 * EDGE_PADDING(), &uihelper.font_owner.jb_medium_l, "Kopplungsanfrage", TXT_DEFAULT, uihelper);
+            let y = y + 2 * uihelper.font_owner.jb_medium_l.char_dim().two() as i32;
+            ctx.draw_text(xp, y, &uihelper.font_owner.jb_medium_m, request.device.as_str(), TXT_SUBTEXT, uihelper);
+
+            if let Some(code) = &request.code {
+                let y = y + 2 * uihelper.font_owner.jb_medium_m.char_dim().two() as i32;
+                ctx.draw_text(xp, y, &uihelper.font_owner.jb_large_s, code.as_str(), TXT_DEFAULT, uihelper);
+            }
+
+            if request.needs_response() {
+                let (accept, reject) = self.buttons;
+                draw_button(ctx, uihelper, accept, "Annehmen", PB_FULLY);
+                draw_button(ctx, uihelper, reject, "Ablehnen", PB_EMPTY);
+            }
+        }
+    }
+
+    // Modal while there is a request: Return accepts, Escape rejects / dismisses. Taps answer
+    // it, the pointer events themselves only stay here
+    fn handle_event(&mut self, event: &Event) -> Handled {
+        let shown = self.request.peek().as_ref().is_some_and(|request| !request.is_expired());
+        if matches!(event, Event::MouseButtonDown { .. }) {
+            self.pressed = shown;
+        }
+        if !shown {
+            return Handled::No;
+        }
+        match *event {
+            Event::MouseButtonDown { .. } | Event::MouseButtonUp { .. } | Event::MouseMotion { .. } => {}
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } => respond_pairing(&self.store, true),
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => respond_pairing(&self.store, false),
+            _ => return Handled::No
+        }
+        Handled::Yes
+    }
+
+    // A tap answers it, nothing gets through while it is shown or from a press that started on it
+    fn handle_gesture(&mut self, gesture: &Gesture) -> Handled {
+        let needs_response = match &*self.request.peek() {
+            Some(request) if !request.is_expired() => request.needs_response(),
+            _ if self.pressed => return Handled::Yes,
+            _ => return Handled::No
+        };
+        if let Gesture::Tap(point) = *gesture {
+            self.handle_click(point, needs_response);
+        }
+        Handled::Yes
+    }
+
+    fn changed(&self) -> bool {
+        self.request.changed()
+    }
+
+    fn redraw(&self) -> Redraw {
+        // Hide it once it expired
+        match &*self.request.peek() {
+            Some(request) if !request.is_expired() => Redraw::At(request.expires),
+            _ => Redraw::Idle
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use zvariant::OwnedObjectPath;
+use crate::api::{set_weather_info, WeatherInfo};
+use crate::bluetooth::{set_devices, set_transport, BluetoothDevice, MediaTransport};
+use crate::clock;
+use crate::media::{set_playback_data, PlaybackData, PlaybackState, SourceDevice};
+use crate::redraw::request_redraw;
+use crate::state::Store;
+use crate::wifi_api::{set_wifi_strength, WifiSignalBars};
+
+const CLOCK_SCHEDULE_RECORD: Duration = Duration::from_secs(1);
+// Names of the media sources, `PlaybackData::source` is static
+const SOURCE_NAMES: [&str; 5] = ["bluetooth", "mpris", "mpd", "airplay", "simulation"];
+
+static _RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+struct Recorder {
+    file: BufWriter<File>,
+    start: Instant,
+}
+
+/// Output of a data source, one line `{"t": <ms since start>, "type": .., "data": ..}` in the log
+pub enum Event {
+    // Boxed, both are far larger than the rest
+    Weather(Option<Box<WeatherInfo>>),
+    // Cover art is a path / handle on the recording device, it is left out
+    Playback(Option<Box<PlaybackData>>),
+    Wifi(WifiSignalBars),
+    Devices(Vec<BluetoothDevice>),
+    // Without the time of the last volume change, replaying sets it again
+    Transport(Option<MediaTransport>),
+    Clock(DateTime<Local>),
+}
+
+fn state_name(state: PlaybackState) -> &'static str {
+    match state {
+        PlaybackState::Playing => "playing",
+        PlaybackState::Paused => "paused",
+        PlaybackState::Stopped => "stopped"
+    }
+}
+
+fn wifi_level(strength: WifiSignalBars) -> u64 {
+    match strength {
+        WifiSignalBars::NoSignal => 0,
+        WifiSignalBars::Weak => 1,
+        WifiSignalBars::Fair => 2,
+        WifiSignalBars::Good => 3,
+        WifiSignalBars::Excellent => 4
+    }
+}
+
+fn weather_to_json(info: &WeatherInfo) -> Value {
+    json!({
+        "city": info.city,
+        "is_day": info.is_day,
+        "current": [info.current.0, info.current.1, info.current.2],
+        "daily": info.daily.iter().map(|d| json!([d.0, d.1, d.2, d.3])).collect::<Vec<_>>(),
+        "hourly": info.hourly.iter().map(|h| json!([h.0, h.1, h.2, h.3])).collect::<Vec<_>>(),
+    })
+}
+
+fn weather_from_json(value: &Value) -> Option<WeatherInfo> {
+    let f = |v: &Value, i: usize| v.get(i).and_then(Value::as_f64);
+    let current = value.get("current")?;
+    let daily = value.get("daily")?.as_array()?;
+    let hourly = value.get("hourly")?.as_array()?;
+    if daily.len() != 7 || hourly.len() != 24 {
+        return None;
+    }
+    let daily = daily.iter().map(|d| Some((f(d, 0)?, f(d, 1)?, f(d, 2)?, f(d, 3)?))).collect::<Option<Vec<_>>>()?;
+    let hourly = hourly.iter()
+        .map(|h| Some((f(h, 0)?, f(h, 1)?, h.get(2)?.as_i64()?, h.get(3)?.as_str()?.to_string())))
+        .collect::<Option<Vec<_>>>()?;
+    Some(WeatherInfo {
+        city: value.get("city")?.as_str()?.to_string(),
+        is_day: value.get("is_day")?.as_bool()?,
+        current: (f(current, 0)?, f(current, 1)?, current.get(2)?.as_i64()?),
+        daily: daily.try_into().ok()?,
+        hourly: hourly.try_into().ok()?,
+    })
+}
+
+fn playback_to_json(data: &PlaybackData) -> Value {
+    json!({
+        "title": data.title,
+        "artist": data.artist,
+        "album": data.album,
+        "state": state_name(data.playback_state),
+        "position": data.position(),
+        "duration": data.duration,
+        "device": data.device.as_ref().map(|d| json!({"name": d.name, "battery": d.battery})),
+        "source": data.source,
+        "queue_position": data.queue_position.map(|(index, length)| json!([index, length])),
+    })
+}
+
+fn playback_from_json(value: &Value) -> Option<PlaybackData> {
+    let mut data = PlaybackData::new(
+        value.get("title")?.as_str()?.to_string(),
+        value.get("artist")?.as_str()?.to_string(),
+        PlaybackState::from(value.get("state")?.as_str()?.to_string()),
+        value.get("position")?.as_u64()? as u32,
+        value.get("duration")?.as_u64()? as u32,
+    );
+    data.album = value.get("album").and_then(Value::as_str).map(str::to_string);
+    data.device = value.get("device").and_then(|d| Some(SourceDevice {
+        name: d.get("name")?.as_str()?.to_string(),
+        battery: d.get("battery").and_then(Value::as_u64).map(|b| b as u8),
+    }));
+    let source = value.get("source").and_then(Value::as_str).unwrap_or_default();
+    data.source = SOURCE_NAMES.iter().find(|name| **name == source).copied().unwrap_or("");
+    data.queue_position = value.get("queue_position")
+        .and_then(|q| Some((q.get(0)?.as_u64()? as u32, q.get(1)?.as_u64()? as u32)));
+    Some(data)
+}
+
+fn object_path(value: Option<&Value>) -> Option<OwnedObjectPath> {
+    OwnedObjectPath::try_from(value?.as_str()?.to_string()).ok()
+}
+
+fn device_to_json(device: &BluetoothDevice) -> Value {
+    json!({
+        "path": device.path.as_str(),
+        "address": device.address,
+        "alias": device.alias,
+        "connected": device.connected,
+        "paired": device.paired,
+        "trusted": device.trusted,
+        "battery": device.battery,
+    })
+}
+
+fn device_from_json(value: &Value) -> Option<BluetoothDevice> {
+    let flag = |key: &str| value.get(key).and_then(Value::as_bool);
+    Some(BluetoothDevice {
+        path: object_path(value.get("path"))?,
+        address: value.get("address")?.as_str()?.to_string(),
+        alias: value.get("alias")?.as_str()?.to_string(),
+        connected: flag("connected")?,
+        paired: flag("paired")?,
+        trusted: flag("trusted")?,
+        battery: value.get("battery").and_then(Value::as_u64).map(|b| b as u8),
+    })
+}
+
+fn transport_to_json(transport: &MediaTransport) -> Value {
+    json!({
+        "path": transport.path.as_str(),
+        "device": transport.device.as_ref().map(|device| device.as_str()),
+        "codec": transport.codec,
+        "state": transport.state,
+        "volume": transport.volume,
+    })
+}
+
+fn transport_from_json(value: &Value) -> Option<MediaTransport> {
+    Some(MediaTransport {
+        path: object_path(value.get("path"))?,
+        device: object_path(value.get("device")),
+        codec: value.get("codec")?.as_u64()? as u8,
+        state: value.get("state")?.as_str()?.to_string(),
+        volume: value.get("volume").and_then(Value::as_u64).map(|v| v as u16),
+        volume_changed: None,
+    })
+}
+
+impl Event {
+    pub fn to_json(&self, t: u64) -> Value {
+        let (kind, data) = match self {
+            Event::Weather(info) => ("weather", info.as_deref().map_or(Value::Null, weather_to_json)),
+            Event::Playback(data) => ("playback", data.as_deref().map_or(Value::Null, playback_to_json)),
+            Event::Wifi(strength) => ("wifi", json!(wifi_level(*strength))),
+            Event::Devices(devices) => ("devices", devices.iter().map(device_to_json).collect()),
+            Event::Transport(transport) => ("transport", transport.as_ref().map_or(Value::Null, transport_to_json)),
+            Event::Clock(time) => ("clock", json!(time.to_rfc3339())),
+        };
+        json!({"t": t, "type": kind, "data": data})
+    }
+
+    /// Time (ms since the start of the recording) and event of a log line
+    pub fn from_json(value: &Value) -> Option<(u64, Self)> {
+        let t = value.get("t")?.as_u64()?;
+        let data = value.get("data")?;
+        let event = match value.get("type")?.as_str()? {
+            "weather" if data.is_null() => Event::Weather(None),
+            "weather" => Event::Weather(Some(Box::new(weather_from_json(data)?))),
+            "playback" if data.is_null() => Event::Playback(None),
+            "playback" => Event::Playback(Some(Box::new(playback_from_json(data)?))),
+            "wifi" => Event::Wifi(match data.as_u64()? {
+                0 => WifiSignalBars::NoSignal,
+                1 => WifiSignalBars::Weak,
+                2 => WifiSignalBars::Fair,
+                3 => WifiSignalBars::Good,
+                _ => WifiSignalBars::Excellent
+            }),
+            "devices" => Event::Devices(data.as_array()?.iter().map(device_from_json).collect::<Option<_>>()?),
+            "transport" if data.is_null() => Event::Transport(None),
+            "transport" => Event::Transport(Some(transport_from_json(data)?)),
+            "clock" => Event::Clock(DateTime::parse_from_rfc3339(data.as_str()?).ok()?.with_timezone(&Local)),
+            _ => return None
+        };
+        Some((t, event))
+    }
+
+    /// Shows the event in `store` as if the data source had just produced it
+    pub fn apply(self, store: &Store) {
+        match self {
+            Event::Weather(info) => set_weather_info(store, info.map(|info| *info)),
+            Event::Playback(data) => set_playback_data(store, data.map(|data| *data)),
+            Event::Wifi(strength) => set_wifi_strength(store, strength),
+            Event::Devices(devices) => set_devices(store, devices),
+            Event::Transport(transport) => set_transport(store, transport),
+            Event::Clock(time) => {
+                clock::freeze(Some(time));
+                request_redraw();
+            }
+        }
+    }
+}
+
+/// Appends the event to the log if recording, `event` is only built then
+pub fn record(event: impl FnOnce() -> Event) {
+    let mut recorder = _RECORDER.lock().unwrap();
+    let Some(recorder) = recorder.as_mut() else { return };
+    let line = event().to_json(recorder.start.elapsed().as_millis() as u64);
+    if let Err(e) = writeln!(recorder.file, "{line}").and_then(|_| recorder.file.flush()) {
+        eprintln!("Failed to write recording: {e}");
+    }
+}
+
+/// Logs every data source update to `path` from now on, starting with the current state
+pub fn start_recording(path: &Path, store: &Store) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    *_RECORDER.lock().unwrap() = Some(Recorder {file, start: Instant::now()});
+
+    let (weather, playback, wifi) = ((*store.weather.get()).clone().map(Box::new), (*store.playback.get()).clone(), *store.wifi.get());
+    record(|| Event::Clock(Local::now()));
+    record(|| Event::Weather(weather));
+    record(|| Event::Playback(playback.map(Box::new)));
+    record(|| Event::Wifi(wifi));
+    record(|| Event::Devices((*store.devices.get()).clone()));
+    record(|| Event::Transport((*store.transport.get()).clone()));
+    thread::spawn(|| {
+        loop {
+            thread::sleep(CLOCK_SCHEDULE_RECORD);
+            record(|| Event::Clock(Local::now()));
+        }
+    });
+    Ok(())
+}
+
+/// Reads a recording, skipping lines that can't be parsed
+pub fn load_recording(path: &Path) -> std::io::Result<Vec<(u64, Event)>> {
+    let mut events = vec![];
+    for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        match serde_json::from_str(&line).ok().as_ref().and_then(Event::from_json) {
+            Some(event) => events.push(event),
+            None if line.trim().is_empty() => {}
+            None => eprintln!("Skipping invalid event in line {}", number + 1)
+        }
+    }
+    Ok(events)
+}
+
+/// Plays the recording back in place of the data source threads, `speed` times as fast.
+/// Playback positions between two recorded updates still run at 1×.
+pub fn start_replay(path: &Path, speed: f64, store: Arc<Store>) -> std::io::Result<()> {
+    let events = load_recording(path)?;
+    println!("Replaying {} events at {speed}x", events.len());
+    thread::spawn(move || {
+        let start = Instant::now();
+        for (t, event) in events {
+            let due = start + Duration::from_millis(t).div_f64(speed);
+            thread::sleep(due.saturating_duration_since(Instant::now()));
+            event.apply(&store);
+        }
+        println!("Replay finished");
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    // Through a log line and back, compared as JSON since events have no PartialEq
+    fn round_trip(event: Event) -> Event {
+        let line = event.to_json(1500).to_string();
+        let (t, parsed) = Event::from_json(&serde_json::from_str(&line).unwrap()).expect("event can't be parsed");
+        assert_eq!(t, 1500);
+        assert_eq!(parsed.to_json(t), event.to_json(t));
+        parsed
+    }
+
+    fn path(path: &str) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(path).unwrap()
+    }
+
+    #[test]
+    fn weather() {
+        let info = WeatherInfo {
+            city: "Berlin".to_string(),
+            is_day: false,
+            current: (-1.5, 0.2, 85),
+            daily: core::array::from_fn(|i| (i as f64 * 1.5, 3.0, 0.5, 3600.0 * i as f64)),
+            hourly: core::array::from_fn(|i| (10.0 - i as f64 * 0.25, 0.0, (i * 4) as i64, format!("{i}:00"))),
+        };
+        let Event::Weather(Some(parsed)) = round_trip(Event::Weather(Some(Box::new(info.clone())))) else { panic!("not weather") };
+        assert!(*parsed == info);
+        assert!(matches!(round_trip(Event::Weather(None)), Event::Weather(None)));
+    }
+
+    #[test]
+    fn playback() {
+        let mut data = PlaybackData::new("Blue Monday".to_string(), "New Order".to_string(), PlaybackState::Paused, 83_000, 448_000);
+        data.album = Some("Substance".to_string());
+        data.device = Some(SourceDevice {name: "Pixel 7".to_string(), battery: Some(80)});
+        data.source = "mpd";
+        data.queue_position = Some((3, 12));
+        let Event::Playback(Some(parsed)) = round_trip(Event::Playback(Some(Box::new(data)))) else { panic!("not playback") };
+        assert_eq!((parsed.title.as_str(), parsed.album.as_deref(), parsed.source), ("Blue Monday", Some("Substance"), "mpd"));
+        assert_eq!((parsed.position(), parsed.duration, parsed.queue_position), (83_000, 448_000, Some((3, 12))));
+        assert!(matches!(round_trip(Event::Playback(None)), Event::Playback(None)));
+    }
+
+    #[test]
+    fn wifi() {
+        for strength in [WifiSignalBars::NoSignal, WifiSignalBars::Fair, WifiSignalBars::Excellent] {
+            assert!(matches!(round_trip(Event::Wifi(strength)), Event::Wifi(parsed) if parsed == strength));
+        }
+    }
+
+    #[test]
+    fn devices() {
+        let devices = vec![
+            BluetoothDevice {path: path("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF"), address: "AA:BB:CC:DD:EE:FF".to_string(), alias: "Pixel 7".to_string(),
+                connected: true, paired: true, trusted: true, battery: Some(80)},
+            BluetoothDevice {path: path("/org/bluez/hci0/dev_11_22_33_44_55_66"), address: "11:22:33:44:55:66".to_string(), alias: "Kopfhörer".to_string(),
+                connected: false, paired: true, trusted: false, battery: None},
+        ];
+        let Event::Devices(parsed) = round_trip(Event::Devices(devices.clone())) else { panic!("not devices") };
+        assert_eq!(parsed, devices);
+        assert!(matches!(round_trip(Event::Devices(vec![])), Event::Devices(parsed) if parsed.is_empty()));
+    }
+
+    #[test]
+    fn transport() {
+        let transport = MediaTransport {path: path("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF/fd0"), device: Some(path("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF")),
+            codec: 0x02, state: "active".to_string(), volume: Some(96), volume_changed: Some(Instant::now())};
+        let Event::Transport(Some(parsed)) = round_trip(Event::Transport(Some(transport.clone()))) else { panic!("not a transport") };
+        assert_eq!(parsed, MediaTransport {volume_changed: None, ..transport});
+        assert!(matches!(round_trip(Event::Transport(None)), Event::Transport(None)));
+    }
+
+    #[test]
+    fn clock() {
+        let time = Local.with_ymd_and_hms(2025, 6, 14, 15, 30, 0).unwrap();
+        assert!(matches!(round_trip(Event::Clock(time)), Event::Clock(parsed) if parsed == time));
+    }
+
+    #[test]
+    fn unknown_lines() {
+        for line in [r#"{"t": 0, "type": "radio", "data": null}"#, r#"{"type": "wifi", "data": 2}"#, r#"{"t": 0, "type": "devices", "data": [{}]}"#] {
+            assert!(Event::from_json(&serde_json::from_str(line).unwrap()).is_none(), "{line}");
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use chrono::Timelike;
+use sdl2::event::{Event, EventSender};
+use sdl2::EventSubsystem;
+
+// Sender and type of the SDL user event waking the render loop
+static _WAKE_EVENT: OnceLock<(EventSender, u32)> = OnceLock::new();
+// One wake-up in the queue is enough
+static _WAKE_PENDING: AtomicBool = AtomicBool::new(false);
+// Set by data outside the store, draws the next frame even if no widget reports a change
+static _REDRAW_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// When a widget wants to be drawn again, the most urgent one wins (`min`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Redraw {
+    // Every frame (at vsync)
+    Animating,
+    At(Instant),
+    // Only when something changes
+    Idle,
+}
+
+/// Registers the user event data sources use to wake the render loop
+pub fn init_wake_event(events: &EventSubsystem) -> Result<(), String> {
+    let event_type = unsafe { events.register_event()? };
+    _WAKE_EVENT.set((events.event_sender(), event_type)).map_err(|_| "Wake event already registered".to_string())
+}
+
+/// Wakes the render loop for a redraw, from any thread
+pub fn request_redraw() {
+    _REDRAW_REQUESTED.store(true, Ordering::Release);
+    wake();
+}
+
+/// True once after `request_redraw`
+pub fn take_redraw_request() -> bool {
+    _REDRAW_REQUESTED.swap(false, Ordering::AcqRel)
+}
+
+/// Wakes the render loop, it redraws if a widget on screen reports `changed`
+pub fn wake() {
+    let Some((sender, event_type)) = _WAKE_EVENT.get() else { return };
+    if _WAKE_PENDING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let event = Event::User {timestamp: 0, window_id: 0, type_: *event_type, code: 0, data1: null_mut(), data2: null_mut()};
+    if let Err(e) = sender.push_event(event) {
+        _WAKE_PENDING.store(false, Ordering::Release);
+        eprintln!("Failed to post wake event: {e}");
+    }
+}
+
+/// True for the wake event, allows the next one to be posted
+pub fn is_wake_event(event: &Event) -> bool {
+    let is_wake = _WAKE_EVENT.get().is_some_and(|(_, event_type)| matches!(event, Event::User {type_, ..} if type_ == event_type));
+    if is_wake {
+        _WAKE_PENDING.store(false, Ordering::Release);
+    }
+    is_wake
+}
+
+/// The next full second of the wall clock
+pub fn next_second() -> Redraw {
+    let nanos = chrono::Local::now().nanosecond() % 1_000_000_000;
+    Redraw::At(Instant::now() + Duration::from_nanos(1_000_000_000 - nanos as u64))
+}
@@ -0,0 +1,198 @@
+use std::io::Write;
+use std::sync::LazyLock;
+use std::sync::mpsc::{channel, Sender};
+use isahc::{ReadResponseExt, Request, RequestExt};
+use serde_json::{json, Value};
+use crate::config::{ScrobblerConfig, ScrobblerService, CONFIG};
+use crate::errors::{UnyoError, UnyoResult};
+use crate::history::{read_entries, HistoryEntry};
+
+const QUEUE_FILE: &str = "scrobble_queue.jsonl";
+// Most listens per request the services accept
+const LISTENBRAINZ_BATCH: usize = 100;
+const LASTFM_BATCH: usize = 50;
+// Last.fm errors worth retrying (service offline / temporarily unavailable / rate limit)
+const LASTFM_TEMPORARY_ERRORS: [i64; 3] = [11, 16, 29];
+
+// The worker owns the queue file, a listen to queue or None to just retry
+static _WORKER: LazyLock<Sender<Option<HistoryEntry>>> = LazyLock::new(start_worker);
+
+fn start_worker() -> Sender<Option<HistoryEntry>> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        for entry in receiver {
+            if let Some(entry) = entry {
+                append(&entry);
+            }
+            submit_all();
+        }
+    });
+    sender
+}
+
+/// Queues a listen and tries to submit right away, never waits for the service
+pub fn enqueue(entry: &HistoryEntry) {
+    let _ = _WORKER.send(Some(entry.clone()));
+}
+
+/// Tries to submit what is queued, in the background
+pub fn submit_queue() {
+    let _ = _WORKER.send(None);
+}
+
+fn append(entry: &HistoryEntry) {
+    let state_dir = &CONFIG().state_dir;
+    let result = std::fs::create_dir_all(state_dir)
+        .and_then(|_| std::fs::OpenOptions::new().create(true).append(true).open(state_dir.join(QUEUE_FILE)))
+        .and_then(|mut file| writeln!(file, "{}", entry.to_json()));
+    if let Err(e) = result {
+        eprintln!("Failed to queue scrobble: {e}");
+    }
+}
+
+// Submits everything queued, whatever fails stays queued for the next try
+fn submit_all() {
+    let Some(config) = &CONFIG().history.scrobbler else { return };
+    let path = CONFIG().state_dir.join(QUEUE_FILE);
+    let queue = read_entries(&std::fs::read_to_string(&path).unwrap_or_default());
+    if queue.is_empty() {
+        return;
+    }
+
+    let batch_size = match config.service {
+        ScrobblerService::ListenBrainz => LISTENBRAINZ_BATCH,
+        ScrobblerService::LastFm => LASTFM_BATCH,
+    };
+    let mut remaining = vec![];
+    for batch in queue.chunks(batch_size) {
+        // Keep the order, once one failed the service is most likely unreachable
+        if !remaining.is_empty() {
+            remaining.extend_from_slice(batch);
+            continue;
+        }
+        if let Err(e) = submit(config, batch) {
+            eprintln!("Scrobbling failed, retrying later: {e:?}");
+            remaining.extend_from_slice(batch);
+        }
+    }
+
+    let content: String = remaining.iter().map(|entry| format!("{}\n", entry.to_json())).collect();
+    if let Err(e) = std::fs::write(&path, content) {
+        eprintln!("Failed to update scrobble queue: {e}");
+    }
+}
+
+fn api_err(e: impl ToString, uri: &str) -> UnyoError {
+    UnyoError::ApiReq(e.to_string(), uri.to_string())
+}
+
+/// Err means try again later, permanently rejected listens are dropped
+fn submit(config: &ScrobblerConfig, batch: &[HistoryEntry]) -> UnyoResult<()> {
+    match config.service {
+        ScrobblerService::ListenBrainz => submit_listenbrainz(config, batch),
+        ScrobblerService::LastFm => submit_lastfm(config, batch),
+    }
+}
+
+fn submit_listenbrainz(config: &ScrobblerConfig, batch: &[HistoryEntry]) -> UnyoResult<()> {
+    let uri = format!("{}/1/submit-listens", config.endpoint.trim_end_matches('/'));
+    let payload: Vec<Value> = batch.iter().map(|entry| {
+        let mut additional_info = json!({"submission_client": "unyo", "media_player": entry.source});
+        if entry.duration != 0 {
+            additional_info["duration_ms"] = json!(entry.duration);
+        }
+        json!({
+            "listened_at": entry.played_at,
+            "track_metadata": {"artist_name": entry.artist, "track_name": entry.title, "additional_info": additional_info}
+        })
+    }).collect();
+    let listen_type = if batch.len() == 1 { "single" } else { "import" };
+    let body = json!({"listen_type": listen_type, "payload": payload}).to_string();
+
+    let mut response = Request::post(&uri)
+        .header("Authorization", format!("Token {}", config.token))
+        .header("Content-Type", "application/json")
+        .body(body).map_err(|e| api_err(e, &uri))?
+        .send().map_err(|e| api_err(e, &uri))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else if status.is_server_error() || status.as_u16() == 429 {
+        Err(api_err(status, &uri))
+    } else {
+        // Also a wrong token (401), retrying wouldn't help
+        eprintln!("ListenBrainz rejected {} listens ({status}): {}", batch.len(), response.text().unwrap_or_default());
+        Ok(())
+    }
+}
+
+fn lastfm_signature(params: &[(String, String)], secret: &str) -> String {
+    let mut sorted = params.to_vec();
+    sorted.sort();
+    let mut signed: String = sorted.into_iter().map(|(key, value)| key + &value).collect();
+    signed.push_str(secret);
+    format!("{:x}", md5::compute(signed))
+}
+
+fn submit_lastfm(config: &ScrobblerConfig, batch: &[HistoryEntry]) -> UnyoResult<()> {
+    let uri = &config.endpoint;
+    let mut params = vec![
+        ("method".to_string(), "track.scrobble".to_string()),
+        ("api_key".to_string(), config.api_key.clone()),
+        ("sk".to_string(), config.session_key.clone()),
+    ];
+    for (i, entry) in batch.iter().enumerate() {
+        params.push((format!("artist[{i}]"), entry.artist.clone()));
+        params.push((format!("track[{i}]"), entry.title.clone()));
+        params.push((format!("timestamp[{i}]"), entry.played_at.to_string()));
+        if entry.duration != 0 {
+            params.push((format!("duration[{i}]"), (entry.duration / 1000).to_string()));
+        }
+    }
+    params.push(("api_sig".to_string(), lastfm_signature(&params, &config.api_secret)));
+    // Not part of the signature
+    params.push(("format".to_string(), "json".to_string()));
+    let body = form_urlencoded::Serializer::new(String::new()).extend_pairs(params).finish();
+
+    let mut response = Request::post(uri)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body).map_err(|e| api_err(e, uri))?
+        .send().map_err(|e| api_err(e, uri))?;
+
+    let status = response.status();
+    let text = response.text().unwrap_or_default();
+    let error = serde_json::from_str::<Value>(&text).ok()
+        .and_then(|v| v.get("error").and_then(|e| e.as_i64()));
+    match error {
+        None if status.is_success() => Ok(()),
+        Some(code) if LASTFM_TEMPORARY_ERRORS.contains(&code) => Err(api_err(text, uri)),
+        None => Err(api_err(status, uri)),
+        Some(_) => {
+            eprintln!("Last.fm rejected {} scrobbles: {text}", batch.len());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn signature() {
+        // The example from the Last.fm API docs
+        let signature = lastfm_signature(&params(&[("method", "auth.getSession"), ("token", "xxxxxxx"), ("api_key", "xxxxxxxx")]), "mysecret");
+        assert_eq!(signature, "68afb32bee072407a63b6c41f3e1e2b4");
+
+        // Sorted by key, values as UTF-8
+        let signature = lastfm_signature(&params(&[
+            ("track[0]", "Ace of Spades"), ("timestamp[0]", "1700000000"), ("sk", "sk"), ("method", "track.scrobble"), ("artist[0]", "Motörhead"),
+        ]), "secret");
+        assert_eq!(signature, "aafa67563e9afcf5d0264f283d276ac0");
+    }
+}
@@ -0,0 +1,235 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::api::{set_weather_info, WeatherInfo};
+use crate::history;
+use crate::media::{init_media_sources, ControlFuture, MediaCommand, MediaSource, PlaybackData, PlaybackState, PollFuture, SourceDevice};
+use crate::state::Store;
+use crate::threads::start_media_data_update_thread;
+use crate::weather_widget::time_with_hour_offset;
+use crate::wifi_api::{set_wifi_strength, WifiSignalBars};
+
+const WEATHER_SCHEDULE_UPDATE: Duration = Duration::from_secs(20);
+const WIFI_SCHEDULE_UPDATE: Duration = Duration::from_secs(3);
+
+// Title, artist, duration (ms)
+const PLAYLIST: [(&str, &str, u32); 5] = [
+    ("Bohemian Rhapsody (Remastered 2011)", "Queen", 354_000),
+    ("Blue Monday", "New Order", 448_000),
+    ("Kids", "MGMT", 302_000),
+    ("Da Da Da ich lieb dich nicht du liebst mich nicht aha aha aha", "Trio", 215_000),
+    ("Intro", "The xx", 127_000),
+];
+
+const WIFI_LEVELS: [WifiSignalBars; 5] = [
+    WifiSignalBars::NoSignal,
+    WifiSignalBars::Weak,
+    WifiSignalBars::Fair,
+    WifiSignalBars::Good,
+    WifiSignalBars::Excellent,
+];
+
+/// Scripted weather shown instead of the forecast
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeatherScenario {
+    Storm,
+    Snow,
+    Heat,
+}
+
+impl WeatherScenario {
+    pub const ALL: [WeatherScenario; 3] = [WeatherScenario::Storm, WeatherScenario::Snow, WeatherScenario::Heat];
+
+    pub fn weather_info(self) -> WeatherInfo {
+        let hour = |i: usize, temp: f64, rain: f64, cloud: i64| (temp, rain, cloud, time_with_hour_offset(i as i64));
+        match self {
+            WeatherScenario::Storm => WeatherInfo {
+                city: "Hamburg".to_string(),
+                is_day: true,
+                current: (13.5, 7.2, 100),
+                daily: [(13.0, 1.0, 24.5, 0.0), (14.2, 2.0, 11.0, 1800.0), (15.1, 3.0, 4.2, 7200.0), (16.8, 4.0, 0.0, 21000.0),
+                        (15.5, 3.0, 6.3, 3600.0), (12.9, 1.0, 18.0, 0.0), (14.0, 2.0, 2.1, 9000.0)],
+                hourly: core::array::from_fn(|i| hour(i, 13.5 - (i % 8) as f64 * 0.25, if i < 10 { 7.2 - i as f64 * 0.6 } else { 0.4 }, 100 - (i as i64 % 4) * 5)),
+            },
+            WeatherScenario::Snow => WeatherInfo {
+                city: "Garmisch".to_string(),
+                is_day: true,
+                current: (-4.5, 1.1, 90),
+                daily: [(-3.0, 1.0, 3.5, 0.0), (-6.2, 1.0, 8.0, 0.0), (-8.4, 2.0, 0.0, 18000.0), (-5.1, 2.0, 0.0, 25000.0),
+                        (-2.0, 1.0, 1.2, 3600.0), (0.5, 1.0, 4.0, 0.0), (-1.5, 2.0, 0.0, 14000.0)],
+                hourly: core::array::from_fn(|i| hour(i, -4.5 - (i as f64 * 0.3).min(3.0), if i % 5 < 3 { 1.1 } else { 0.0 }, 90 - (i as i64 * 3) % 40)),
+            },
+            WeatherScenario::Heat => WeatherInfo {
+                city: "Sevilla".to_string(),
+                is_day: true,
+                current: (41.2, 0.0, 3),
+                daily: [(38.5, 10.0, 0.0, 46000.0), (40.1, 11.0, 0.0, 47000.0), (42.3, 11.0, 0.0, 47000.0), (39.8, 10.0, 0.0, 45000.0),
+                        (36.4, 9.0, 0.0, 43000.0), (37.0, 10.0, 0.0, 44000.0), (39.2, 10.0, 0.0, 46000.0)],
+                hourly: core::array::from_fn(|i| hour(i, 41.2 - (i as f64 * 1.5).min(14.0), 0.0, (i as i64 * 7) % 20)),
+            },
+        }
+    }
+}
+
+impl FromStr for WeatherScenario {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "storm" => Ok(WeatherScenario::Storm),
+            "snow" => Ok(WeatherScenario::Snow),
+            "heat" => Ok(WeatherScenario::Heat),
+            _ => Err(format!("Unknown weather scenario: {value}"))
+        }
+    }
+}
+
+// Xorshift, good enough to make the Wi-Fi flicker
+struct Rng(u64);
+
+impl Rng {
+    fn seeded() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64;
+        Self(nanos | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+struct Player {
+    track: usize,
+    // Position at `since`
+    position: u32,
+    since: Instant,
+    playing: bool,
+    // Tracks played so far, drains the battery
+    played: u32,
+}
+
+impl Player {
+    fn position(&self) -> u32 {
+        if self.playing { self.position + self.since.elapsed().as_millis() as u32 } else { self.position }
+    }
+
+    fn jump(&mut self, track: usize) {
+        self.track = track % PLAYLIST.len();
+        self.position = 0;
+        self.since = Instant::now();
+        self.played += 1;
+    }
+
+    // Moves on once the current track ended
+    fn advance(&mut self) {
+        if self.position() >= PLAYLIST[self.track].2 {
+            self.jump(self.track + 1);
+        }
+    }
+
+    fn data(&self) -> PlaybackData {
+        let (title, artist, duration) = PLAYLIST[self.track];
+        let state = if self.playing { PlaybackState::Playing } else { PlaybackState::Paused };
+        let mut data = PlaybackData::new(title.to_string(), artist.to_string(), state, self.position(), duration);
+        data.device = Some(SourceDevice {name: "Pixel 7".to_string(), battery: Some(87u32.saturating_sub(self.played * 2).max(5) as u8)});
+        data.queue_position = Some((self.track as u32 + 1, PLAYLIST.len() as u32));
+        data
+    }
+}
+
+/// A phone playing through a playlist, controllable like a Bluetooth device
+pub struct SimulatedSource {
+    player: Mutex<Player>,
+}
+
+impl Default for SimulatedSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedSource {
+    pub fn new() -> Self {
+        // Start in the middle of a track so the progress bar has something to show
+        Self {player: Mutex::new(Player {track: 0, position: 60_000, since: Instant::now(), playing: true, played: 0})}
+    }
+}
+
+impl MediaSource for SimulatedSource {
+    fn name(&self) -> &'static str {
+        "simulation"
+    }
+
+    fn poll(&self) -> PollFuture<'_> {
+        Box::pin(async move {
+            let mut player = self.player.lock().unwrap();
+            player.advance();
+            Some(player.data())
+        })
+    }
+
+    fn control(&self, command: MediaCommand) -> ControlFuture<'_> {
+        Box::pin(async move {
+            let mut player = self.player.lock().unwrap();
+            match command {
+                MediaCommand::PlayPause => {
+                    player.position = player.position();
+                    player.since = Instant::now();
+                    player.playing = !player.playing;
+                }
+                MediaCommand::Next => {
+                    let next = player.track + 1;
+                    player.jump(next);
+                }
+                // Like most players: back to the start first, previous track only right at the start
+                MediaCommand::Previous => {
+                    let track = if player.position() < 3_000 { player.track + PLAYLIST.len() - 1 } else { player.track };
+                    player.jump(track);
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn start_weather_thread(scenario: Option<WeatherScenario>, store: Arc<Store>) {
+    thread::spawn(move || {
+        for round in 0.. {
+            let scenario = scenario.unwrap_or(WeatherScenario::ALL[round % WeatherScenario::ALL.len()]);
+            set_weather_info(&store, Some(scenario.weather_info()));
+            thread::sleep(WEATHER_SCHEDULE_UPDATE);
+        }
+    });
+}
+
+fn start_wifi_thread(store: Arc<Store>) {
+    thread::spawn(move || {
+        let mut rng = Rng::seeded();
+        let mut level: usize = 3;
+        loop {
+            // Mostly stays, sometimes a bar up or down
+            level = match rng.next() % 6 {
+                0 => level.saturating_sub(1),
+                1 => (level + 1).min(WIFI_LEVELS.len() - 1),
+                _ => level
+            };
+            set_wifi_strength(&store, WIFI_LEVELS[level]);
+            thread::sleep(WIFI_SCHEDULE_UPDATE);
+        }
+    });
+}
+
+/// Feeds the UI from scripted data instead of BlueZ, NetworkManager and the weather API.
+/// Without a scenario the weather cycles through all of them.
+pub fn start_simulation(scenario: Option<WeatherScenario>, store: Arc<Store>) {
+    // Fake tracks don't belong in the history
+    history::set_recording(false);
+    init_media_sources(vec![Box::new(SimulatedSource::new())]);
+    start_media_data_update_thread(store.clone());
+    start_weather_thread(scenario, store.clone());
+    start_wifi_thread(store);
+}
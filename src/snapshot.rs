@@ -0,0 +1,239 @@
+use std::path::Path;
+use chrono::{Local, TimeZone};
+use sdl2::image::{LoadSurface, SaveSurface};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
+use serde_json::{json, Map, Value};
+use crate::api::{set_weather_info, WeatherInfo};
+use crate::clock;
+use crate::config::default_screen;
+use crate::headless::render_widget;
+use crate::media::{set_playback_data, PlaybackData, PlaybackState, SourceDevice};
+use crate::state::Store;
+use crate::ui_renderer::set_screen_height;
+use crate::weather_widget::time_with_hour_offset;
+use crate::widget_registry::WidgetRegistry;
+use crate::wifi_api::{set_wifi_strength, WifiSignalBars};
+
+/// Largest difference per color channel that still counts as the same pixel
+pub const DEFAULT_TOLERANCE: u8 = 8;
+
+/// A widget rendered with the fixtures in its slot on the default main screen, compared
+/// against `<name>.png`
+struct Case {
+    name: &'static str,
+    widget: &'static str,
+    // With or without something playing
+    track: bool,
+    screen: (u32, u32),
+}
+
+const CASES: [Case; 5] = [
+    Case {name: "info-track", widget: "info", track: true, screen: (1920, 1080)},
+    Case {name: "info-idle", widget: "info", track: false, screen: (1920, 1080)},
+    Case {name: "info-track-720p", widget: "info", track: true, screen: (1280, 720)},
+    Case {name: "weather", widget: "weather", track: false, screen: (1920, 1080)},
+    Case {name: "weather-720p", widget: "weather", track: false, screen: (1280, 720)},
+];
+
+// Size of the first `widget` on the default main screen
+fn slot_size(registry: &WidgetRegistry, widget: &str, (w, h): (u32, u32)) -> Option<(u32, u32)> {
+    set_screen_height(h);
+    let screen = default_screen();
+    let slots = registry.slots(&screen, Rect::new(0, 0, w, h));
+    slots.into_iter().find(|(node, _)| node.kind == widget).map(|(_, slot)| slot.size())
+}
+
+// Saturday afternoon, so weekdays and hours are always the same
+fn load_fixtures(store: &Store) {
+    clock::freeze(Some(Local.with_ymd_and_hms(2025, 6, 14, 15, 30, 0).unwrap()));
+    set_wifi_strength(store, WifiSignalBars::Good);
+    set_weather_info(store, Some(WeatherInfo {
+        city: "Berlin".to_string(),
+        is_day: true,
+        current: (21.5, 0.0, 40),
+        daily: [(22.1, 6.0, 0.0, 36000.0), (19.4, 4.0, 2.5, 7200.0), (17.0, 3.0, 8.1, 1800.0), (20.3, 5.0, 0.0, 28000.0),
+                (24.8, 7.0, 0.0, 40000.0), (23.0, 6.0, 0.4, 20000.0), (18.6, 2.0, 12.0, 0.0)],
+        hourly: core::array::from_fn(|i| (21.5 - i as f64 * 0.5, if i % 6 == 3 { 0.4 } else { 0.0 }, (i as i64 * 17) % 100, time_with_hour_offset(i as i64))),
+    }));
+}
+
+fn playback() -> PlaybackData {
+    // Paused, so the position does not move
+    let mut data = PlaybackData::new("Bohemian Rhapsody (Remastered 2011)".to_string(), "Queen".to_string(), PlaybackState::Paused, 83_000, 354_000);
+    data.device = Some(SourceDevice {name: "Pixel 7".to_string(), battery: Some(80)});
+    data.source = "bluetooth";
+    data.queue_position = Some((3, 12));
+    data
+}
+
+// RGBA of every pixel, row by row
+fn pixels(surface: &Surface) -> Result<Vec<[u8; 4]>, String> {
+    let surface = surface.convert_format(PixelFormatEnum::RGBA32)?;
+    let (w, pitch) = (surface.width() as usize, surface.pitch() as usize);
+    Ok(surface.with_lock(|data| data.chunks_exact(pitch)
+        .flat_map(|row| row[..w * 4].chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]))
+        .collect()))
+}
+
+/// Number of pixels that differ by more than `tolerance` in any channel, and an image with those
+/// in red over the dimmed actual frame
+pub fn compare(actual: &Surface, reference: &Surface, tolerance: u8) -> Result<(usize, Surface<'static>), String> {
+    if actual.size() != reference.size() {
+        return Err(format!("size {:?}, expected {:?}", actual.size(), reference.size()));
+    }
+    let (actual_pixels, reference_pixels) = (pixels(actual)?, pixels(reference)?);
+
+    let (w, h) = actual.size();
+    let mut diff = Surface::new(w, h, PixelFormatEnum::RGBA32)?;
+    let pitch = diff.pitch() as usize;
+    let mut mismatches = 0;
+    diff.with_lock_mut(|data| {
+        for (i, (a, r)) in actual_pixels.iter().zip(&reference_pixels).enumerate() {
+            let differs = a.iter().zip(r).any(|(a, r)| a.abs_diff(*r) > tolerance);
+            mismatches += differs as usize;
+            let pixel = if differs { [255, 0, 0, 255] } else { [a[0] / 4, a[1] / 4, a[2] / 4, 255] };
+            let offset = (i / w as usize) * pitch + (i % w as usize) * 4;
+            data[offset..offset + 4].copy_from_slice(&pixel);
+        }
+    });
+    Ok((mismatches, diff))
+}
+
+/// Renders every case and compares it with the reference in `dir`, or replaces the references if
+/// `update` is set. Failed cases leave `<name>.actual.png` and `<name>.diff.png` in `dir/failed`.
+/// Returns whether all cases passed.
+pub fn run_snapshots(dir: &Path, update: bool, tolerance: u8) -> bool {
+    let store = Store::new();
+    load_fixtures(&store);
+    let registry = WidgetRegistry::builtin();
+    let failed_dir = dir.join("failed");
+    // No audio capture in the fixtures
+    let options: Map<String, Value> = json!({"spectrum": false}).as_object().cloned().unwrap();
+    let mut passed = true;
+
+    for case in &CASES {
+        set_playback_data(&store, case.track.then(playback));
+        let Some(size) = slot_size(&registry, case.widget, case.screen) else {
+            println!("{}: no {} on the default screen", case.name, case.widget);
+            passed = false;
+            continue;
+        };
+        let actual = match render_widget(size, case.screen.1, |position| registry.create(case.widget, position, &store, &options).unwrap()) {
+            Ok(actual) => actual,
+            Err(e) => {
+                println!("{}: failed to render: {e}", case.name);
+                passed = false;
+                continue;
+            }
+        };
+
+        let reference_path = dir.join(format!("{}.png", case.name));
+        if update {
+            match std::fs::create_dir_all(dir).map_err(|e| e.to_string()).and_then(|_| actual.save(&reference_path)) {
+                Ok(()) => println!("{}: updated", case.name),
+                Err(e) => {
+                    println!("{}: failed to save {}: {e}", case.name, reference_path.display());
+                    passed = false;
+                }
+            }
+            continue;
+        }
+
+        let result = Surface::from_file(&reference_path).and_then(|reference| compare(&actual, &reference, tolerance));
+        let diff = match result {
+            Ok((0, _)) => {
+                println!("{}: ok", case.name);
+                continue;
+            }
+            Ok((mismatches, diff)) => {
+                println!("{}: {mismatches} pixels differ", case.name);
+                Some(diff)
+            }
+            Err(e) => {
+                println!("{}: {e}", case.name);
+                None
+            }
+        };
+        passed = false;
+
+        let saved = std::fs::create_dir_all(&failed_dir).map_err(|e| e.to_string())
+            .and_then(|_| actual.save(failed_dir.join(format!("{}.actual.png", case.name))))
+            .and_then(|_| diff.map_or(Ok(()), |diff| diff.save(failed_dir.join(format!("{}.diff.png", case.name)))));
+        if let Err(e) = saved {
+            eprintln!("Failed to save the output of {}: {e}", case.name);
+        }
+    }
+
+    clock::freeze(None);
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui_renderer::on_screen_height;
+    use super::*;
+
+    // A surface of `w`×`h` in `color`
+    fn filled(w: u32, h: u32, color: [u8; 4]) -> Surface<'static> {
+        let mut surface = Surface::new(w, h, PixelFormatEnum::RGBA32).unwrap();
+        let pitch = surface.pitch() as usize;
+        surface.with_lock_mut(|data| {
+            for row in data.chunks_exact_mut(pitch) {
+                row[..w as usize * 4].chunks_exact_mut(4).for_each(|pixel| pixel.copy_from_slice(&color));
+            }
+        });
+        surface
+    }
+
+    // Sets the pixel at (x, y)
+    fn set_pixel(surface: &mut Surface, x: usize, y: usize, color: [u8; 4]) {
+        let pitch = surface.pitch() as usize;
+        surface.with_lock_mut(|data| data[y * pitch + x * 4..y * pitch + x * 4 + 4].copy_from_slice(&color));
+    }
+
+    #[test]
+    fn within_tolerance() {
+        let reference = filled(4, 3, [100, 100, 100, 255]);
+        let mut actual = filled(4, 3, [100, 100, 100, 255]);
+        set_pixel(&mut actual, 1, 1, [100, 108, 92, 255]);
+        let (mismatches, _) = compare(&actual, &reference, 8).unwrap();
+        assert_eq!(mismatches, 0);
+    }
+
+    #[test]
+    fn past_tolerance() {
+        let reference = filled(4, 3, [100, 100, 100, 255]);
+        let mut actual = filled(4, 3, [100, 100, 100, 255]);
+        set_pixel(&mut actual, 2, 1, [100, 109, 100, 255]);
+        let (mismatches, diff) = compare(&actual, &reference, 8).unwrap();
+        assert_eq!(mismatches, 1);
+
+        // Red where it differs, the dimmed frame elsewhere
+        let diff = pixels(&diff).unwrap();
+        assert_eq!(diff[4 + 2], [255, 0, 0, 255]);
+        assert_eq!(diff[0], [25, 25, 25, 255]);
+        assert_eq!(diff.iter().filter(|pixel| **pixel == [255, 0, 0, 255]).count(), 1);
+    }
+
+    #[test]
+    fn size_mismatch() {
+        assert!(compare(&filled(4, 3, [0, 0, 0, 255]), &filled(3, 4, [0, 0, 0, 255]), 8).is_err());
+    }
+
+    #[test]
+    fn slots_of_the_default_screen() {
+        let registry = WidgetRegistry::builtin();
+        for (w, h) in [(1280, 720), (1920, 1080)] {
+            let (info, weather) = on_screen_height(h, || {
+                (slot_size(&registry, "info", (w, h)).unwrap(), slot_size(&registry, "weather", (w, h)).unwrap())
+            });
+            // Above each other in the left 5/9
+            assert_eq!(info, weather);
+            assert!(info.0 <= w * 5 / 9 && info.0 > w / 2);
+            assert!(info.1 <= h / 2);
+        }
+        assert_eq!(on_screen_height(1080, || slot_size(&registry, "history", (1920, 1080))), None);
+    }
+}
@@ -0,0 +1,279 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::os::unix::fs::FileTypeExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use crate::config::{CaptureSource, CONFIG};
+use crate::redraw::request_redraw;
+
+const FFT_SIZE: usize = 2048;
+// Analyses per second
+const ANALYSIS_RATE: usize = 60;
+const MIN_FREQUENCY: f32 = 50.0;
+const MAX_FREQUENCY: f32 = 16_000.0;
+// Levels are shown from -DB_RANGE dBFS to 0 dBFS
+const DB_RANGE: f32 = 60.0;
+// How fast bars fall (full height per second)
+const DECAY_PER_SECOND: f32 = 1.5;
+// Without new samples for this long the bars fall to zero (paused, capture died)
+const SILENCE_AFTER: Duration = Duration::from_millis(300);
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+// Capture format of arecord / pw-record and raw FIFOs
+const CAPTURE_RATE: u32 = 44100;
+const CAPTURE_CHANNELS: u16 = 2;
+
+static _SPECTRUM: LazyLock<Mutex<Spectrum>> = LazyLock::new(|| {Mutex::new(Spectrum::new(CONFIG().spectrum.bars as usize))});
+
+#[allow(non_snake_case)]
+pub fn SPECTRUM<'a>() -> MutexGuard<'a, Spectrum> {
+    _SPECTRUM.lock().unwrap()
+}
+
+/// Bar levels (0..1), `targets` come from the analysis, `levels` fall towards them
+pub struct Spectrum {
+    targets: Vec<f32>,
+    levels: Vec<f32>,
+    updated: Instant,
+    last_frame: Instant,
+}
+
+impl Spectrum {
+    fn new(bars: usize) -> Self {
+        Self {targets: vec![0.0; bars], levels: vec![0.0; bars], updated: Instant::now(), last_frame: Instant::now()}
+    }
+
+    fn set_targets(&mut self, targets: Vec<f32>) {
+        // Wake the render loop if the bars were at rest
+        if !self.is_active() && targets.iter().any(|t| *t > 0.0) {
+            request_redraw();
+        }
+        self.targets = targets;
+        self.updated = Instant::now();
+    }
+
+    /// True while bars move or are still visible
+    pub fn is_active(&self) -> bool {
+        let receiving = self.updated.elapsed() < SILENCE_AFTER && self.targets.iter().any(|t| *t > 0.0);
+        receiving || self.levels.iter().any(|l| *l > 0.0)
+    }
+
+    /// Levels for the next frame, rising instantly and decaying smoothly
+    pub fn frame(&mut self) -> &[f32] {
+        let dt = self.last_frame.elapsed().as_secs_f32();
+        self.last_frame = Instant::now();
+        let silent = self.updated.elapsed() > SILENCE_AFTER;
+        for (level, target) in self.levels.iter_mut().zip(&self.targets) {
+            let target = if silent { 0.0 } else { *target };
+            *level = target.max(*level - DECAY_PER_SECOND * dt);
+        }
+        &self.levels
+    }
+}
+
+/// FFT with Hann window, magnitudes grouped into log-spaced bars
+struct Analyzer {
+    fft: std::sync::Arc<dyn rustfft::Fft<f32>>,
+    window: Vec<f32>,
+    // FFT bin range of each bar
+    bins: Vec<(usize, usize)>,
+}
+
+impl Analyzer {
+    fn new(bars: usize, sample_rate: u32) -> Self {
+        let fft = FftPlanner::new().plan_fft_forward(FFT_SIZE);
+        let window = (0..FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / (FFT_SIZE - 1) as f32).cos())
+            .collect();
+
+        let max_frequency = MAX_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let bin_of = |frequency: f32| ((frequency * FFT_SIZE as f32 / sample_rate as f32) as usize).clamp(1, FFT_SIZE / 2);
+        let edge = |i: usize| MIN_FREQUENCY * (max_frequency / MIN_FREQUENCY).powf(i as f32 / bars as f32);
+        let bins = (0..bars)
+            .map(|i| {
+                let start = bin_of(edge(i));
+                (start, bin_of(edge(i + 1)).max(start + 1))
+            })
+            .collect();
+        Self {fft, window, bins}
+    }
+
+    fn analyze(&self, samples: &[f32]) -> Vec<f32> {
+        let mut buffer: Vec<Complex<f32>> = samples.iter().zip(&self.window)
+            .map(|(s, w)| Complex::new(s * w, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+
+        // The Hann window halves the amplitude
+        let scale = 4.0 / FFT_SIZE as f32;
+        self.bins.iter().map(|(start, end)| {
+            let magnitude = buffer[*start..*end].iter().map(|c| c.norm()).fold(0.0, f32::max) * scale;
+            let db = 20.0 * magnitude.max(1e-9).log10();
+            ((db + DB_RANGE) / DB_RANGE).clamp(0.0, 1.0)
+        }).collect()
+    }
+}
+
+/// Where the samples come from, 16 bit little endian interleaved
+struct Capture {
+    reader: Box<dyn Read + Send>,
+    sample_rate: u32,
+    channels: u16,
+    // Files are read at playback speed, devices and FIFOs pace themselves
+    paced: bool,
+    // arecord / pw-record, stopped when the capture ends
+    child: Option<Child>,
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// Skips the WAV header, returns (sample rate, channels)
+fn read_wav_header(reader: &mut impl Read) -> std::io::Result<(u32, u16)> {
+    let invalid = |e: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string());
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(invalid("Not a WAV file"));
+    }
+
+    let mut format = None;
+    loop {
+        let mut chunk = [0u8; 8];
+        reader.read_exact(&mut chunk)?;
+        let size = u32::from_le_bytes(chunk[4..8].try_into().unwrap()) as usize;
+        match &chunk[0..4] {
+            b"fmt " => {
+                if size < 16 {
+                    return Err(invalid("WAV fmt chunk too short"));
+                }
+                let mut fmt = vec![0u8; size];
+                reader.read_exact(&mut fmt)?;
+                let channels = u16::from_le_bytes([fmt[2], fmt[3]]);
+                let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap());
+                let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+                if bits != 16 {
+                    return Err(invalid("Only 16 bit WAV is supported"));
+                }
+                if channels == 0 || sample_rate == 0 {
+                    return Err(invalid("WAV without channels or sample rate"));
+                }
+                format = Some((sample_rate, channels));
+            }
+            b"data" => return format.ok_or(invalid("WAV without fmt chunk")),
+            _ => { std::io::copy(&mut reader.by_ref().take(size as u64), &mut std::io::sink())?; }
+        }
+    }
+}
+
+impl Capture {
+    fn open(source: &CaptureSource) -> std::io::Result<Self> {
+        let (program, args) = match source {
+            CaptureSource::Alsa(device) => ("arecord", vec!["-q", "-D", device, "-t", "raw", "-f", "S16_LE", "-c", "2", "-r", "44100"]),
+            CaptureSource::PipeWire(target) => ("pw-record", vec!["--target", target, "--format", "s16", "--channels", "2", "--rate", "44100", "-"]),
+            CaptureSource::File(path) => {
+                let mut reader = BufReader::new(File::open(path)?);
+                let paced = !std::fs::metadata(path).is_ok_and(|m| m.file_type().is_fifo());
+                if path.ends_with(".wav") {
+                    let (sample_rate, channels) = read_wav_header(&mut reader)?;
+                    return Ok(Self {reader: Box::new(reader), sample_rate, channels, paced, child: None});
+                }
+                return Ok(Self {reader: Box::new(reader), sample_rate: CAPTURE_RATE, channels: CAPTURE_CHANNELS, paced, child: None});
+            }
+        };
+
+        let mut child = Command::new(program).args(args).stdout(Stdio::piped()).stderr(Stdio::null()).spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        Ok(Self {reader: Box::new(stdout), sample_rate: CAPTURE_RATE, channels: CAPTURE_CHANNELS, paced: false, child: Some(child)})
+    }
+
+    /// Reads samples until the source ends, feeding the analysis
+    fn run(mut self) -> std::io::Result<()> {
+        let analyzer = Analyzer::new(CONFIG().spectrum.bars as usize, self.sample_rate);
+        let hop = (self.sample_rate as usize / ANALYSIS_RATE).min(FFT_SIZE);
+        let frame_bytes = 2 * self.channels as usize;
+        let mut samples = vec![0.0f32; FFT_SIZE];
+        let mut raw = vec![0u8; hop * frame_bytes];
+        let started = Instant::now();
+        let mut read_frames = 0u64;
+
+        loop {
+            self.reader.read_exact(&mut raw)?;
+            // Mono mix
+            let mono = raw.chunks_exact(frame_bytes).map(|frame| {
+                let sum: f32 = frame.chunks_exact(2).map(|s| i16::from_le_bytes([s[0], s[1]]) as f32).sum();
+                sum / (self.channels as f32 * i16::MAX as f32)
+            });
+            samples.drain(..hop);
+            samples.extend(mono);
+            SPECTRUM().set_targets(analyzer.analyze(&samples));
+
+            if self.paced {
+                read_frames += hop as u64;
+                let due = started + Duration::from_secs_f64(read_frames as f64 / self.sample_rate as f64);
+                std::thread::sleep(due.saturating_duration_since(Instant::now()));
+            }
+        }
+    }
+}
+
+/// Captures in the background, restarting the source when it ends
+pub fn start_capture() {
+    let Some(source) = &CONFIG().spectrum.capture else { return };
+    std::thread::spawn(move || {
+        loop {
+            match Capture::open(source).and_then(Capture::run) {
+                Err(e) if e.kind() != std::io::ErrorKind::UnexpectedEof => eprintln!("Spectrum capture ({source:?}): {e}"),
+                _ => {}
+            }
+            std::thread::sleep(RESTART_DELAY);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(fmt: &[u8]) -> Vec<u8> {
+        [b"RIFF\0\0\0\0WAVEfmt ".as_slice(), &(fmt.len() as u32).to_le_bytes(), fmt, b"data\0\0\0\0"].concat()
+    }
+
+    #[test]
+    fn wav_header() {
+        // PCM, stereo, 48 kHz, 16 bit
+        let fmt = [1, 0, 2, 0, 0x80, 0xbb, 0, 0, 0, 0xee, 2, 0, 4, 0, 16, 0];
+        assert_eq!(read_wav_header(&mut wav(&fmt).as_slice()).unwrap(), (48000, 2));
+    }
+
+    #[test]
+    fn broken_wav_headers() {
+        // Too short for the bit depth
+        assert!(read_wav_header(&mut wav(&[1, 0, 2, 0, 0x80, 0xbb, 0, 0]).as_slice()).is_err());
+        // 24 bit
+        assert!(read_wav_header(&mut wav(&[1, 0, 2, 0, 0x80, 0xbb, 0, 0, 0, 0, 0, 0, 6, 0, 24, 0]).as_slice()).is_err());
+        // No channels
+        assert!(read_wav_header(&mut wav(&[1, 0, 0, 0, 0x80, 0xbb, 0, 0, 0, 0, 0, 0, 0, 0, 16, 0]).as_slice()).is_err());
+        assert!(read_wav_header(&mut b"RIFX\0\0\0\0WAVE".as_slice()).is_err());
+    }
+
+    #[test]
+    fn capture_process_is_reaped() {
+        let child = Command::new("sleep").arg("30").stdout(Stdio::piped()).spawn().unwrap();
+        let pid = child.id();
+        let mut capture = Capture {reader: Box::new(std::io::empty()), sample_rate: CAPTURE_RATE, channels: CAPTURE_CHANNELS, paced: false, child: Some(child)};
+        capture.reader = Box::new(capture.child.as_mut().unwrap().stdout.take().unwrap());
+        drop(capture);
+        // Neither running nor a zombie
+        assert!(!std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
+}
@@ -0,0 +1,40 @@
+use sdl2::rect::Rect;
+use crate::color::{PB_EMPTY, PB_FULLY};
+use crate::redraw::Redraw;
+use crate::spectrum::SPECTRUM;
+use crate::ui_renderer::{UIContext, EDGE_PADDING};
+
+/// Spectrum bars along the bottom of the media panel
+pub struct SpectrumBars {
+    position: Rect,
+}
+
+impl SpectrumBars {
+    pub fn new(position: Rect) -> Self {
+        Self {position}
+    }
+
+    pub fn draw(&self, ctx: &mut UIContext) {
+        let mut spectrum = SPECTRUM();
+        let levels = spectrum.frame();
+        if levels.is_empty() {
+            return;
+        }
+
+        let gap = EDGE_PADDING() / 2;
+        let bar_w = ((self.position.w - gap * (levels.len() as i32 - 1)) / levels.len() as i32).max(1);
+        for (i, level) in levels.iter().enumerate() {
+            let x = self.position.x + i as i32 * (bar_w + gap);
+            let h = (self.position.h as f32 * level) as i32;
+            // Baseline, so the bars don't vanish during silence
+            ctx.draw_rect(Rect::new(x, self.position.bottom() - gap, bar_w as u32, gap as u32), PB_EMPTY);
+            if h > gap {
+                ctx.draw_rect(Rect::new(x, self.position.bottom() - h, bar_w as u32, h as u32), PB_FULLY);
+            }
+        }
+    }
+
+    pub fn redraw(&self) -> Redraw {
+        if SPECTRUM().is_active() { Redraw::Animating } else { Redraw::Idle }
+    }
+}
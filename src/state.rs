@@ -0,0 +1,188 @@
+use std::cell::Cell;
+use std::ops::Deref;
+use std::sync::Arc;
+use tokio::sync::watch;
+use crate::api::WeatherInfo;
+use crate::bluetooth::{BluetoothDevice, MediaTransport};
+use crate::media::PlaybackData;
+use crate::pairing::PairingRequest;
+use crate::redraw::wake;
+use crate::wifi_api::WifiSignalBars;
+
+/// Everything the data sources produce for the UI. Created once in `main` and handed to the
+/// sources and widgets, tests and snapshots use their own
+pub struct Store {
+    pub weather: Slice<Option<WeatherInfo>>,
+    pub playback: Slice<Option<PlaybackData>>,
+    pub wifi: Slice<WifiSignalBars>,
+    // All devices known to BlueZ (connected first, then paired)
+    pub devices: Slice<Vec<BluetoothDevice>>,
+    // The transport of the active (or shown) A2DP stream
+    pub transport: Slice<Option<MediaTransport>>,
+    // Shown in the pairing overlay until answered or expired
+    pub pairing: Slice<Option<PairingRequest>>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store {
+    pub fn new() -> Self {
+        Self {
+            weather: Slice::new(None),
+            playback: Slice::new(None),
+            wifi: Slice::new(WifiSignalBars::NoSignal),
+            devices: Slice::new(vec![]),
+            transport: Slice::new(None),
+            pairing: Slice::new(None),
+        }
+    }
+}
+
+/// A value as it was at `version`, cheap to clone and holds no lock
+#[derive(Debug)]
+pub struct Snapshot<T> {
+    pub version: u64,
+    value: Arc<T>,
+}
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self {version: self.version, value: self.value.clone()}
+    }
+}
+
+impl<T> Deref for Snapshot<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// One typed piece of the store, every change gets a new version and wakes the renderer
+pub struct Slice<T> {
+    sender: watch::Sender<Snapshot<T>>,
+}
+
+impl<T> Slice<T> {
+    pub fn new(value: T) -> Self {
+        Self {sender: watch::Sender::new(Snapshot {version: 0, value: Arc::new(value)})}
+    }
+
+    pub fn get(&self) -> Snapshot<T> {
+        self.sender.borrow().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        self.sender.send_modify(|snapshot| *snapshot = Snapshot {version: snapshot.version + 1, value: Arc::new(value)});
+        wake();
+    }
+
+    /// Replaces the value without a new version, for updates that show the same
+    /// (e.g. a newly reported position of a track that is extrapolated anyway)
+    pub fn refresh(&self, value: T) {
+        self.sender.send_if_modified(|snapshot| {
+            snapshot.value = Arc::new(value);
+            false
+        });
+    }
+
+    pub fn subscribe(&self) -> Subscription<T> {
+        Subscription {receiver: self.sender.subscribe(), seen: Cell::new(None)}
+    }
+}
+
+impl<T: PartialEq> Slice<T> {
+    /// `set` if the value differs, returns whether it did
+    pub fn set_if_changed(&self, value: T) -> bool {
+        let changed = self.sender.send_if_modified(|snapshot| {
+            if *snapshot.value == value {
+                return false;
+            }
+            *snapshot = Snapshot {version: snapshot.version + 1, value: Arc::new(value)};
+            true
+        });
+        if changed {
+            wake();
+        }
+        changed
+    }
+}
+
+impl<T: Clone + PartialEq> Slice<T> {
+    /// Changes a copy of the value in place, stored as a new version if it differs afterwards.
+    /// Holds the slice locked meanwhile, so no other change gets lost; `change` must not use it
+    pub fn update(&self, change: impl FnOnce(&mut T)) -> bool {
+        let changed = self.sender.send_if_modified(|snapshot| {
+            let mut value = (*snapshot.value).clone();
+            change(&mut value);
+            if *snapshot.value == value {
+                return false;
+            }
+            *snapshot = Snapshot {version: snapshot.version + 1, value: Arc::new(value)};
+            true
+        });
+        if changed {
+            wake();
+        }
+        changed
+    }
+}
+
+/// A widget's view of a slice, remembers the version it last drew
+pub struct Subscription<T> {
+    receiver: watch::Receiver<Snapshot<T>>,
+    seen: Cell<Option<u64>>,
+}
+
+impl<T> Subscription<T> {
+    /// Latest value for drawing, `changed` is false until the next version
+    pub fn get(&self) -> Snapshot<T> {
+        let snapshot = self.receiver.borrow().clone();
+        self.seen.set(Some(snapshot.version));
+        snapshot
+    }
+
+    /// Latest value, without counting it as drawn
+    pub fn peek(&self) -> Snapshot<T> {
+        self.receiver.borrow().clone()
+    }
+
+    /// Whether there is a version that wasn't drawn yet
+    pub fn changed(&self) -> bool {
+        self.seen.get() != Some(self.receiver.borrow().version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let slice = Arc::new(Slice::new(0u32));
+        let threads: Vec<_> = (0..8).map(|_| {
+            let slice = slice.clone();
+            thread::spawn(move || (0..500).for_each(|_| { slice.update(|value| *value += 1); }))
+        }).collect();
+        threads.into_iter().for_each(|thread| thread.join().unwrap());
+        assert_eq!(*slice.get(), 4000);
+        assert_eq!(slice.get().version, 4000);
+    }
+
+    #[test]
+    fn unchanged_update_keeps_version() {
+        let slice = Slice::new(vec![1, 2]);
+        let subscription = slice.subscribe();
+        subscription.get();
+        assert!(!slice.update(|value| value.sort()));
+        assert!(!subscription.changed());
+        assert!(slice.update(|value| value.push(3)));
+        assert!(subscription.changed());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// What a cached texture was made from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TextureKey {
+    // Font path, font size, RGBA, text
+    Text(String, u16, (u8, u8, u8, u8), String),
+    // Image path
+    Image(String),
+}
+
+struct CachedTexture<T> {
+    texture: Rc<T>,
+    size: (u32, u32),
+    // Modification time of the image file, a newer file replaces the texture
+    modified: Option<SystemTime>,
+    last_used: u64,
+}
+
+impl<T> CachedTexture<T> {
+    fn bytes(&self) -> usize {
+        // RGBA
+        self.size.0 as usize * self.size.1 as usize * 4
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Least recently used textures (`T`, SDL textures when drawing) are dropped once the cache
+/// grows past `capacity` bytes, counted as RGBA of the size given with each texture
+pub struct TextureCache<T> {
+    entries: HashMap<TextureKey, CachedTexture<T>>,
+    capacity: usize,
+    bytes: usize,
+    // Incremented on every access, orders the entries by use
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<T> TextureCache<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {entries: HashMap::new(), capacity, bytes: 0, clock: 0, hits: 0, misses: 0}
+    }
+
+    /// The cached texture, or the one `create` makes (cached from now on)
+    pub fn get_or_insert(&mut self, key: TextureKey, modified: Option<SystemTime>,
+                         create: impl FnOnce() -> (T, (u32, u32))) -> (Rc<T>, (u32, u32)) {
+        self.get_or_try_insert(key, modified, || Ok::<_, ()>(create())).unwrap()
+    }
+
+    /// Like `get_or_insert`, nothing is cached if `create` fails
+    pub fn get_or_try_insert<E>(&mut self, key: TextureKey, modified: Option<SystemTime>,
+                                create: impl FnOnce() -> Result<(T, (u32, u32)), E>) -> Result<(Rc<T>, (u32, u32)), E> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(&key) {
+            if entry.modified == modified {
+                self.hits += 1;
+                entry.last_used = self.clock;
+                return Ok((entry.texture.clone(), entry.size));
+            }
+            self.invalidate(&key);
+        }
+
+        self.misses += 1;
+        let (texture, size) = create()?;
+        let entry = CachedTexture {texture: Rc::new(texture), size, modified, last_used: self.clock};
+        self.bytes += entry.bytes();
+        let result = (entry.texture.clone(), size);
+        self.entries.insert(key, entry);
+        self.evict();
+        Ok(result)
+    }
+
+    fn evict(&mut self) {
+        // Never evicts the newest entry, even if it alone is larger than the capacity
+        while self.bytes > self.capacity && self.entries.len() > 1 {
+            let oldest = self.entries.iter().min_by_key(|(_, entry)| entry.last_used).map(|(key, _)| key.clone()).unwrap();
+            self.invalidate(&oldest);
+        }
+    }
+
+    pub fn invalidate(&mut self, key: &TextureKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.bytes -= entry.bytes();
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {entries: self.entries.len(), bytes: self.bytes, capacity: self.capacity, hits: self.hits, misses: self.misses}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> TextureKey {
+        TextureKey::Image(name.to_string())
+    }
+
+    // 10×10 RGBA, 400 bytes each
+    fn insert(cache: &mut TextureCache<&'static str>, name: &'static str) {
+        cache.get_or_insert(key(name), None, || (name, (10, 10)));
+    }
+
+    fn cached(cache: &TextureCache<&'static str>, name: &str) -> bool {
+        cache.entries.contains_key(&key(name))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = TextureCache::new(1200);
+        insert(&mut cache, "a");
+        insert(&mut cache, "b");
+        insert(&mut cache, "c");
+        // Used again, so "b" is the oldest
+        insert(&mut cache, "a");
+        insert(&mut cache, "d");
+        assert!(cached(&cache, "a") && !cached(&cache, "b") && cached(&cache, "c") && cached(&cache, "d"));
+        assert_eq!(cache.stats().bytes, 1200);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 4));
+    }
+
+    #[test]
+    fn keeps_newest_entry() {
+        let mut cache = TextureCache::new(100);
+        insert(&mut cache, "a");
+        assert!(cached(&cache, "a"));
+        insert(&mut cache, "b");
+        assert!(!cached(&cache, "a") && cached(&cache, "b"));
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    #[test]
+    fn bytes_after_invalidation() {
+        let mut cache = TextureCache::new(10_000);
+        insert(&mut cache, "a");
+        insert(&mut cache, "b");
+        assert_eq!(cache.stats().bytes, 800);
+        cache.invalidate(&key("a"));
+        cache.invalidate(&key("b"));
+        cache.invalidate(&key("b"));
+        assert_eq!((cache.stats().entries, cache.stats().bytes), (0, 0));
+    }
+
+    #[test]
+    fn modified_file_is_reloaded() {
+        let mut cache = TextureCache::new(10_000);
+        let (old, new) = (SystemTime::UNIX_EPOCH, SystemTime::now());
+        cache.get_or_insert(key("a"), Some(old), || ("old", (10, 10)));
+        assert_eq!(*cache.get_or_insert(key("a"), Some(old), || ("unused", (10, 10))).0, "old");
+        assert_eq!(*cache.get_or_insert(key("a"), Some(new), || ("new", (10, 10))).0, "new");
+        assert_eq!((cache.stats().hits, cache.stats().misses, cache.stats().bytes), (1, 2, 400));
+    }
+}
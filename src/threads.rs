@@ -1,40 +1,60 @@
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
-use crate::api::UPDATE_WEATHER_INFO;
-use crate::bluetooth::UPDATE_BLUETOOTH_DATA;
+use crate::api::{get_location, UPDATE_WEATHER_INFO};
+use crate::config::CONFIG;
+use crate::media::UPDATE_PLAYBACK_DATA;
+use crate::scrobbler::submit_queue;
+use crate::spectrum::start_capture;
+use crate::state::Store;
 use crate::wifi_api::refresh_wifi_connectivity;
 
 const WIFI_STAT_SCHEDULE_UPDATE: Duration = Duration::from_secs(15);
-const BT_DATA_SCHEDULE_UPDATE: Duration = Duration::from_millis(350);
+const MEDIA_DATA_SCHEDULE_UPDATE: Duration = Duration::from_millis(350);
 const WEATHER_SCHEDULE_UPDATE: Duration = Duration::from_secs(10_000);
+const SCROBBLE_SCHEDULE_RETRY: Duration = Duration::from_secs(600);
 
-fn start_wifi_con_update_thread() {
-    thread::spawn(|| {
+fn start_wifi_con_update_thread(store: Arc<Store>) {
+    thread::spawn(move || {
         loop {
-            refresh_wifi_connectivity();
+            refresh_wifi_connectivity(&store);
             thread::sleep(WIFI_STAT_SCHEDULE_UPDATE)
         }
     });
 }
 
-fn start_bt_data_update_thread() {
-    tokio::spawn((async || {loop {
-        UPDATE_BLUETOOTH_DATA().await;
-        tokio::time::sleep(BT_DATA_SCHEDULE_UPDATE).await;
-    }})());
+pub fn start_media_data_update_thread(store: Arc<Store>) {
+    tokio::spawn(async move {loop {
+        UPDATE_PLAYBACK_DATA(&store).await;
+        tokio::time::sleep(MEDIA_DATA_SCHEDULE_UPDATE).await;
+    }});
 }
 
-fn start_weather_update_thread() {
-    thread::spawn(|| {
+fn start_weather_update_thread(store: Arc<Store>) {
+    thread::spawn(move || {
+        let location = get_location().expect("Failed to get geo location");
         loop {
-            UPDATE_WEATHER_INFO();
+            UPDATE_WEATHER_INFO(&store, &location);
             thread::sleep(WEATHER_SCHEDULE_UPDATE)
         }
     });
 }
 
-pub fn init_threads() {
-    start_wifi_con_update_thread();
-    start_weather_update_thread();
-    start_bt_data_update_thread();
+fn start_scrobble_retry_thread() {
+    thread::spawn(|| {
+        loop {
+            submit_queue();
+            thread::sleep(SCROBBLE_SCHEDULE_RETRY)
+        }
+    });
+}
+
+pub fn init_threads(store: Arc<Store>) {
+    start_wifi_con_update_thread(store.clone());
+    start_weather_update_thread(store.clone());
+    start_media_data_update_thread(store);
+    if CONFIG().history.scrobbler.is_some() {
+        start_scrobble_retry_thread();
+    }
+    start_capture();
 }
\ No newline at end of file
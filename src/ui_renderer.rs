@@ -1,23 +1,56 @@
-use std::sync::OnceLock;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use lazy_static::lazy_static;
 use sdl2::EventPump;
+use sdl2::event::Event;
 use sdl2::gfx::primitives::{DrawRenderer};
 use sdl2::image::{InitFlag, LoadTexture};
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::{Point, Rect};
-use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::render::{BlendMode, Texture, TextureCreator, WindowCanvas};
 use sdl2::surface::Surface;
 use sdl2::ttf::{Sdl2TtfContext};
 use sdl2::video::WindowContext;
+use crate::color::BG_TINTED;
+use crate::config::resource;
 use crate::errors::{UnyoError, UnyoResult};
+use crate::gestures::Gesture;
+use crate::redraw::{init_wake_event, Redraw};
+use crate::texture_cache::{CacheStats, TextureCache, TextureKey};
 
 const _TEXT_SIZE_CONST: f64 = 32_f64 / (1080 * 40) as f64;
 
-pub static _EDGE_PADDING_GLOB: OnceLock<i32> = OnceLock::new();
-pub static _TEXT_SIZE_MOD_GLOB: OnceLock<f64> = OnceLock::new();
+// Scroll speed in EDGE_PADDINGs per second and the pause at each end
+const MARQUEE_SPEED: f32 = 6.0;
+const MARQUEE_PAUSE: Duration = Duration::from_secs(2);
+// Texture memory before the least recently used ones are dropped
+const TEXTURE_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+// Both depend on the screen height, set by `init` / `init_headless`
+static _EDGE_PADDING_GLOB: AtomicI32 = AtomicI32::new(0);
+static _TEXT_SIZE_MOD_GLOB: AtomicU64 = AtomicU64::new(0);
+
+/// Scales paddings and fonts for a screen `h` px high
+pub fn set_screen_height(h: u32) {
+    _EDGE_PADDING_GLOB.store((h / 108) as i32, Ordering::Relaxed);
+    _TEXT_SIZE_MOD_GLOB.store((h as f64 * _TEXT_SIZE_CONST).to_bits(), Ordering::Relaxed);
+}
+
+/// Runs `test` scaled for a screen `h` px high. EDGE_PADDING is global, so tests depending on
+/// it take turns
+#[cfg(test)]
+pub(crate) fn on_screen_height<T>(h: u32, test: impl FnOnce() -> T) -> T {
+    static SCREEN: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _screen = SCREEN.lock().unwrap_or_else(|e| e.into_inner());
+    set_screen_height(h);
+    test()
+}
 
 pub fn get_custom_font_size(s: f64) -> u16 {
-    (_TEXT_SIZE_MOD_GLOB.get().unwrap() * s) as u16
+    (f64::from_bits(_TEXT_SIZE_MOD_GLOB.load(Ordering::Relaxed)) * s) as u16
 }
 
 pub enum FontSize {
@@ -46,11 +79,12 @@ impl FontSize {
 #[allow(non_snake_case)]
 // 10px on a Full-HD screen
 pub fn EDGE_PADDING() -> i32 {
-    *_EDGE_PADDING_GLOB.get().unwrap()
+    _EDGE_PADDING_GLOB.load(Ordering::Relaxed)
 }
 
-const FNT_PTH_ROBOTO: &str = "/home/jasper/res/Roboto-Medium.ttf";
-const FNT_PTH_JETBRAINS_MONO: &str = "/home/jasper/res/JetBrainsMono-Medium.ttf";
+// In the resource directory
+const FNT_ROBOTO: &str = "Roboto-Medium.ttf";
+const FNT_JETBRAINS_MONO: &str = "JetBrainsMono-Medium.ttf";
 
 lazy_static! {
     static ref TTF_CTX: Sdl2TtfContext = {
@@ -120,56 +154,59 @@ pub enum AvailableFonts {
 }
 
 impl AvailableFonts {
-    pub fn to_path(&self) -> &'static str {
-        match self {
+    pub fn to_path(&self) -> String {
+        resource(match self {
             AvailableFonts::Roboto => {
-                FNT_PTH_ROBOTO
+                FNT_ROBOTO
             }
             AvailableFonts::JetbrainsMono => {
-                FNT_PTH_JETBRAINS_MONO
+                FNT_JETBRAINS_MONO
             }
-        }
+        })
     }
 }
 
 fn load_font<'a>(font_path: &str, size: u16) -> UnyoResult<sdl2::ttf::Font<'a, 'a>> {
     TTF_CTX.load_font(font_path, size)
-        .map_err(|_| { UnyoError::UiLoadFont })
+        .map_err(|e| { UnyoError::UiLoadFont(format!("{font_path}: {e}")) })
 }
 
-pub struct Font<'a>(sdl2::ttf::Font<'a, 'a>);
+pub struct Font<'a> {
+    font: sdl2::ttf::Font<'a, 'a>,
+    // Identify the font in the texture cache
+    path: String,
+    size: u16,
+}
 
 impl<'a> Font<'a> {
-    pub fn new(font: sdl2::ttf::Font<'a, 'a>) -> Self {
-        Self(font)
+    pub fn new(font: sdl2::ttf::Font<'a, 'a>, path: &str, size: u16) -> Self {
+        Self {font, path: path.to_string(), size}
     }
 
-    pub fn load_path(font: &str, size: u16) -> Self {
-        load_font(font, size).expect("Failed to load font").into()
+    pub fn load_path(font: &str, size: u16) -> UnyoResult<Self> {
+        Ok(Self::new(load_font(font, size)?, font, size))
     }
     
-    pub fn load(font: AvailableFonts, size: u16) -> Self {
-        Self::load_path(font.to_path(), size)
+    pub fn load(font: AvailableFonts, size: u16) -> UnyoResult<Self> {
+        Self::load_path(&font.to_path(), size)
     }
 
     pub fn char_dim(&self) -> USize {
-        self.0.size_of_char('A').unwrap().into()
+        self.font.size_of_char('A').unwrap().into()
     }
 
     pub fn write_text(&self, text: &str, color: Color) -> (Surface, USize) {
-        let size = self.0.size_of(text).unwrap();
-        let surface = self.0.render(text).solid(color).unwrap();
+        let size = self.font.size_of(text).unwrap();
+        let surface = self.font.render(text).solid(color).unwrap();
         (surface, size.into())
     }
 
     pub fn size_of_text(&self, text: &str) -> USize {
-        self.0.size_of(text).unwrap().into()
+        self.font.size_of(text).unwrap().into()
     }
-}
 
-impl<'a> From<sdl2::ttf::Font<'a, 'a>> for Font<'a> {
-    fn from(value: sdl2::ttf::Font<'a, 'a>) -> Self {
-        Font::new(value)
+    fn cache_key(&self, text: &str, color: Color) -> TextureKey {
+        TextureKey::Text(self.path.clone(), self.size, color.rgba(), text.to_string())
     }
 }
 
@@ -182,34 +219,76 @@ pub struct FontOwner<'a> {
 }
 
 impl<'a> FontOwner<'a> {
-    pub fn new() -> Self {
-        let jb_medium_l = Font::load(AvailableFonts::JetbrainsMono, FontSize::MediumL.to_real_size());
-        let jb_medium_m = Font::load(AvailableFonts::JetbrainsMono, FontSize::MediumM.to_real_size());
-        let jb_medium_s = Font::load(AvailableFonts::JetbrainsMono, FontSize::MediumS.to_real_size());
+    pub fn new() -> UnyoResult<Self> {
+        let jb_medium_l = Font::load(AvailableFonts::JetbrainsMono, FontSize::MediumL.to_real_size())?;
+        let jb_medium_m = Font::load(AvailableFonts::JetbrainsMono, FontSize::MediumM.to_real_size())?;
+        let jb_medium_s = Font::load(AvailableFonts::JetbrainsMono, FontSize::MediumS.to_real_size())?;
 
-        let jb_large_l = Font::load(AvailableFonts::JetbrainsMono, FontSize::LargeL.to_real_size());
-        let jb_large_s = Font::load(AvailableFonts::JetbrainsMono, FontSize::LargeS.to_real_size());
+        let jb_large_l = Font::load(AvailableFonts::JetbrainsMono, FontSize::LargeL.to_real_size())?;
+        let jb_large_s = Font::load(AvailableFonts::JetbrainsMono, FontSize::LargeS.to_real_size())?;
 
-        Self {jb_medium_l, jb_medium_m, jb_medium_s, jb_large_l, jb_large_s}
+        Ok(Self {jb_medium_l, jb_medium_m, jb_medium_s, jb_large_l, jb_large_s})
     }
 }
 
 pub struct UIHelper<'a> {
     pub font_owner: FontOwner<'a>,
-    pub texture_creator: &'a TextureCreator<WindowContext>
+    pub texture_creator: &'a TextureCreator<WindowContext>,
+    texture_cache: RefCell<TextureCache<Texture<'a>>>,
+    // Images that couldn't be loaded, by path and modification time
+    failed_images: RefCell<HashSet<(String, Option<SystemTime>)>>,
 }
 
 impl<'a> UIHelper<'a> {
-    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Self {
-        Self {font_owner: FontOwner::new(), texture_creator}
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Result<Self, String> {
+        let font_owner = FontOwner::new().map_err(|e| format!("{e:?}"))?;
+        Ok(Self {font_owner, texture_creator, texture_cache: RefCell::new(TextureCache::new(TEXTURE_CACHE_BYTES)), failed_images: RefCell::new(HashSet::new())})
     }
     
-    pub fn texture_from_surface(&self, surface: Surface) -> Texture {
+    pub fn texture_from_surface(&self, surface: Surface) -> Texture<'a> {
         self.texture_creator.create_texture_from_surface(surface).unwrap()
     }
-    
-    pub fn image_texture(&self, path: &str) -> Texture {
-        self.texture_creator.load_texture(path).unwrap()
+
+    /// Rendered text, cached by font, color and text
+    pub fn text_texture(&self, font: &Font, text: &str, color: Color) -> (Rc<Texture<'a>>, (u32, u32)) {
+        self.texture_cache.borrow_mut().get_or_insert(font.cache_key(text, color), None, || {
+            let (surface, size) = font.write_text(text, color);
+            (self.texture_from_surface(surface), size.into())
+        })
+    }
+
+    /// Image loaded from disk, cached until the file changes. None if it can't be loaded,
+    /// which is reported once per version of the file
+    pub fn image_texture(&self, path: &str) -> Option<Rc<Texture<'a>>> {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let failed = (path.to_string(), modified);
+        if self.failed_images.borrow().contains(&failed) {
+            return None;
+        }
+        let result = self.texture_cache.borrow_mut().get_or_try_insert(TextureKey::Image(path.to_string()), modified, || {
+            let texture = self.texture_creator.load_texture(path)?;
+            let query = texture.query();
+            Ok::<_, String>((texture, (query.width, query.height)))
+        });
+        match result {
+            Ok((texture, _)) => Some(texture),
+            Err(e) => {
+                eprintln!("Failed to load image {path}: {e}");
+                self.failed_images.borrow_mut().insert(failed);
+                None
+            }
+        }
+    }
+
+    /// Drops the cached texture of an image, e.g. one rewritten within the same second. Files
+    /// with a new modification time are reloaded anyway
+    pub fn invalidate_image(&self, path: &str) {
+        self.texture_cache.borrow_mut().invalidate(&TextureKey::Image(path.to_string()));
+        self.failed_images.borrow_mut().retain(|(failed, _)| failed != path);
+    }
+
+    pub fn texture_cache_stats(&self) -> CacheStats {
+        self.texture_cache.borrow().stats()
     }
 }
 
@@ -236,7 +315,6 @@ impl UIContext {
         self.canvas.copy(texture, None, Rect::new(x, y, size.0, size.1)).expect("Failed to draw texture");
     }
 
-    #[allow(dead_code)]
     pub fn draw_polygon(&mut self, vertices: Vec<(i16, i16)>, color: Color, filled: bool) {
         let mut vx = vec![];
         let mut vy = vec![];
@@ -263,22 +341,52 @@ impl UIContext {
         self.canvas.fill_rect(rect).expect("Failed to draw rectangle")
     }
 
+    /// Covers `rect` with `color` at `alpha` (0 - 255)
+    pub fn fade_rect(&mut self, rect: Rect, color: Color, alpha: u8) {
+        let previous = self.canvas.blend_mode();
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        self.draw_rect(rect, Color::RGBA(color.r, color.g, color.b, alpha));
+        self.canvas.set_blend_mode(previous);
+    }
+
+    /// Moves everything drawn afterwards by (x, y), until it is set back to (0, 0)
+    pub fn set_offset(&mut self, x: i32, y: i32) {
+        if (x, y) == (0, 0) {
+            self.canvas.set_viewport(None);
+        } else {
+            let (w, h) = self.canvas.output_size().expect("Failed to get the output size");
+            self.canvas.set_viewport(Rect::new(x, y, w, h));
+        }
+    }
+
     pub fn draw_line(&mut self, start: Point, end: Point, thickness: i32, color: Color) {
         self.canvas.set_draw_color(color);
         self.canvas.thick_line(start.x as i16, start.y as i16, end.x as i16, end.y as i16, thickness as u8, color).expect("Failed to draw a line")
     }
 
     pub fn draw_text(&mut self, x: i32, y: i32, font: &Font, text: &str, color: Color, uihelper: &UIHelper) -> (i32, i32) {
-        let (surface, size) = font.write_text(text, color);
-        let size = size.into();
-        let texture = uihelper.texture_from_surface(surface);
+        let (texture, size) = uihelper.text_texture(font, text, color);
         self.draw_texture3(&texture, x, y, size);
         (x + size.0 as i32, y)
     }
 
+    /// Draws the part of `text` starting `offset` px in, cut to `width` px
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_text_clipped(&mut self, x: i32, y: i32, width: u32, offset: i32, font: &Font, text: &str, color: Color, uihelper: &UIHelper) {
+        let (texture, size) = uihelper.text_texture(font, text, color);
+        let width = width.min(size.0.saturating_sub(offset.max(0) as u32));
+        if width == 0 {
+            return;
+        }
+        self.canvas.copy(&texture, Rect::new(offset, 0, width, size.1), Rect::new(x, y, width, size.1)).expect("Failed to draw texture");
+    }
+
     pub fn draw_image(&mut self, x: i32, y: i32, size: (u32, u32), path: &str, uihelper: &UIHelper) -> (i32, i32) {
-        let texture = uihelper.image_texture(path);
-        self.draw_texture3(&texture, x, y, size);
+        match uihelper.image_texture(path) {
+            Some(texture) => self.draw_texture3(&texture, x, y, size),
+            // Placeholder of the same size
+            None => self.draw_rect(Rect::new(x, y, size.0, size.1), BG_TINTED)
+        }
         (x + size.0 as i32, y)
     }
     
@@ -295,37 +403,544 @@ impl UIContext {
     pub fn size(&self) -> USize {
         self.canvas.window().size().into()
     }
+
+    /// Copy of what has been drawn since the last `clear`
+    pub fn read_surface(&self) -> Result<Surface<'static>, String> {
+        let (w, h) = self.canvas.output_size()?;
+        let pixels = self.canvas.read_pixels(None, PixelFormatEnum::RGBA32)?;
+        let mut surface = Surface::new(w, h, PixelFormatEnum::RGBA32)?;
+        let pitch = surface.pitch() as usize;
+        surface.with_lock_mut(|data| {
+            for (row, line) in pixels.chunks_exact(w as usize * 4).enumerate() {
+                data[row * pitch..row * pitch + line.len()].copy_from_slice(line);
+            }
+        });
+        Ok(surface)
+    }
     
-    pub fn draw(&mut self, drawable: &impl Drawable, uihelper: &UIHelper) {
+    pub fn draw(&mut self, drawable: &(impl Drawable + ?Sized), uihelper: &UIHelper) {
         drawable.draw(self, uihelper)
     }
 }
 
+/// Text in a box of fixed pixel width, centered if it fits, otherwise scrolling
+/// back and forth with a pause at each end
+pub struct Marquee {
+    // Text shown, since when (the scrolling restarts when it changes) and how much does not fit
+    shown: RefCell<(String, Instant, i32)>,
+}
+
+impl Default for Marquee {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Marquee {
+    pub fn new() -> Self {
+        Self {shown: RefCell::new((String::new(), Instant::now(), 0))}
+    }
+
+    // Offset and when it changes next
+    fn offset(overflow: i32, elapsed: Duration) -> (i32, Redraw) {
+        let speed = MARQUEE_SPEED * EDGE_PADDING() as f32;
+        let scroll = overflow as f32 / speed;
+        let pause = MARQUEE_PAUSE.as_secs_f32();
+        let t = elapsed.as_secs_f32() % (2.0 * (pause + scroll));
+        let until = |end: f32| Redraw::At(Instant::now() + Duration::from_secs_f32(end - t));
+
+        let (offset, redraw) = if t < pause { (0.0, until(pause)) }
+        else if t < pause + scroll { ((t - pause) * speed, Redraw::Animating) }
+        else if t < 2.0 * pause + scroll { (overflow as f32, until(2.0 * pause + scroll)) }
+        else { (overflow as f32 - (t - 2.0 * pause - scroll) * speed, Redraw::Animating) };
+        ((offset as i32).clamp(0, overflow), redraw)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(&self, ctx: &mut UIContext, x: i32, y: i32, width: u32, font: &Font, text: &str, color: Color, uihelper: &UIHelper) {
+        let mut shown = self.shown.borrow_mut();
+        if shown.0 != text {
+            *shown = (text.to_string(), Instant::now(), 0);
+        }
+
+        let text_width = font.size_of_text(text).one();
+        shown.2 = text_width.saturating_sub(width) as i32;
+        if shown.2 == 0 {
+            ctx.draw_text(x + (width - text_width) as i32 / 2, y, font, text, color, uihelper);
+            return;
+        }
+
+        let (offset, _) = Self::offset(shown.2, shown.1.elapsed());
+        ctx.draw_text_clipped(x, y, width, offset, font, text, color, uihelper);
+    }
+
+    /// As of the last `draw`
+    pub fn redraw(&self) -> Redraw {
+        let shown = self.shown.borrow();
+        if shown.2 == 0 { Redraw::Idle } else { Self::offset(shown.2, shown.1.elapsed()).1 }
+    }
+}
+
+/// Size of a layout node along its parent's axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Length {
+    Px(i32),
+    /// Multiples of EDGE_PADDING, so it scales with the screen
+    Ep(f32),
+    /// Part of the parent's size (without padding), e.g. `fraction(5, 9)`
+    Fraction(f32),
+    /// Share of the space the fixed siblings leave, fills the parent on the cross axis
+    Flex(u32),
+}
+
+impl Length {
+    // Size in px, None for flex
+    fn fixed(&self, parent: i32) -> Option<i32> {
+        match *self {
+            Length::Px(px) => Some(px),
+            Length::Ep(ep) => Some((ep * EDGE_PADDING() as f32) as i32),
+            Length::Fraction(fraction) => Some((parent as f32 * fraction) as i32),
+            Length::Flex(_) => None
+        }
+    }
+}
+
+/// Where a child smaller than its slot is put
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Arrange {
+    Row,
+    Column,
+    // On top of each other
+    Stack,
+}
+
+// Start and size of something `length` long in `available` px
+fn align_axis(start: i32, available: i32, length: Length, align: Align) -> (i32, i32) {
+    let size = length.fixed(available).unwrap_or(available).clamp(0, available.max(0));
+    let offset = match align {
+        Align::Start => 0,
+        Align::Center => (available - size) / 2,
+        Align::End => available - size
+    };
+    (start + offset, size)
+}
+
+fn rect_of(x: i32, y: i32, w: i32, h: i32) -> Rect {
+    Rect::new(x, y, w.max(0) as u32, h.max(0) as u32)
+}
+
+/// Rows, columns and stacks of slots, resolved into `Rect`s for a given screen size.
+/// Widgets get the `Rect` of their named slot, so nothing depends on the resolution
+#[derive(Clone, Debug)]
+pub struct Layout {
+    arrange: Arrange,
+    name: Option<String>,
+    width: Length,
+    height: Length,
+    // Left, top, right, bottom
+    padding: (i32, i32, i32, i32),
+    spacing: i32,
+    halign: Align,
+    valign: Align,
+    children: Vec<Layout>,
+}
+
+impl Layout {
+    fn new(arrange: Arrange, children: Vec<Layout>) -> Self {
+        Self {arrange, name: None, width: Length::Flex(1), height: Length::Flex(1), padding: (0, 0, 0, 0),
+            spacing: 0, halign: Align::Start, valign: Align::Start, children}
+    }
+
+    /// Children left to right
+    pub fn row(children: Vec<Layout>) -> Self {
+        Self::new(Arrange::Row, children)
+    }
+
+    /// Children top to bottom
+    pub fn column(children: Vec<Layout>) -> Self {
+        Self::new(Arrange::Column, children)
+    }
+
+    /// Children on top of each other
+    pub fn stack(children: Vec<Layout>) -> Self {
+        Self::new(Arrange::Stack, children)
+    }
+
+    /// Empty space
+    pub fn leaf() -> Self {
+        Self::stack(vec![])
+    }
+
+    /// The slot of a widget
+    pub fn widget(name: &str) -> Self {
+        Self::leaf().named(name)
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn width(mut self, width: Length) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: Length) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn padding(self, padding: i32) -> Self {
+        self.padding_sides(padding, padding, padding, padding)
+    }
+
+    pub fn padding_sides(mut self, left: i32, top: i32, right: i32, bottom: i32) -> Self {
+        self.padding = (left, top, right, bottom);
+        self
+    }
+
+    /// Gap between the children of a row / column
+    pub fn spacing(mut self, spacing: i32) -> Self {
+        self.spacing = spacing;
+        self
+    }
+
+    /// Where the children go if they do not fill this node
+    pub fn align(mut self, halign: Align, valign: Align) -> Self {
+        self.halign = halign;
+        self.valign = valign;
+        self
+    }
+
+    /// Assigns a `Rect` to every node within `bounds`
+    pub fn resolve(&self, bounds: Rect) -> Placement {
+        let mut placement = Placement::default();
+        self.place(bounds, &mut placement);
+        placement
+    }
+
+    fn place(&self, bounds: Rect, placement: &mut Placement) {
+        let (left, top, right, bottom) = self.padding;
+        let inner = rect_of(bounds.x + left, bounds.y + top, bounds.w - left - right, bounds.h - top - bottom);
+        let slots = match self.arrange {
+            Arrange::Row => self.split(inner, true),
+            Arrange::Column => self.split(inner, false),
+            Arrange::Stack => self.children.iter().map(|child| {
+                let (x, w) = align_axis(inner.x, inner.w, child.width, self.halign);
+                let (y, h) = align_axis(inner.y, inner.h, child.height, self.valign);
+                rect_of(x, y, w, h)
+            }).collect()
+        };
+
+        for (child, slot) in self.children.iter().zip(&slots) {
+            child.place(*slot, placement);
+        }
+        if let Some(name) = &self.name {
+            // Widgets get their slot without the padding
+            placement.rects.insert(name.clone(), if self.children.is_empty() { inner } else { bounds });
+            placement.children.insert(name.clone(), slots);
+        }
+    }
+
+    // Slots of a row (horizontal) or column
+    fn split(&self, inner: Rect, horizontal: bool) -> Vec<Rect> {
+        let (start, main, cross_start, cross) = if horizontal { (inner.x, inner.w, inner.y, inner.h) } else { (inner.y, inner.h, inner.x, inner.w) };
+        let (main_align, cross_align) = if horizontal { (self.halign, self.valign) } else { (self.valign, self.halign) };
+        let lengths = |child: &Layout| if horizontal { (child.width, child.height) } else { (child.height, child.width) };
+
+        let gaps = self.spacing * (self.children.len() as i32 - 1).max(0);
+        let fixed = self.children.iter().filter_map(|child| lengths(child).0.fixed(main)).fold(0, i32::saturating_add);
+        let weights: u32 = self.children.iter().map(|child| match lengths(child).0 {
+            Length::Flex(weight) => weight,
+            _ => 0
+        }).sum();
+        let free = main.saturating_sub(gaps).saturating_sub(fixed).max(0);
+
+        // Without flex children the free space is left according to the alignment
+        let mut position = start + match main_align {
+            _ if weights != 0 => 0,
+            Align::Start => 0,
+            Align::Center => free / 2,
+            Align::End => free
+        };
+        let mut weight_before = 0;
+        self.children.iter().map(|child| {
+            let (length, cross_length) = lengths(child);
+            let size = match length {
+                // Nothing to share if all weights are 0
+                Length::Flex(_) if weights == 0 => 0,
+                // Cumulative, so rounding never loses a pixel
+                Length::Flex(weight) => {
+                    let before = free * weight_before as i32 / weights as i32;
+                    weight_before += weight;
+                    free * weight_before as i32 / weights as i32 - before
+                }
+                length => length.fixed(main).unwrap()
+            };
+            let (cross_position, cross_size) = align_axis(cross_start, cross, cross_length, cross_align);
+            let slot = if horizontal { rect_of(position, cross_position, size, cross_size) } else { rect_of(cross_position, position, cross_size, size) };
+            position += size + self.spacing;
+            slot
+        }).collect()
+    }
+}
+
+/// Result of `Layout::resolve`, by name
+#[derive(Default)]
+pub struct Placement {
+    rects: HashMap<String, Rect>,
+    children: HashMap<String, Vec<Rect>>,
+}
+
+impl Placement {
+    pub fn rect(&self, name: &str) -> Rect {
+        *self.rects.get(name).unwrap_or_else(|| panic!("'{name}' is not part of the layout"))
+    }
+
+    /// Slots of the children of a named row / column / stack
+    pub fn children(&self, name: &str) -> &[Rect] {
+        self.children.get(name).unwrap_or_else(|| panic!("'{name}' is not part of the layout"))
+    }
+}
+
+/// Whether a widget used an event, unhandled ones go on to the next receiver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Handled {
+    Yes,
+    No,
+}
+
+/// Where a pointer event happened, for hit-testing (touch arrives as mouse events)
+pub fn pointer_position(event: &Event) -> Option<Point> {
+    match *event {
+        Event::MouseButtonDown { x, y, .. } | Event::MouseButtonUp { x, y, .. } | Event::MouseMotion { x, y, .. } => Some(Point::new(x, y)),
+        _ => None
+    }
+}
+
 pub trait Drawable {
     fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper);
+
+    /// Area the widget covers on screen
+    fn bounds(&self) -> Rect;
+
+    /// Whether pointer events at `point` go to this widget
+    fn hit_test(&self, point: Point) -> bool {
+        self.bounds().contains_point(point)
+    }
+
+    /// Advances state off the draw path, `dt` is the time since the last update
+    fn update(&mut self, _dt: Duration) {}
+
+    /// Pointer events that hit the widget, keys while it has the focus
+    fn handle_event(&mut self, _event: &Event) -> Handled {
+        Handled::No
+    }
+
+    /// Gestures that started on the widget
+    fn handle_gesture(&mut self, _gesture: &Gesture) -> Handled {
+        Handled::No
+    }
+
+    /// Whether the widget takes the keyboard focus
+    fn focusable(&self) -> bool {
+        false
+    }
+
+    fn set_focused(&mut self, _focused: bool) {}
+
+    /// When the widget has to be drawn again without anything else changing
+    fn redraw(&self) -> Redraw {
+        Redraw::Idle
+    }
+
+    /// Whether store data the widget shows changed since it was last drawn
+    fn changed(&self) -> bool {
+        false
+    }
 }
 
-pub fn init() -> Result<(sdl2::video::Window, EventPump), String> {
+/// Where the UI is shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowMode {
+    // Fullscreen on the panel (display 0)
+    Panel,
+    // Normal desktop window of that size
+    Desktop(u32, u32),
+}
+
+pub fn init(mode: WindowMode) -> Result<(sdl2::video::Window, EventPump), String> {
     let sdl_context = sdl2::init()?;
     let event_pump = sdl_context.event_pump()?;
+    init_wake_event(&sdl_context.event()?)?;
     let video_subsystem = sdl_context.video()?;
-    // Currently only needs PNG, maybe add JPG support?
-    let _image_context = sdl2::image::init(InitFlag::PNG)?;
-    let (w, h) = video_subsystem.display_bounds(0).expect("Display not found!!").size();
-    _EDGE_PADDING_GLOB.set((h / 108) as i32).expect("Failed to set global");
-    _TEXT_SIZE_MOD_GLOB.set(h as f64 * _TEXT_SIZE_CONST).expect("Failed to set global");
-    sdl_context.mouse().show_cursor(false);
+    // PNG for icons, JPG for cover art
+    let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
+    let (w, h) = match mode {
+        WindowMode::Panel => video_subsystem.display_bounds(0).expect("Display not found!!").size(),
+        WindowMode::Desktop(w, h) => (w, h)
+    };
+    set_screen_height(h);
+    sdl_context.mouse().show_cursor(mode != WindowMode::Panel);
     
     println!("Unyo running ({w}x{h})");
 
-    // Try forcing SDL to use KMSDRM (no X11)
-    std::env::set_var("SDL_VIDEODRIVER", "KMSDRM");
-
-    let window = video_subsystem
-        .window("unyo", w, h)
-        .vulkan()
-        .build()
-        .map_err(|e| e.to_string())?;
+    let mut builder = video_subsystem.window("unyo", w, h);
+    if mode == WindowMode::Panel {
+        // Try forcing SDL to use KMSDRM (no X11)
+        std::env::set_var("SDL_VIDEODRIVER", "KMSDRM");
+        builder.vulkan();
+    } else {
+        builder.position_centered();
+    }
+    let window = builder.build().map_err(|e| e.to_string())?;
     
     Ok((window, event_pump))
-}
\ No newline at end of file
+}
+
+/// Hidden window of `size` for software rendering, without a display (SDL's dummy driver unless
+/// `SDL_VIDEODRIVER` says otherwise). Paddings and fonts are scaled for a screen `screen_height` px high
+pub fn init_headless(size: (u32, u32), screen_height: u32) -> Result<sdl2::video::Window, String> {
+    if std::env::var_os("SDL_VIDEODRIVER").is_none() {
+        std::env::set_var("SDL_VIDEODRIVER", "dummy");
+    }
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
+    set_screen_height(screen_height);
+
+    video_subsystem
+        .window("unyo", size.0, size.1)
+        .hidden()
+        .build()
+        .map_err(|e| e.to_string())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREENS: [(u32, u32); 3] = [(1280, 720), (1920, 1080), (3840, 2160)];
+
+    fn on_screens(test: impl Fn(Rect)) {
+        for (w, h) in SCREENS {
+            on_screen_height(h, || test(Rect::new(0, 0, w, h)));
+        }
+    }
+
+    #[test]
+    fn flex_weights() {
+        on_screens(|screen| {
+            let placement = Layout::row(vec![
+                Layout::leaf(),
+                Layout::leaf().width(Length::Flex(2)),
+                Layout::leaf(),
+            ]).named("row").resolve(screen);
+            let slots = placement.children("row");
+            assert_eq!(slots.iter().map(|slot| slot.w).collect::<Vec<_>>(), [screen.w / 4, screen.w / 2, screen.w / 4]);
+            // Next to each other, filling the row and its height
+            for pair in slots.windows(2) {
+                assert_eq!(pair[0].right(), pair[1].x);
+            }
+            assert_eq!(slots.last().unwrap().right(), screen.right());
+            assert!(slots.iter().all(|slot| slot.y == 0 && slot.h == screen.h));
+        });
+    }
+
+    #[test]
+    fn flex_rounding() {
+        on_screens(|screen| {
+            let placement = Layout::row(vec![Layout::leaf(); 7]).named("row").resolve(screen);
+            let slots = placement.children("row");
+            assert_eq!(slots.iter().map(|slot| slot.w).sum::<i32>(), screen.w);
+            assert!(slots.iter().all(|slot| slot.w.abs_diff(screen.w / 7) <= 1));
+        });
+    }
+
+    #[test]
+    fn fixed_sizes() {
+        on_screens(|screen| {
+            let ep = screen.h / 108;
+            let placement = Layout::column(vec![
+                Layout::widget("px").height(Length::Px(50)),
+                Layout::widget("ep").height(Length::Ep(3.0)),
+                Layout::widget("fraction").height(Length::Fraction(0.25)),
+                Layout::widget("rest"),
+            ]).resolve(screen);
+            assert_eq!(placement.rect("px"), Rect::new(0, 0, screen.width(), 50));
+            assert_eq!(placement.rect("ep"), Rect::new(0, 50, screen.width(), 3 * ep as u32));
+            assert_eq!(placement.rect("fraction"), Rect::new(0, 50 + 3 * ep, screen.width(), screen.height() / 4));
+            let rest = placement.rect("rest");
+            assert_eq!((rest.y, rest.bottom()), (50 + 3 * ep + screen.h / 4, screen.bottom()));
+        });
+    }
+
+    #[test]
+    fn alignment() {
+        on_screens(|screen| {
+            let ep = screen.h / 108;
+            let row = |halign| Layout::row(vec![Layout::leaf().width(Length::Ep(10.0)); 2])
+                .named("row").spacing(ep).align(halign, Align::Center).height(Length::Ep(4.0));
+            let placement = Layout::stack(vec![row(Align::Start).named("start"), row(Align::Center).named("center"), row(Align::End).named("end")])
+                .align(Align::Start, Align::End).resolve(screen);
+
+            let width = 2 * 10 * ep + ep;
+            let first_x = |name| placement.children(name)[0].x;
+            assert_eq!(first_x("start"), 0);
+            assert_eq!(first_x("center"), (screen.w - width) / 2);
+            assert_eq!(first_x("end"), screen.w - width);
+            assert_eq!(placement.children("end")[1].right(), screen.right());
+            assert_eq!(placement.children("end")[1].x - placement.children("end")[0].right(), ep);
+
+            // The stack puts the rows at the bottom
+            assert_eq!(placement.rect("end"), Rect::new(0, screen.h - 4 * ep, screen.width(), 4 * ep as u32));
+        });
+    }
+
+    #[test]
+    fn padding() {
+        on_screens(|screen| {
+            let ep = EDGE_PADDING();
+            let placement = Layout::column(vec![Layout::leaf(), Layout::leaf()])
+                .named("column").padding(ep).spacing(ep).resolve(screen);
+            let slots = placement.children("column");
+            let inner = Rect::new(ep, ep, screen.width() - 2 * ep as u32, screen.height() - 2 * ep as u32);
+            assert_eq!(slots[0].top_left(), inner.top_left());
+            assert_eq!(slots[1].bottom_right(), inner.bottom_right());
+            assert_eq!(slots[1].y - slots[0].bottom(), ep);
+            assert!(slots.iter().all(|slot| slot.x == inner.x && slot.w == inner.w));
+            // The column itself keeps its whole slot
+            assert_eq!(placement.rect("column"), screen);
+
+            let placement = Layout::row(vec![Layout::widget("padded").padding(ep), Layout::widget("plain")]).resolve(screen);
+            let padded = placement.rect("padded");
+            assert_eq!(padded, Rect::new(ep, ep, screen.width() / 2 - 2 * ep as u32, screen.height() - 2 * ep as u32));
+            assert_eq!(placement.rect("plain").x, screen.w / 2);
+        });
+    }
+
+    #[test]
+    fn zero_weights() {
+        on_screens(|screen| {
+            // Nothing to share, the fixed child still gets its size
+            let placement = Layout::row(vec![
+                Layout::leaf().width(Length::Flex(0)),
+                Layout::leaf().width(Length::Px(100)),
+                Layout::leaf().width(Length::Flex(0)),
+            ]).named("row").resolve(screen);
+            let slots = placement.children("row");
+            assert_eq!((slots[1].x, slots[1].w), (0, 100));
+
+            // Too large fixed sizes don't overflow
+            let placement = Layout::column(vec![Layout::leaf().height(Length::Px(i32::MAX)), Layout::leaf()]).named("column").resolve(screen);
+            assert_eq!(placement.children("column")[0].y, 0);
+        });
+    }
+}
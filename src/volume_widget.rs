@@ -0,0 +1,63 @@
+use std::time::Duration;
+use sdl2::rect::{Point, Rect};
+use crate::bluetooth::{MediaTransport, VOLUME_MAX};
+use crate::color::{BG_SHADED, PB_EMPTY, PB_FULLY, TXT_DEFAULT};
+use crate::redraw::Redraw;
+use crate::state::{Store, Subscription};
+use crate::ui_renderer::{Drawable, UIContext, UIHelper, EDGE_PADDING};
+
+const VOLUME_OVERLAY_DURATION: Duration = Duration::from_secs(2);
+
+/// Shows the volume for a moment after it changed
+pub struct VolumeOverlay {
+    position: Rect,
+    transport: Subscription<Option<MediaTransport>>,
+}
+
+impl VolumeOverlay {
+    pub fn new(position: Rect, store: &Store) -> Self {
+        Self {position, transport: store.transport.subscribe()}
+    }
+}
+
+impl Drawable for VolumeOverlay {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
+    fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
+        let transport = self.transport.get();
+        let Some(transport) = transport.as_ref() else { return };
+        let (Some(volume), Some(changed)) = (transport.volume, transport.volume_changed) else { return };
+        if changed.elapsed() > VOLUME_OVERLAY_DURATION {
+            return;
+        }
+
+        ctx.draw_rect(self.position, BG_SHADED);
+        let percent = (volume as u32 * 100 / VOLUME_MAX as u32).to_string();
+        let text_size = uihelper.font_owner.jb_medium_l.size_of_text(percent.as_str());
+        let y = self.position.y + self.position.h / 2;
+        ctx.draw_text(self.position.right() - 2 * EDGE_PADDING() - text_size.one() as i32, y - text_size.two() as i32 / 2,
+                      &uihelper.font_owner.jb_medium_l, percent.as_str(), TXT_DEFAULT, uihelper);
+
+        let start = self.position.x + 3 * EDGE_PADDING();
+        let end = self.position.right() - 5 * EDGE_PADDING() - text_size.one() as i32;
+        let length = (end - start) * volume as i32 / VOLUME_MAX as i32;
+        ctx.draw_line(Point::new(start, y), Point::new(end, y), 2 * EDGE_PADDING(), PB_EMPTY);
+        if length != 0 {
+            ctx.draw_line(Point::new(start, y), Point::new(start + length, y), 2 * EDGE_PADDING(), PB_FULLY);
+        }
+    }
+
+    fn redraw(&self) -> Redraw {
+        // Hide it again
+        match self.transport.peek().as_ref().and_then(|t| t.volume_changed) {
+            Some(changed) if changed.elapsed() <= VOLUME_OVERLAY_DURATION => Redraw::At(changed + VOLUME_OVERLAY_DURATION),
+            _ => Redraw::Idle
+        }
+    }
+
+    fn changed(&self) -> bool {
+        self.transport.changed()
+    }
+}
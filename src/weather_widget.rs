@@ -1,13 +1,15 @@
 use sdl2::rect::Rect;
-use crate::api::{WEATHER_INFO};
-use crate::ui_renderer::{Drawable, UIContext, UIHelper, USize, EDGE_PADDING};
-use chrono::{Datelike, Duration, Local, Timelike, Weekday};
-use crate::bluetooth::limit_string_size;
+use crate::api::{WeatherInfo, FORECAST_DAYS};
+use crate::ui_renderer::{Drawable, Layout, Length, UIContext, UIHelper, EDGE_PADDING};
+use chrono::{Datelike, Duration, Timelike, Weekday};
+use crate::clock;
+use crate::config::resource;
+use crate::media::limit_string_size;
+use crate::state::{Store, Subscription};
 use crate::color::{BG_SHADED, DIV_LINE, TXT_DEFAULT, TXT_SUBTEXT, TXT_WEATHER};
-use crate::fraction;
 
 fn day_of_week_with_offset(days_offset: i64) -> String {
-    let today = Local::now().date_naive();
+    let today = clock::now().date_naive();
     let offset_date = today + Duration::days(days_offset + 1);
     let wd = offset_date.weekday();
     
@@ -37,7 +39,7 @@ fn day_of_week_with_offset(days_offset: i64) -> String {
 }
 
 pub fn time_with_hour_offset(hours_offset: i64) -> String {
-    let now = Local::now().naive_local();
+    let now = clock::now().naive_local();
     let offset_time = now + Duration::hours(hours_offset);
     format!("{}:00", offset_time.time().hour().to_string())
 }
@@ -50,32 +52,49 @@ enum WeatherImage {
 }
 
 impl WeatherImage {
-    pub fn to_path(&self) -> &str {
-        match self {
+    pub fn to_path(&self) -> String {
+        resource(match self {
             WeatherImage::Sun => {
-                "/home/jasper/res/sun.png"
+                "sun.png"
             }
             WeatherImage::Moon => {
-                "/home/jasper/res/moon.png"
+                "moon.png"
             }
             WeatherImage::Rain => {
-                "/home/jasper/res/rain.png"
+                "rain.png"
             }
             WeatherImage::Cloud => {
-                "/home/jasper/res/cloudy.png"
+                "cloudy.png"
             }
-        }
+        })
     }
 }
 
+// Width of an hour of the forecast, the days share the row
+const HOUR_WIDTH: f32 = 21.0;
+
 pub struct WeatherWidget {
-    position: Rect
+    position: Rect,
+    // Title and current weather
+    header: Rect,
+    // Slots of the next hours (those that fit) and days
+    hourly: Vec<Rect>,
+    daily: Vec<Rect>,
+    weather: Subscription<Option<WeatherInfo>>,
 }
 
 impl WeatherWidget {
-    pub fn new(w_size: &USize) -> Self {
-        let position = w_size.scale_1_2(fraction(5, 9), 0.5).to_rect(EDGE_PADDING(), (w_size.two() / 2) as i32 - EDGE_PADDING());
-        Self {position}
+    pub fn new(position: Rect, hours: u32, store: &Store) -> Self {
+        let placement = Layout::column(vec![
+            Layout::widget("header").height(Length::Ep(14.0)),
+            Layout::row(vec![Layout::leaf().width(Length::Ep(HOUR_WIDTH)); hours as usize]).named("hourly").padding_sides(4 * EDGE_PADDING(), 0, 0, 0),
+            Layout::row(vec![Layout::leaf(); FORECAST_DAYS]).named("daily").height(Length::Ep(12.0)),
+        ]).padding(EDGE_PADDING()).resolve(position);
+        // Only as many hours as fit
+        let row = placement.rect("hourly");
+        let hourly = placement.children("hourly").iter().copied().take_while(|slot| slot.right() <= row.right()).collect();
+        Self {position, header: placement.rect("header"), hourly,
+            daily: placement.children("daily").to_vec(), weather: store.weather.subscribe()}
     }
     
     fn select_image_for_params(
@@ -106,65 +125,58 @@ fn add_degree(x: f64) -> String {
 }
 
 impl Drawable for WeatherWidget {
+    fn bounds(&self) -> Rect {
+        self.position
+    }
+
     fn draw(&self, ctx: &mut UIContext, uihelper: &UIHelper) {
-        if let Some(weather_info) = &*WEATHER_INFO() {
+        if let Some(weather_info) = self.weather.get().as_ref() {
             let medium_l_char_size = uihelper.font_owner.jb_medium_l.char_dim();
             let medium_m_char_size = uihelper.font_owner.jb_medium_m.char_dim();
             let medium_s_char_size = uihelper.font_owner.jb_medium_s.char_dim();
 
             ctx.draw_rect(self.position, BG_SHADED);
             ctx.draw_line(self.position.top_right(), self.position.top_left(), EDGE_PADDING() / 2, DIV_LINE);
-            let (x, y) = ctx.draw_text(self.position.x + EDGE_PADDING(), self.position.y + EDGE_PADDING(), &uihelper.font_owner.jb_medium_l,
-                                       format!("WETTER (in {})", limit_string_size(weather_info.city, 9)).as_str(), TXT_DEFAULT, uihelper);
+            let (x, y) = ctx.draw_text(self.header.x, self.header.y, &uihelper.font_owner.jb_medium_l,
+                                       format!("WETTER (in {})", limit_string_size(weather_info.city.clone(), 9)).as_str(), TXT_DEFAULT, uihelper);
 
             let w_current_p = self.select_image_for_params(weather_info.current.1, Some(weather_info.current.2), None, Some(weather_info.is_day));
             let (x, y) = ctx.draw_text(x + (medium_l_char_size.one() * 4) as i32, y, &uihelper.font_owner.jb_medium_l,
                                        format!("Aktuell: {} °C", weather_info.current.0).as_str(), TXT_SUBTEXT, uihelper);
-            ctx.draw_image(x + (medium_l_char_size.one() * 3) as i32, y, medium_l_char_size.scale_1(2.5).into(), w_current_p.to_path(), uihelper);
+            ctx.draw_image(x + (medium_l_char_size.one() * 3) as i32, y, medium_l_char_size.scale_1(2.5).into(), &w_current_p.to_path(), uihelper);
 
-            let (mut x, mut y) = (self.position.x + EDGE_PADDING(), y + 40 * EDGE_PADDING());
             let day_img_size = medium_s_char_size.scale_1_2(3f32, 1.8).into();
             let hour_img_size = medium_m_char_size.scale_1_2(3.5, 2f32).into();
 
-            for (day, data) in weather_info.daily.iter().enumerate() {
+            for (day, (data, slot)) in weather_info.daily.iter().zip(&self.daily).enumerate() {
                 let name = day_of_week_with_offset(day as i64);
 
-                let xp = x + EDGE_PADDING() * 8 * (day != 0) as i32;    // Bounding position for item
-
-                let rebound = ctx.draw_text(xp, y, &uihelper.font_owner.jb_medium_l, name.as_str(), TXT_WEATHER, uihelper);
-                x = rebound.0;
-                y = rebound.1;
+                let (x, y) = ctx.draw_text(slot.x, slot.y, &uihelper.font_owner.jb_medium_l, name.as_str(), TXT_WEATHER, uihelper);
 
                 let img = self.select_image_for_params(data.2, None, Some(data.3), None);
-                ctx.draw_image(x + 2 * EDGE_PADDING(), y + EDGE_PADDING(), day_img_size, img.to_path(), uihelper);
+                ctx.draw_image(x + 2 * EDGE_PADDING(), y + EDGE_PADDING(), day_img_size, &img.to_path(), uihelper);
 
-                ctx.draw_text(xp, y + 3 * EDGE_PADDING() + medium_s_char_size.two() as i32, &uihelper.font_owner.jb_medium_s, add_degree(data.0).as_str(), TXT_WEATHER, uihelper);
+                ctx.draw_text(slot.x, y + 3 * EDGE_PADDING() + medium_s_char_size.two() as i32, &uihelper.font_owner.jb_medium_s, add_degree(data.0).as_str(), TXT_WEATHER, uihelper);
             }
 
-            y = self.position.y + 15 * EDGE_PADDING();
-            x = self.position.x + 5 * EDGE_PADDING();
-
-            for (hour, data) in weather_info.hourly.iter().enumerate() {
+            for (hour, (data, slot)) in weather_info.hourly.iter().zip(&self.hourly).enumerate() {
                 let dstr = add_degree(data.0);
                 let name = time_with_hour_offset(hour as i64);
+                let (xp, y) = (slot.x, slot.y);
 
-                let xp = x + EDGE_PADDING() * 6 * (hour != 0) as i32;
-
-                let rebound = ctx.draw_text(xp, y, &uihelper.font_owner.jb_medium_l, name.as_str(), TXT_WEATHER, uihelper);
-                x = rebound.0 + 3 * EDGE_PADDING();
-                y = rebound.1;
+                ctx.draw_text(xp, y, &uihelper.font_owner.jb_medium_l, name.as_str(), TXT_WEATHER, uihelper);
 
                 // Center text, add a full char to the right if there is no comma/point, add 0.3 if there is
                 let (_, ty) = ctx.draw_text(if dstr.len() == 5 { xp + medium_m_char_size.one() as i32 } else { xp + (medium_m_char_size.one() as f32 * 0.3) as i32 },
                                             y + (1.5 * medium_m_char_size.two() as f32) as i32, &uihelper.font_owner.jb_medium_m, dstr.as_str(), TXT_WEATHER, uihelper);
 
                 let img = self.select_image_for_params(data.1, Some(data.2), None, None);
-                ctx.draw_image(xp + (medium_m_char_size.one() as f32 * 1.25) as i32, ty + (1.75 * medium_m_char_size.two() as f32) as i32, hour_img_size, img.to_path(), uihelper);
-
-                if hour >= 4 {
-                    break;
-                }
+                ctx.draw_image(xp + (medium_m_char_size.one() as f32 * 1.25) as i32, ty + (1.75 * medium_m_char_size.two() as f32) as i32, hour_img_size, &img.to_path(), uihelper);
             }
         }
     }
+
+    fn changed(&self) -> bool {
+        self.weather.changed()
+    }
 }
\ No newline at end of file
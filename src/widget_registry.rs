@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use sdl2::rect::Rect;
+use serde_json::{Map, Value};
+use crate::config::{get_bool, get_u32, ScreenNode};
+use crate::history_widget::RecentlyPlayedWidget;
+use crate::info_widget::InfoWidget;
+use crate::lyrics_widget::LyricsWidget;
+use crate::state::Store;
+use crate::ui_renderer::{Drawable, Layout, EDGE_PADDING};
+use crate::weather_widget::WeatherWidget;
+
+/// Creates a widget in its slot, showing `store`, with the options of the instance
+pub type WidgetFactory = fn(Rect, &Store, &Map<String, Value>) -> Box<dyn Drawable>;
+
+/// Widget types that can be used in the `screen` config, by name
+pub struct WidgetRegistry {
+    factories: HashMap<&'static str, WidgetFactory>,
+}
+
+impl Default for WidgetRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WidgetRegistry {
+    pub fn new() -> Self {
+        Self {factories: HashMap::new()}
+    }
+
+    pub fn register(&mut self, kind: &'static str, factory: WidgetFactory) {
+        self.factories.insert(kind, factory);
+    }
+
+    /// All widgets unyo ships with
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        registry.register("info", |position, store, options| Box::new(InfoWidget::new(position, get_bool(options, "spectrum", true), store)));
+        registry.register("weather", |position, store, options| Box::new(WeatherWidget::new(position, get_u32(options, "hours", 5), store)));
+        registry.register("lyrics", |position, store, _| Box::new(LyricsWidget::new(position, store)));
+        registry.register("history", |position, _, _| Box::new(RecentlyPlayedWidget::new(position)));
+        registry
+    }
+
+    /// A single widget, None if `kind` is not registered
+    pub fn create(&self, kind: &str, position: Rect, store: &Store, options: &Map<String, Value>) -> Option<Box<dyn Drawable>> {
+        self.factories.get(kind).map(|factory| factory(position, store, options))
+    }
+
+    // Containers become layouts, widgets slots named by their index in `instances`
+    fn to_layout<'a>(&self, node: &'a ScreenNode, instances: &mut Vec<&'a ScreenNode>) -> Layout {
+        let mut children = || node.children.iter().map(|child| self.to_layout(child, instances)).collect();
+        let layout = match node.kind.as_str() {
+            "row" => Layout::row(children()),
+            "column" => Layout::column(children()),
+            "stack" => Layout::stack(children()),
+            kind if self.factories.contains_key(kind) => {
+                instances.push(node);
+                Layout::widget(&(instances.len() - 1).to_string())
+            }
+            kind => {
+                eprintln!("Unknown widget: {kind}");
+                Layout::leaf()
+            }
+        };
+        let ep = |value: f32| (value * EDGE_PADDING() as f32) as i32;
+        layout.width(node.width)
+            .height(node.height)
+            .padding(ep(node.padding))
+            .spacing(ep(node.spacing))
+            .align(node.halign, node.valign)
+    }
+
+    /// The widget nodes of a screen tree with their slots within `bounds`, in drawing order
+    pub fn slots<'a>(&self, root: &'a ScreenNode, bounds: Rect) -> Vec<(&'a ScreenNode, Rect)> {
+        let mut instances = vec![];
+        let placement = self.to_layout(root, &mut instances).resolve(bounds);
+        instances.into_iter().enumerate().map(|(index, node)| (node, placement.rect(&index.to_string()))).collect()
+    }
+
+    /// The widgets of a screen tree within `bounds`, in drawing order
+    pub fn build(&self, root: &ScreenNode, bounds: Rect, store: &Store) -> Vec<Box<dyn Drawable>> {
+        self.slots(root, bounds).into_iter()
+            .filter_map(|(node, slot)| self.create(&node.kind, slot, store, &node.options))
+            .collect()
+    }
+}
@@ -1,14 +1,9 @@
 use std::process::Command;
-use std::sync::{LazyLock, Mutex};
+use crate::config::resource;
+use crate::recording::{record, Event};
+use crate::state::Store;
 
-static _WIFI_STRENGTH_GLOB: LazyLock<Mutex<WifiSignalBars>> = LazyLock::new(|| {Mutex::from(WifiSignalBars::NoSignal)});
-
-#[allow(non_snake_case)]
-pub fn WIFI_STRENGTH() -> WifiSignalBars {
-    *_WIFI_STRENGTH_GLOB.lock().unwrap()
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WifiSignalBars {
     NoSignal,  // 0 bars
     Weak,      // 1 bar
@@ -19,23 +14,23 @@ pub enum WifiSignalBars {
 
 impl WifiSignalBars {
     pub fn to_path(self) -> String {
-        match self {
+        resource(match self {
             WifiSignalBars::NoSignal => {
-                "/home/jasper/res/0-wifi.png"
+                "0-wifi.png"
             }
             WifiSignalBars::Weak => {
-                "/home/jasper/res/1-wifi.png"
+                "1-wifi.png"
             }
             WifiSignalBars::Fair => {
-                "/home/jasper/res/2-wifi.png"
+                "2-wifi.png"
             }
             WifiSignalBars::Good => {
-                "/home/jasper/res/3-wifi.png"
+                "3-wifi.png"
             }
             WifiSignalBars::Excellent => {
-                "/home/jasper/res/4-wifi.png"
+                "4-wifi.png"
             }
-        }.to_string()
+        })
     }
 }
 
@@ -71,6 +66,12 @@ fn get_wifi_signal_bars() -> Option<WifiSignalBars> {
     None
 }
 
-pub fn refresh_wifi_connectivity() {
-    *_WIFI_STRENGTH_GLOB.lock().unwrap() = get_wifi_signal_bars().unwrap_or(WifiSignalBars::NoSignal);
+pub fn refresh_wifi_connectivity(store: &Store) {
+    set_wifi_strength(store, get_wifi_signal_bars().unwrap_or(WifiSignalBars::NoSignal));
+}
+
+pub fn set_wifi_strength(store: &Store, strength: WifiSignalBars) {
+    if store.wifi.set_if_changed(strength) {
+        record(|| Event::Wifi(strength));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,16 @@
+use std::path::Path;
+use unyo::config::resource;
+use unyo::snapshot::{run_snapshots, DEFAULT_TOLERANCE};
+
+// Regenerate the references with `unyo --snapshots tests/snapshots --update`. The fonts aren't
+// part of the repository, `UNYO_RES` has to point to a directory with them (see res/README)
+#[test]
+fn widgets_match_references() {
+    for font in ["JetBrainsMono-Medium.ttf", "Roboto-Medium.ttf"] {
+        let font = resource(font);
+        assert!(Path::new(&font).exists(), "{font} is missing, set UNYO_RES to a directory with the fonts from res/README");
+    }
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots");
+    assert!(dir.join("info-track.png").exists(), "no references in {}, create them with `unyo --snapshots tests/snapshots --update`", dir.display());
+    assert!(run_snapshots(&dir, false, DEFAULT_TOLERANCE), "snapshots differ, see {}", dir.join("failed").display());
+}